@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS mention_events
+(
+    id         BIGSERIAL PRIMARY KEY,
+    chat_id    BIGINT    NOT NULL,
+    thread_id  BIGINT,
+    user_id    BIGINT    NOT NULL,
+    username   VARCHAR(255),
+    message_id BIGINT    NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS mention_events_chat_id_created_at_idx
+    ON mention_events (chat_id, created_at);
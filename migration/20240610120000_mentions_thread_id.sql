@@ -0,0 +1,32 @@
+-- Forum topic mentions used to be stored with the topic (thread) id in the
+-- chat_id column. Split them into separate chat_id / thread_id columns,
+-- thread_id = 0 means "not in a forum topic".
+ALTER TABLE mentions
+    ADD thread_id BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE mentions
+    DROP CONSTRAINT mentions_pkey;
+
+-- Group chat ids are negative and topic ids positive, so positive chat_ids are
+-- misplaced topic ids. Recover the owning chat from the mention event log.
+UPDATE mentions
+SET thread_id = mentions.chat_id,
+    chat_id   = topics.chat_id
+FROM (SELECT DISTINCT ON (thread_id) thread_id, chat_id
+      FROM mention_events
+      WHERE thread_id IS NOT NULL
+      ORDER BY thread_id, created_at DESC) topics
+WHERE mentions.chat_id > 0
+  AND topics.thread_id = mentions.chat_id;
+
+-- The event log only starts with the release that added it, so on an existing
+-- deployment this finds nothing. Such topics can't be attributed to a chat: keep
+-- their counters under chat_id 0 rather than dropping them. They are orphaned
+-- (no chat's /rustboard shows them) until moved by hand, see README "Upgrading".
+UPDATE mentions
+SET thread_id = mentions.chat_id,
+    chat_id   = 0
+WHERE mentions.chat_id > 0;
+
+ALTER TABLE mentions
+    ADD PRIMARY KEY (user_id, chat_id, thread_id);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS chat_settings
+(
+    chat_id                 BIGINT PRIMARY KEY,
+    mention_timer_per_topic BOOLEAN   NOT NULL DEFAULT FALSE,
+    updated_at              TIMESTAMP NOT NULL DEFAULT NOW()
+);
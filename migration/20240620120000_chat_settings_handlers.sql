@@ -0,0 +1,9 @@
+ALTER TABLE chat_settings
+    ADD rust_mention_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD blazing_fast_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD gayness_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD gpt_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD url_summary_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD mention_cooldown_minutes INT,
+    ADD mute_tiers TEXT,
+    ADD silent_counting BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,18 @@
+-- Personas the bot answers as, on top of the ones from the bot config.
+-- chat_id 0 holds personas shared by every chat (narrowed by enabled_chats),
+-- any other value a persona private to that chat. A row replaces a configured
+-- persona with the same id.
+CREATE TABLE IF NOT EXISTS personas
+(
+    chat_id       BIGINT    NOT NULL DEFAULT 0,
+    id            TEXT      NOT NULL,
+    name          TEXT      NOT NULL,
+    mention_regex TEXT      NOT NULL,
+    system_prompt TEXT      NOT NULL,
+    model         TEXT,
+    temperature   REAL,
+    languages     TEXT[]    NOT NULL DEFAULT '{}',
+    enabled_chats BIGINT[]  NOT NULL DEFAULT '{}',
+    updated_at    TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (chat_id, id)
+);
@@ -0,0 +1,4 @@
+-- LLM provider a persona is answered by, one of the providers named in the
+-- bot config. NULL means the chat's provider.
+ALTER TABLE personas
+    ADD COLUMN IF NOT EXISTS provider TEXT;
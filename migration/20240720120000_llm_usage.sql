@@ -0,0 +1,22 @@
+-- Tokens and cost of every LLM call, booked to the chat, user, persona and
+-- handler it was made for.
+CREATE TABLE IF NOT EXISTS llm_usage
+(
+    id                BIGSERIAL PRIMARY KEY,
+    chat_id           BIGINT    NOT NULL,
+    user_id           BIGINT,
+    persona_id        TEXT,
+    handler           TEXT      NOT NULL,
+    provider          TEXT      NOT NULL,
+    model             TEXT      NOT NULL,
+    prompt_tokens     INTEGER   NOT NULL,
+    completion_tokens INTEGER   NOT NULL,
+    -- Millionths of a US dollar, 0 for models without a price.
+    cost_micros       BIGINT    NOT NULL,
+    -- Counted by the bot because the provider did not report usage.
+    estimated         BOOLEAN   NOT NULL DEFAULT FALSE,
+    created_at        TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS llm_usage_created_at_chat_id_idx
+    ON llm_usage (created_at, chat_id);
@@ -0,0 +1,2 @@
+ALTER TABLE chat_settings
+    ADD voice_enabled BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,716 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use chrono::DateTime;
+use regex::Regex;
+use scraper::{ElementRef, Html, Node};
+use serde_json::Value;
+
+use crate::boot::compile_regex;
+
+/// Page chrome, scripts and forms: never part of an article.
+const SKIPPED_TAGS: &[&str] = &[
+    "aside", "button", "canvas", "footer", "form", "header", "iframe", "input", "nav", "noscript",
+    "object", "script", "select", "style", "svg", "template", "textarea",
+];
+const SKIPPED_ROLES: &[&str] = &[
+    "alert",
+    "banner",
+    "complementary",
+    "contentinfo",
+    "dialog",
+    "menu",
+    "menubar",
+    "navigation",
+    "search",
+];
+/// Tags that start a new block of text; a `div` without any of them inside
+/// is a paragraph of its own.
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "blockquote",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "main",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "tr",
+    "ul",
+];
+/// Shorter paragraphs are captions, bylines and buttons rather than text.
+const MIN_PARAGRAPH_CHARS: usize = 25;
+/// A paragraph next to the best candidate is part of the article when it is
+/// this long and mostly not links.
+const SIBLING_PARAGRAPH_CHARS: usize = 80;
+const SIBLING_LINK_DENSITY: f64 = 0.25;
+/// How deep the walks over the page go: markup nested further is left out
+/// instead of overflowing the stack.
+const MAX_DEPTH: usize = 256;
+const TITLE_SEPARATORS: &[&str] = &[" | ", " - ", " — ", " – ", " · ", " :: "];
+
+/// Class and id words of page parts that are not the article.
+const UNLIKELY_REGEX: &str = r"(?i)-ad-|\bads?\b|\bad-|advert|banner|breadcrumb|comment|consent|cookie|disqus|footer|gdpr|menu|modal|\bnav|newsletter|popup|promo|related|replies|share|sidebar|social|sponsor|subscribe|toolbar|widget";
+/// Words that keep an otherwise unlikely element, e.g. `sidebar-and-content`.
+const MAYBE_REGEX: &str = r"(?i)and|article|body|column|content|main|shadow";
+const POSITIVE_REGEX: &str =
+    r"(?i)article|body|content|entry|hentry|h-entry|main|page|post|text|blog|story";
+const NEGATIVE_REGEX: &str = r"(?i)-ad-|hidden|banner|combx|comment|com-|contact|foot|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|widget";
+
+static UNLIKELY_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(UNLIKELY_REGEX));
+static MAYBE_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(MAYBE_REGEX));
+static POSITIVE_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(POSITIVE_REGEX));
+static NEGATIVE_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(NEGATIVE_REGEX));
+
+/// What a web page says, without the page around it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Article {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// `YYYY-MM-DD` when the page dates the article with a full timestamp,
+    /// as written otherwise.
+    pub published: Option<String>,
+    pub site_name: Option<String>,
+    pub description: Option<String>,
+    /// The main text, paragraphs separated by blank lines.
+    pub body: String,
+}
+
+impl Article {
+    /// Site, author and date, those the page has, on one line.
+    pub fn byline(&self) -> Option<String> {
+        let parts: Vec<&str> = [&self.site_name, &self.author, &self.published]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(" · "))
+    }
+}
+
+/// Pull the article out of `html` the way reader modes do: paragraphs score
+/// the elements around them, class names and links add and take away, and
+/// the best scoring element with its like siblings is the body. Navigation,
+/// banners, comments and footers are left out. Title, author, date and site
+/// come from OpenGraph, JSON-LD and the usual meta tags.
+pub fn extract(html: &str) -> Article {
+    let document = Html::parse_document(html);
+    let metadata = metadata(&document);
+    let first = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| metadata.get(*key))
+            .map(String::to_owned)
+    };
+    let site_name = first(&["og:site_name", "ld:publisher", "application-name"]);
+    let title = first(&["og:title", "twitter:title", "ld:headline", "title", "h1"])
+        .map(|title| strip_site_name(title, site_name.as_deref()));
+    let author = first(&[
+        "author",
+        "article:author",
+        "ld:author",
+        "itemprop:author",
+        "rel:author",
+    ])
+    .filter(|author| !author.starts_with("http"));
+    let published = first(&[
+        "article:published_time",
+        "ld:datepublished",
+        "itemprop:datepublished",
+        "date",
+        "pubdate",
+        "publishdate",
+        "dc.date",
+        "dcterms.created",
+        "time",
+    ])
+    .map(|published| short_date(&published));
+    let description = first(&["og:description", "twitter:description", "description"]);
+
+    let mut text = TextWriter::default();
+    let content = main_content(&document)
+        .or_else(|| body(&document).map(|body| vec![body]))
+        .unwrap_or_default();
+    for element in content {
+        text.block(2);
+        text.element(element);
+    }
+    Article {
+        title,
+        author,
+        published,
+        site_name,
+        description,
+        body: text.out,
+    }
+}
+
+fn body(document: &Html) -> Option<ElementRef<'_>> {
+    document
+        .root_element()
+        .child_elements()
+        .find(|element| element.value().name() == "body")
+}
+
+/// The best scoring element and those of its siblings that look like more of
+/// the same article.
+fn main_content(document: &Html) -> Option<Vec<ElementRef<'_>>> {
+    let mut paragraphs = Vec::new();
+    collect_paragraphs(body(document)?, 0, &mut paragraphs);
+
+    let mut scores = HashMap::new();
+    for paragraph in paragraphs {
+        let text: String = paragraph.text().collect();
+        let length = text_len(paragraph);
+        if length < MIN_PARAGRAPH_CHARS {
+            continue;
+        }
+        let commas = text.matches([',', '،', '，']).count();
+        let score = 1.0 + commas as f64 + (length / 100).min(3) as f64;
+        // The parent gets the whole score, the grandparent half of it.
+        let ancestors = paragraph.ancestors().filter_map(ElementRef::wrap);
+        for (ancestor, divider) in ancestors.zip([1.0, 2.0]) {
+            scores
+                .entry(ancestor.id())
+                .or_insert_with(|| (ancestor, initial_score(ancestor)))
+                .1 += score / divider;
+        }
+    }
+    let scores: HashMap<_, _> = scores
+        .into_iter()
+        .map(|(id, (element, score))| (id, (element, score * (1.0 - link_density(element)))))
+        .collect();
+    let (top, top_score) = scores
+        .values()
+        .copied()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+    let Some(parent) = top.parent() else {
+        return Some(vec![top]);
+    };
+    let threshold = (top_score * 0.2).max(10.0);
+    let content = parent
+        .children()
+        .filter_map(ElementRef::wrap)
+        .filter(|sibling| {
+            *sibling == top
+                || scores
+                    .get(&sibling.id())
+                    .is_some_and(|(_, score)| *score >= threshold)
+                || (sibling.value().name() == "p"
+                    && text_len(*sibling) >= SIBLING_PARAGRAPH_CHARS
+                    && link_density(*sibling) < SIBLING_LINK_DENSITY)
+        })
+        .collect();
+    Some(content)
+}
+
+/// The elements that hold running text: paragraphs, preformatted blocks,
+/// table cells and `div`s used as paragraphs.
+fn collect_paragraphs<'a>(
+    element: ElementRef<'a>,
+    depth: usize,
+    paragraphs: &mut Vec<ElementRef<'a>>,
+) {
+    if depth >= MAX_DEPTH {
+        return;
+    }
+    for child in element.child_elements() {
+        if is_skipped(child) {
+            continue;
+        }
+        match child.value().name() {
+            "p" | "pre" => paragraphs.push(child),
+            "td" => {
+                paragraphs.push(child);
+                collect_paragraphs(child, depth + 1, paragraphs);
+            }
+            "div" if !child.child_elements().any(is_block) => paragraphs.push(child),
+            _ => collect_paragraphs(child, depth + 1, paragraphs),
+        }
+    }
+}
+
+fn is_block(element: ElementRef) -> bool {
+    BLOCK_TAGS.contains(&element.value().name())
+}
+
+/// Page chrome by its tag, role or class names, or hidden altogether.
+fn is_skipped(element: ElementRef) -> bool {
+    let value = element.value();
+    let name = value.name();
+    if SKIPPED_TAGS.contains(&name)
+        || value.attr("hidden").is_some()
+        || value.attr("aria-hidden") == Some("true")
+        || value
+            .attr("role")
+            .is_some_and(|role| SKIPPED_ROLES.contains(&role))
+    {
+        return true;
+    }
+    if matches!(name, "a" | "article" | "body" | "main") {
+        return false;
+    }
+    let names = class_and_id(element);
+    UNLIKELY_RE.is_match(&names) && !MAYBE_RE.is_match(&names)
+}
+
+fn class_and_id(element: ElementRef) -> String {
+    let value = element.value();
+    [value.attr("class"), value.id()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn initial_score(element: ElementRef) -> f64 {
+    let tag_score = match element.value().name() {
+        "div" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    let value = element.value();
+    let name_score: f64 = [value.attr("class"), value.id()]
+        .into_iter()
+        .flatten()
+        .map(|names| {
+            let positive = if POSITIVE_RE.is_match(names) {
+                25.0
+            } else {
+                0.0
+            };
+            let negative = if NEGATIVE_RE.is_match(names) {
+                25.0
+            } else {
+                0.0
+            };
+            positive - negative
+        })
+        .sum();
+    tag_score + name_score
+}
+
+/// The share of the element's text that is link text.
+fn link_density(element: ElementRef) -> f64 {
+    let total = text_len(element);
+    if total == 0 {
+        return 0.0;
+    }
+    let linked: usize = element
+        .descendent_elements()
+        .filter(|descendant| descendant.value().name() == "a")
+        .map(text_len)
+        .sum();
+    (linked as f64 / total as f64).min(1.0)
+}
+
+fn text_len(element: ElementRef) -> usize {
+    element
+        .text()
+        .flat_map(str::chars)
+        .filter(|c| !c.is_whitespace())
+        .count()
+}
+
+/// Plain text of elements: whitespace collapsed except in `pre`, a blank
+/// line between blocks and a dash before list items.
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    space: bool,
+    breaks: usize,
+    depth: usize,
+}
+
+impl TextWriter {
+    fn element(&mut self, element: ElementRef) {
+        if self.depth >= MAX_DEPTH {
+            return;
+        }
+        self.depth += 1;
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.words(text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.child(child);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.depth -= 1;
+    }
+
+    fn child(&mut self, element: ElementRef) {
+        if is_skipped(element) {
+            return;
+        }
+        match element.value().name() {
+            // Two in a row part paragraphs in pages without `p`.
+            "br" => self.breaks = (self.breaks + 1).min(2),
+            "hr" => self.block(2),
+            "pre" => {
+                self.block(2);
+                let code: String = element.text().collect();
+                self.separate();
+                self.out.push_str(code.trim_matches('\n'));
+                self.block(2);
+            }
+            "li" => {
+                self.block(1);
+                self.separate();
+                self.out.push_str("- ");
+                self.element(element);
+                self.block(1);
+            }
+            "td" | "th" => {
+                self.space = true;
+                self.element(element);
+                self.space = true;
+            }
+            "tr" | "dt" | "dd" => {
+                self.block(1);
+                self.element(element);
+                self.block(1);
+            }
+            name if BLOCK_TAGS.contains(&name) => {
+                self.block(2);
+                self.element(element);
+                self.block(2);
+            }
+            _ => self.element(element),
+        }
+    }
+
+    fn words(&mut self, text: &str) {
+        if text.starts_with(char::is_whitespace) {
+            self.space = true;
+        }
+        for (i, word) in text.split_whitespace().enumerate() {
+            self.space |= i > 0;
+            self.separate();
+            self.out.push_str(word);
+        }
+        if text.ends_with(char::is_whitespace) {
+            self.space = true;
+        }
+    }
+
+    /// End the line, or the paragraph with `breaks` of 2, before the next
+    /// text.
+    fn block(&mut self, breaks: usize) {
+        self.breaks = self.breaks.max(breaks);
+    }
+
+    fn separate(&mut self) {
+        if !self.out.is_empty() {
+            if self.breaks > 0 {
+                let trimmed = self.out.trim_end_matches(' ').len();
+                self.out.truncate(trimmed);
+                self.out.push_str(&"\n".repeat(self.breaks));
+            } else if self.space && !self.out.ends_with(char::is_whitespace) {
+                self.out.push(' ');
+            }
+        }
+        self.breaks = 0;
+        self.space = false;
+    }
+}
+
+/// Meta tags, microdata, JSON-LD and the first `title`, `h1` and `time` of
+/// the page, by lowercase key, the first of each kept.
+fn metadata(document: &Html) -> HashMap<String, String> {
+    let mut found = HashMap::new();
+    let mut add = |key: &str, value: &str| {
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !value.is_empty() {
+            found.entry(key.to_lowercase()).or_insert(value);
+        }
+    };
+    for element in document.root_element().descendent_elements() {
+        let value = element.value();
+        let text = || element.text().collect::<String>();
+        match value.name() {
+            "meta" => {
+                let key = value.attr("property").or(value.attr("name"));
+                if let (Some(key), Some(content)) = (key, value.attr("content")) {
+                    add(key, content);
+                }
+            }
+            "title" | "h1" => add(value.name(), &text()),
+            "time" => {
+                if let Some(datetime) = value.attr("datetime") {
+                    add("time", datetime);
+                }
+            }
+            "script" if value.attr("type") == Some("application/ld+json") => {
+                linked_data(&text(), &mut add);
+            }
+            _ => {}
+        }
+        if value.attr("rel") == Some("author") {
+            add("rel:author", &text());
+        }
+        if let Some(property) = value.attr("itemprop") {
+            let content = value.attr("content").or(value.attr("datetime"));
+            add(
+                &format!("itemprop:{property}"),
+                &content.map_or_else(text, str::to_owned),
+            );
+        }
+    }
+    found
+}
+
+/// Headline, author, date and publisher of the JSON-LD thing that has a
+/// headline, under `ld:` keys.
+fn linked_data(json: &str, add: &mut impl FnMut(&str, &str)) {
+    let Ok(mut value) = serde_json::from_str::<Value>(json) else {
+        return;
+    };
+    let things = match value.get_mut("@graph").map(Value::take) {
+        Some(Value::Array(things)) => things,
+        _ => match value {
+            Value::Array(things) => things,
+            thing => vec![thing],
+        },
+    };
+    for thing in things {
+        let Some(headline) = thing.get("headline").and_then(Value::as_str) else {
+            continue;
+        };
+        add("ld:headline", headline);
+        if let Some(published) = thing.get("datePublished").and_then(Value::as_str) {
+            add("ld:datePublished", published);
+        }
+        if let Some(author) = thing.get("author").and_then(linked_data_name) {
+            add("ld:author", &author);
+        }
+        if let Some(publisher) = thing.get("publisher").and_then(linked_data_name) {
+            add("ld:publisher", &publisher);
+        }
+    }
+}
+
+/// A JSON-LD person or organization is a name, a thing with a name, or a
+/// list of either.
+fn linked_data_name(value: &Value) -> Option<String> {
+    match value {
+        Value::String(name) => Some(name.clone()),
+        Value::Object(thing) => thing.get("name")?.as_str().map(str::to_owned),
+        Value::Array(things) => {
+            let names: Vec<String> = things.iter().filter_map(linked_data_name).collect();
+            (!names.is_empty()).then(|| names.join(", "))
+        }
+        _ => None,
+    }
+}
+
+/// `Error handling - The Crab Book` is `Error handling` on a site of that
+/// name.
+fn strip_site_name(title: String, site_name: Option<&str>) -> String {
+    let Some(site_name) = site_name else {
+        return title;
+    };
+    TITLE_SEPARATORS
+        .iter()
+        .find_map(|separator| {
+            title
+                .strip_suffix(site_name)
+                .and_then(|rest| rest.strip_suffix(separator))
+                .or_else(|| {
+                    title
+                        .strip_prefix(site_name)
+                        .and_then(|rest| rest.strip_prefix(separator))
+                })
+        })
+        .map_or_else(|| title.clone(), str::to_owned)
+}
+
+fn short_date(published: &str) -> String {
+    DateTime::parse_from_rfc3339(published)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|_| published.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract, Article};
+
+    const BLOG_POST: &str = include_str!("../tests/fixtures/articles/blog_post.html");
+    const NEWS_DIV_SOUP: &str = include_str!("../tests/fixtures/articles/news_div_soup.html");
+    const DOCS_PAGE: &str = include_str!("../tests/fixtures/articles/docs_page.html");
+
+    fn assert_left_out(article: &Article, chrome: &[&str]) {
+        for text in chrome {
+            assert!(
+                !article.body.contains(text),
+                "{text:?} in body:\n{}",
+                article.body
+            );
+        }
+    }
+
+    #[test]
+    fn blog_post_loses_banner_navigation_comments_and_footer() {
+        let article = extract(BLOG_POST);
+        assert_eq!(article.title.as_deref(), Some("Async closures are stable"));
+        assert_eq!(article.author.as_deref(), Some("Ferris Crab"));
+        assert_eq!(article.published.as_deref(), Some("2025-02-20"));
+        assert_eq!(article.site_name.as_deref(), Some("Ferris Writes"));
+        assert_eq!(
+            article.description.as_deref(),
+            Some("What async closures fix, and how to move your code to them.")
+        );
+        assert_eq!(
+            article.byline().as_deref(),
+            Some("Ferris Writes · Ferris Crab · 2025-02-20")
+        );
+
+        assert!(
+            article.body.starts_with("Rust 1.85 ships async closures"),
+            "{}",
+            article.body
+        );
+        assert!(article
+            .body
+            .contains("the new AsyncFn, AsyncFnMut and AsyncFnOnce traits"));
+        assert!(article.body.contains("\n\nWhat changes\n\n"));
+        assert!(
+            article.body.contains(
+                "async fn retry<F>(mut attempt: F) -> Result<(), Error>\nwhere\n    F: AsyncFnMut()"
+            ),
+            "code keeps its lines and indentation:\n{}",
+            article.body
+        );
+        assert!(article.body.contains("since the 2018 edition."));
+        assert!(article
+            .body
+            .ends_with("none of them should stop you from using the feature today."));
+        assert_left_out(
+            &article,
+            &[
+                "cookies",
+                "Archive",
+                "Share on",
+                "rustacean_42",
+                "forgot to mention",
+                "Popular posts",
+                "All rights reserved",
+                "dataLayer",
+            ],
+        );
+    }
+
+    #[test]
+    fn news_div_soup_is_read_by_its_divs_and_json_ld() {
+        let article = extract(NEWS_DIV_SOUP);
+        assert_eq!(
+            article.title.as_deref(),
+            Some("В Минске прошёл первый Rust-митап на 300 человек")
+        );
+        assert_eq!(article.author.as_deref(), Some("Анна Ковалёва"));
+        assert_eq!(article.published.as_deref(), Some("2024-11-03"));
+        assert_eq!(article.site_name.as_deref(), Some("Новости ИТ"));
+
+        assert!(article
+            .body
+            .contains("В субботу в Минске прошёл первый крупный митап"));
+        assert!(
+            article
+                .body
+                .contains("после того, как несколько крупных компаний"),
+            "{}",
+            article.body
+        );
+        assert!(article
+            .body
+            .contains("презентации уже доступны в чате митапа."));
+        assert!(
+            article
+                .body
+                .contains("компаний начали переписывать на нём критичные сервисы.\n\nПрограмма"),
+            "line breaks between paragraphs are kept:\n{}",
+            article.body
+        );
+        assert_left_out(
+            &article,
+            &[
+                "Главная",
+                "Реклама",
+                "Читайте также",
+                "рекордные инвестиции",
+                "Перепечатка",
+                "ads",
+            ],
+        );
+    }
+
+    #[test]
+    fn docs_page_keeps_code_and_lists_without_the_table_of_contents() {
+        let article = extract(DOCS_PAGE);
+        assert_eq!(article.title.as_deref(), Some("Error handling"));
+        assert_eq!(article.site_name.as_deref(), Some("The Crab Book"));
+        assert_eq!(article.author, None);
+        assert_eq!(article.published, None);
+
+        assert!(article
+            .body
+            .starts_with("Error handling\n\nRust has no exceptions."));
+        assert!(article
+            .body
+            .contains("fn read_config(path: &Path) -> Result<Config, Error> {\n    let text"));
+        assert!(article.body.contains(
+            "- Use Result for failures the caller can handle.\n- Use panic! for bugs and broken invariants."
+        ));
+        assert_left_out(
+            &article,
+            &["Theme", "Search", "2. Ownership", "Previous:", "Next:"],
+        );
+    }
+
+    #[test]
+    fn page_without_paragraphs_is_read_whole() {
+        let article =
+            extract("<html><body><h1>Hi</h1><span>short</span> text<br>here</body></html>");
+        assert_eq!(article.title.as_deref(), Some("Hi"));
+        assert_eq!(article.body, "Hi\n\nshort text\nhere");
+        assert_eq!(extract("").body, "");
+        assert_eq!(extract("").byline(), None);
+    }
+
+    #[test]
+    fn deeply_nested_markup_is_cut_off_instead_of_overflowing_the_stack() {
+        let paragraph = "<p>Ферриса знают все, кто хоть раз открывал книгу по Rust, и он рад.</p>";
+        let html = format!(
+            "<html><body>{paragraph}{}deep{}{paragraph}</body></html>",
+            "<div>".repeat(10_000),
+            "</div>".repeat(10_000)
+        );
+        let article = extract(&html);
+        assert!(
+            article.body.contains("Ферриса знают все"),
+            "{}",
+            article.body
+        );
+        assert!(!article.body.contains("deep"), "{}", article.body);
+    }
+}
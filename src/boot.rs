@@ -1,8 +1,8 @@
-use std::env;
+use std::collections::HashMap;
 use std::sync::{Arc, LazyLock};
 
 use chrono::Duration;
-use log::{error, warn};
+use log::{error, info, warn};
 use redis::aio::ConnectionManager;
 use regex::Regex;
 use sqlx::{PgPool, Pool, Postgres};
@@ -13,32 +13,53 @@ use teloxide::types::MediaKind::Text;
 use teloxide::types::MessageEntityKind::{TextLink, Url};
 use teloxide::types::MessageKind::Common;
 use teloxide::types::{MediaText, MessageCommon};
+use teloxide::utils::command::BotCommands;
 use teloxide::RequestError;
 
+use crate::chat_cache::ChatCache;
+use crate::chat_settings_repository::ChatSettings;
+use crate::command_handler::Command;
+use crate::config::{BotConfig, ConfigHandle};
+use crate::gpt_quota::{Charge, QuotaLimits};
+use crate::llm_provider::{ProviderKind, ProviderSettings, DEFAULT_PROVIDER};
+use crate::llm_retry::{BreakerPolicy, CircuitBreakers, RetryPolicy};
+use crate::llm_usage::{self, ModelPrice};
+use crate::persona::Persona;
+use crate::prompt_builder::PromptBuilder;
+use crate::safe_fetcher::SafeFetcher;
+use crate::speech::SpeechSettings;
+use crate::url_summary_cache::SUMMARY_CACHE_TTL_HOURS;
 use crate::{
-    bf_mention_handler, chat_gpt_handler, gayness_handler, rust_mention_handler,
-    url_summary_handler, AppError,
+    bf_mention_handler, chat_gpt_handler, chat_history, chat_repository, command_handler,
+    gayness_handler, persona, rust_mention_handler, url_summary_handler, vision, voice_handler,
+    AppError,
 };
 
-const RUST_REGEX: &str = r"(?i)(rust|раст)(.\W|.$|\W|$)";
-const BLAZING_FAST_REGEX: &str = r"\w*[BbБб][LlЛл]\w*\W[FfФф][AaАа]\w*\b";
-const GAYNESS_REGEX: &str = r"(\D[0-4]|\D)\d%\Dg";
-const CHAT_GPT_REGEX: &str = r"(?i)(fedor|ф[её]дор|федя|felix|феликс|feris|ferris|ферис|феррис)";
-const URL_REGEX: &str = r#"https?://[^\s<>"{}|\\^`\[\]]*"#;
-const MIN_TIME_DIFF: i64 = 15;
-
-/// Chat where rust mentions are counted but not announced. Overridable via the
-/// `RUST_CHAT_ID` env var; the default preserves the historically hardcoded id.
-const DEFAULT_RUST_CHAT_ID: i64 = -1001228598755;
+// Built-in defaults; every one of them can be overridden from the bot config
+// file (see `config.rs`).
+pub(crate) const RUST_REGEX: &str = r"(?i)(rust|раст)(.\W|.$|\W|$)";
+pub(crate) const BLAZING_FAST_REGEX: &str = r"\w*[BbБб][LlЛл]\w*\W[FfФф][AaАа]\w*\b";
+pub(crate) const GAYNESS_REGEX: &str = r"(\D[0-4]|\D)\d%\Dg";
+pub(crate) const URL_REGEX: &str = r#"https?://[^\s<>"{}|\\^`\[\]]*"#;
+pub(crate) const MIN_TIME_DIFF: i64 = 15;
+pub(crate) const HISTORY_MAX_ENTRIES: usize = 1000;
+pub(crate) const HISTORY_TTL_HOURS: i64 = 48;
+pub(crate) const DEFAULT_SUMMARY_WINDOW_HOURS: i64 = 2;
+pub(crate) const CONTEXT_MAX_MESSAGES: usize = 12;
+pub(crate) const CONTEXT_TTL_HOURS: i64 = 24;
+pub(crate) const GPT_REQUEST_TIMEOUT_SECS: u64 = 90;
+/// Telegram allows about one edit per second in a chat; stay well below.
+pub(crate) const STREAM_EDIT_INTERVAL_MS: u64 = 1500;
 
 pub const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1/chat/completions";
+pub const DEFAULT_GPT_MODEL: &str = "gpt-4o";
+pub const DEFAULT_MAX_COMPLETION_TOKENS: usize = 1000;
 
 // Compile each mention regex exactly once. The patterns are compile-time
 // constants, so a failure can only be a developer typo, never a runtime error.
 static RUST_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(RUST_REGEX));
 static BLAZING_FAST_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(BLAZING_FAST_REGEX));
 static GAYNESS_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(GAYNESS_REGEX));
-static CHAT_GPT_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(CHAT_GPT_REGEX));
 static URL_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(URL_REGEX));
 
 /// Compile a compile-time-constant regex pattern. The `expect` is the one
@@ -53,20 +74,140 @@ pub(crate) fn compile_regex(pattern: &str) -> Regex {
 #[derive(Clone)]
 pub struct GptParameters {
     pub chat_gpt_api_token: Arc<str>,
-    pub openai_base_url: Arc<str>,
     pub http_client: reqwest::Client,
+    /// Fetches the pages posted in chats, refusing private addresses.
+    pub url_fetcher: SafeFetcher,
     pub redis_connection_manager: ConnectionManager,
+    /// Where the token usage of every call is booked.
+    pub db_pool: PgPool,
+    pub settings: Arc<GptSettings>,
+    /// Outlives config reloads, so a failing provider stays tripped.
+    pub circuit_breakers: Arc<CircuitBreakers>,
+    /// Chat settings and personas, shared by every message of the bot.
+    pub chat_cache: Arc<ChatCache>,
 }
 
-#[derive(Clone)]
+impl GptParameters {
+    pub fn prompt_builder(&self) -> PromptBuilder {
+        self.prompt_builder_for(&self.settings.model)
+    }
+
+    /// Prompt builder for a model other than the configured one, e.g. a
+    /// persona's own.
+    pub fn prompt_builder_for(&self, model: &str) -> PromptBuilder {
+        PromptBuilder::for_model(model, self.settings.max_completion_tokens)
+    }
+
+    /// The provider configured under `name`. Without a `[providers.openai]`
+    /// entry, `openai` is the OpenAI API at `gpt.base_url` authenticated with
+    /// `CHAT_GPT_API_TOKEN`.
+    pub fn provider_settings(&self, name: &str) -> Option<ProviderSettings> {
+        match self.settings.providers.get(name) {
+            Some(provider) => Some(provider.clone()),
+            None if name == DEFAULT_PROVIDER => Some(ProviderSettings {
+                kind: ProviderKind::OpenAi,
+                base_url: Arc::clone(&self.settings.openai_base_url),
+                api_key: Some(Arc::clone(&self.chat_gpt_api_token)),
+                model: None,
+            }),
+            None => None,
+        }
+    }
+}
+
+/// The configurable part of the GPT integration: providers, model, limits and
+/// the personas the bot answers as.
+#[derive(Clone, Debug)]
+pub struct GptSettings {
+    pub openai_base_url: Arc<str>,
+    pub model: Arc<str>,
+    pub max_completion_tokens: usize,
+    pub request_timeout: std::time::Duration,
+    pub context_limits: ContextLimits,
+    pub personas: Vec<Persona>,
+    /// Provider used unless the persona or the chat names another one.
+    pub default_provider: Arc<str>,
+    pub providers: HashMap<String, ProviderSettings>,
+    /// Per-chat provider overrides, by chat id.
+    pub chat_providers: HashMap<i64, String>,
+    pub retry: RetryPolicy,
+    pub breaker: BreakerPolicy,
+    /// Show persona answers as they are generated by editing the reply.
+    pub streaming: bool,
+    /// Minimum time between two edits of a streamed reply.
+    pub stream_edit_interval: std::time::Duration,
+    pub quota: QuotaLimits,
+    /// USD per million tokens, by model name.
+    pub prices: HashMap<String, ModelPrice>,
+    /// Prefixes of the model names that are sent pictures.
+    pub vision_models: Vec<String>,
+    /// Transcription of voice notes.
+    pub speech: SpeechSettings,
+    /// How long URL summaries are reused; zero means they are not.
+    pub summary_cache_ttl: std::time::Duration,
+}
+
+impl GptSettings {
+    pub fn provider_name_for(&self, chat_id: i64) -> &str {
+        self.chat_providers
+            .get(&chat_id)
+            .map_or(&self.default_provider, String::as_str)
+    }
+
+    pub fn sees_images(&self, model: &str) -> bool {
+        vision::sees_images(&self.vision_models, model)
+    }
+}
+
+impl Default for GptSettings {
+    fn default() -> Self {
+        Self {
+            openai_base_url: Arc::from(DEFAULT_OPENAI_BASE_URL),
+            model: Arc::from(DEFAULT_GPT_MODEL),
+            max_completion_tokens: DEFAULT_MAX_COMPLETION_TOKENS,
+            request_timeout: std::time::Duration::from_secs(GPT_REQUEST_TIMEOUT_SECS),
+            context_limits: ContextLimits::default(),
+            personas: persona::default_personas(),
+            default_provider: Arc::from(DEFAULT_PROVIDER),
+            providers: HashMap::new(),
+            chat_providers: HashMap::new(),
+            retry: RetryPolicy::default(),
+            breaker: BreakerPolicy::default(),
+            streaming: true,
+            stream_edit_interval: std::time::Duration::from_millis(STREAM_EDIT_INTERVAL_MS),
+            quota: QuotaLimits::default(),
+            prices: llm_usage::default_prices(),
+            vision_models: vision::default_vision_models(),
+            speech: SpeechSettings::default(),
+            summary_cache_ttl: std::time::Duration::from_secs(SUMMARY_CACHE_TTL_HOURS * 60 * 60),
+        }
+    }
+}
+
+/// Size and idle expiry of the per-persona conversation context kept in Redis.
+#[derive(Clone, Debug)]
+pub struct ContextLimits {
+    pub max_messages: usize,
+    pub ttl: Duration,
+}
+
+impl Default for ContextLimits {
+    fn default() -> Self {
+        Self {
+            max_messages: CONTEXT_MAX_MESSAGES,
+            ttl: Duration::hours(CONTEXT_TTL_HOURS),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct MentionParameters {
     pub rust_regex: Regex,
     pub blazing_fast_regex: Regex,
     pub gayness_regex: Regex,
-    pub chat_gpt_regex: Regex,
     pub url_regex: Regex,
     pub req_time_diff: Duration,
-    pub rust_chat_id: i64,
+    pub stickers: Arc<[String]>,
 }
 
 impl Default for MentionParameters {
@@ -75,21 +216,32 @@ impl Default for MentionParameters {
             rust_regex: RUST_RE.clone(),
             blazing_fast_regex: BLAZING_FAST_RE.clone(),
             gayness_regex: GAYNESS_RE.clone(),
-            chat_gpt_regex: CHAT_GPT_RE.clone(),
             url_regex: URL_RE.clone(),
             req_time_diff: Duration::minutes(MIN_TIME_DIFF),
-            rust_chat_id: rust_chat_id_from_env(),
+            stickers: rust_mention_handler::DEFAULT_STICKERS
+                .iter()
+                .map(|&sticker| sticker.to_owned())
+                .collect(),
         }
     }
 }
 
-fn rust_chat_id_from_env() -> i64 {
-    match env::var("RUST_CHAT_ID") {
-        Ok(raw) => raw.parse().unwrap_or_else(|_| {
-            warn!("RUST_CHAT_ID='{raw}' is not a valid i64; using default {DEFAULT_RUST_CHAT_ID}");
-            DEFAULT_RUST_CHAT_ID
-        }),
-        Err(_) => DEFAULT_RUST_CHAT_ID,
+/// Retention of the recorded group chat history the "что происходит" summaries
+/// are built from.
+#[derive(Clone, Debug)]
+pub struct HistoryParameters {
+    pub max_entries: usize,
+    pub ttl: Duration,
+    pub default_summary_window: Duration,
+}
+
+impl Default for HistoryParameters {
+    fn default() -> Self {
+        Self {
+            max_entries: HISTORY_MAX_ENTRIES,
+            ttl: Duration::hours(HISTORY_TTL_HOURS),
+            default_summary_window: Duration::hours(DEFAULT_SUMMARY_WINDOW_HOURS),
+        }
     }
 }
 
@@ -97,87 +249,263 @@ pub struct AppDeps {
     pub bot: Bot,
     pub db_pool: PgPool,
     pub gpt_parameters: GptParameters,
-    pub mention_parameters: MentionParameters,
+    pub config: ConfigHandle,
 }
 
 pub fn build_handler() -> UpdateHandler<RequestError> {
-    Update::filter_message().branch(
-        dptree::filter(|msg: Message| !msg.chat.is_private()).endpoint(
-            |msg: Message,
-             mention_parameters: MentionParameters,
-             db_pool: Pool<Postgres>,
-             gpt_parameters: GptParameters,
-             bot: Bot| async move {
-                // Every handler returns `Result<(), AppError>`; errors are
-                // logged once here at the dispatcher boundary and swallowed so
-                // a single bad update never tears down the dispatcher.
-                let outcome: Result<(), AppError> = if let Common(MessageCommon {
-                    media_kind: Text(media_text),
-                    ..
-                }) = &msg.kind
-                {
-                    match &media_text.text {
-                        text if mention_parameters.chat_gpt_regex.is_match(text) => {
-                            chat_gpt_handler::handle_chat_gpt_question(bot, msg, &gpt_parameters)
-                                .await
-                        }
-                        text if message_has_url(
-                            &mention_parameters.url_regex,
-                            text,
-                            media_text,
-                        ) =>
-                        {
-                            url_summary_handler::handle_url_summary(
-                                bot,
-                                msg,
-                                mention_parameters.url_regex.clone(),
-                                &gpt_parameters,
-                            )
-                            .await
-                        }
-                        text if mention_parameters.rust_regex.is_match(text) => {
-                            rust_mention_handler::handle_rust_matched_mention(
-                                bot,
-                                msg,
-                                db_pool,
-                                mention_parameters.req_time_diff,
-                                mention_parameters.rust_chat_id,
-                            )
-                            .await
-                        }
-                        text if mention_parameters.blazing_fast_regex.is_match(text) => {
-                            bf_mention_handler::handle_bf_matched_mention(bot, msg).await;
-                            Ok(())
-                        }
-                        text if mention_parameters.gayness_regex.is_match(text) => {
-                            gayness_handler::handle_gayness_mention(bot, msg).await;
-                            Ok(())
-                        }
-                        _ => {
-                            if let Some(reply_msg) = &msg.reply_to_message() {
-                                chat_gpt_handler::handle_reply(
-                                    &bot,
-                                    &msg,
-                                    reply_msg,
-                                    &gpt_parameters,
+    // Each update takes one snapshot of the reloadable config and gets its
+    // parameters from it, overriding whatever `GptParameters` settings were
+    // registered at startup.
+    Update::filter_message()
+        .map(|config: ConfigHandle| config.current())
+        .map(|config: Arc<BotConfig>| config.mention_parameters.clone())
+        .map(|config: Arc<BotConfig>| config.history_parameters.clone())
+        .map(
+            |config: Arc<BotConfig>, gpt_parameters: GptParameters| GptParameters {
+                settings: Arc::clone(&config.gpt_settings),
+                ..gpt_parameters
+            },
+        )
+        .branch(
+            dptree::filter(|msg: Message| msg.chat.is_private()).branch(private_command_branch()),
+        )
+        .branch(
+            dptree::filter(|msg: Message| !msg.chat.is_private())
+                .branch(command_branch())
+                .branch(dptree::endpoint(
+                    |msg: Message,
+                     mention_parameters: MentionParameters,
+                     db_pool: Pool<Postgres>,
+                     gpt_parameters: GptParameters,
+                     history_parameters: HistoryParameters,
+                     bot: Bot| async move {
+                        chat_history::record_message(
+                            &mut gpt_parameters.redis_connection_manager.clone(),
+                            &history_parameters,
+                            &msg,
+                        )
+                        .await;
+                        let chat_cache = &gpt_parameters.chat_cache;
+                        let settings = chat_cache.settings(&db_pool, msg.chat.id.0).await;
+                        let personas = if settings.gpt_enabled {
+                            chat_cache
+                                .personas(
+                                    &db_pool,
+                                    &gpt_parameters.settings.personas,
+                                    msg.chat.id.0,
                                 )
                                 .await
-                            } else {
-                                Ok(())
-                            }
+                        } else {
+                            Vec::new()
+                        };
+
+                        // Every handler returns `Result<(), AppError>`; errors are
+                        // logged once here at the dispatcher boundary and swallowed so
+                        // a single bad update never tears down the dispatcher.
+                        let route = MessageRoute {
+                            mention_parameters: &mention_parameters,
+                            db_pool: &db_pool,
+                            gpt_parameters: &gpt_parameters,
+                            history_parameters: &history_parameters,
+                            settings: &settings,
+                            personas: &personas,
+                            charge: Charge::Due,
+                        };
+                        let outcome = if msg.text().is_some() {
+                            route.text(bot, msg).await
+                        } else if settings.voice_enabled && voice_handler::has_recording(&msg) {
+                            route.voice(bot, msg).await
+                        } else if settings.gpt_enabled && vision::message_image(&msg).is_some() {
+                            route.image(bot, msg).await
+                        } else {
+                            Ok(())
+                        };
+
+                        if let Err(err) = outcome {
+                            error!("message handler failed: {err}");
                         }
-                    }
+
+                        respond(())
+                    },
+                )),
+        )
+}
+
+/// What the free-text handlers of one message need.
+struct MessageRoute<'a> {
+    mention_parameters: &'a MentionParameters,
+    db_pool: &'a PgPool,
+    gpt_parameters: &'a GptParameters,
+    history_parameters: &'a HistoryParameters,
+    settings: &'a ChatSettings,
+    personas: &'a [Persona],
+    charge: Charge,
+}
+
+impl MessageRoute<'_> {
+    /// Hand a text message to the first handler whose pattern it matches.
+    async fn text(&self, bot: Bot, msg: Message) -> Result<(), AppError> {
+        let Common(MessageCommon {
+            media_kind: Text(media_text),
+            ..
+        }) = &msg.kind
+        else {
+            return Ok(());
+        };
+        let settings = self.settings;
+        let mention_parameters = self.mention_parameters;
+        match &media_text.text {
+            text if persona::any_mentioned(self.personas, text) => {
+                chat_gpt_handler::handle_chat_gpt_question(
+                    bot,
+                    msg,
+                    self.gpt_parameters,
+                    self.history_parameters,
+                    self.personas,
+                    self.charge,
+                )
+                .await
+            }
+            text if settings.url_summary_enabled
+                && message_has_url(&mention_parameters.url_regex, text, media_text) =>
+            {
+                url_summary_handler::handle_url_summary(
+                    bot,
+                    msg,
+                    mention_parameters.url_regex.clone(),
+                    self.gpt_parameters,
+                    self.charge,
+                )
+                .await
+            }
+            text if settings.rust_mention_enabled
+                && mention_parameters.rust_regex.is_match(text) =>
+            {
+                rust_mention_handler::handle_rust_matched_mention(
+                    bot,
+                    msg,
+                    self.db_pool.clone(),
+                    mention_parameters,
+                    settings,
+                )
+                .await
+            }
+            text if settings.blazing_fast_enabled
+                && mention_parameters.blazing_fast_regex.is_match(text) =>
+            {
+                bf_mention_handler::handle_bf_matched_mention(bot, msg).await;
+                Ok(())
+            }
+            text if settings.gayness_enabled && mention_parameters.gayness_regex.is_match(text) => {
+                gayness_handler::handle_gayness_mention(bot, msg, &settings.mute_tiers).await;
+                Ok(())
+            }
+            _ => {
+                if let Some(reply_msg) = msg.reply_to_message().filter(|_| settings.gpt_enabled) {
+                    chat_gpt_handler::handle_reply(
+                        &bot,
+                        &msg,
+                        reply_msg,
+                        self.gpt_parameters,
+                        self.personas,
+                        self.charge,
+                    )
+                    .await
                 } else {
                     Ok(())
-                };
-
-                if let Err(err) = outcome {
-                    error!("message handler failed: {err}");
                 }
+            }
+        }
+    }
 
-                respond(())
-            },
-        ),
+    /// A picture is a question when its caption mentions a persona or it
+    /// replies to one.
+    async fn image(&self, bot: Bot, msg: Message) -> Result<(), AppError> {
+        let caption = msg.caption().unwrap_or_default();
+        if persona::any_mentioned(self.personas, caption) {
+            chat_gpt_handler::handle_chat_gpt_question(
+                bot,
+                msg,
+                self.gpt_parameters,
+                self.history_parameters,
+                self.personas,
+                Charge::Due,
+            )
+            .await
+        } else if let Some(reply_msg) = msg.reply_to_message() {
+            chat_gpt_handler::handle_reply(
+                &bot,
+                &msg,
+                reply_msg,
+                self.gpt_parameters,
+                self.personas,
+                Charge::Due,
+            )
+            .await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// A recording is transcribed and its transcript handled like typed text.
+    async fn voice(&self, bot: Bot, msg: Message) -> Result<(), AppError> {
+        let Some(text_msg) = voice_handler::handle_voice(&bot, &msg, self.gpt_parameters).await?
+        else {
+            return Ok(());
+        };
+        chat_history::record_message(
+            &mut self.gpt_parameters.redis_connection_manager.clone(),
+            self.history_parameters,
+            &text_msg,
+        )
+        .await;
+        let route = MessageRoute {
+            charge: Charge::Paid,
+            ..*self
+        };
+        route.text(bot, text_msg).await
+    }
+}
+
+/// The bot ignores private chats, except for the owner commands of bot owners.
+fn private_command_branch() -> UpdateHandler<RequestError> {
+    dptree::entry()
+        .filter_command::<Command>()
+        .filter(|msg: Message, command: Command, config: ConfigHandle| {
+            command.is_private_owner_command() && command_handler::is_bot_owner(&msg, &config)
+        })
+        .chain(command_endpoint())
+}
+
+/// Slash commands, matched ahead of the free-text regex routes.
+fn command_branch() -> UpdateHandler<RequestError> {
+    dptree::entry()
+        .filter_command::<Command>()
+        .chain(command_endpoint())
+}
+
+fn command_endpoint() -> UpdateHandler<RequestError> {
+    dptree::endpoint(
+        |msg: Message,
+         command: Command,
+         db_pool: Pool<Postgres>,
+         gpt_parameters: GptParameters,
+         config: ConfigHandle,
+         bot: Bot| async move {
+            if let Err(err) = command_handler::handle_command(
+                bot,
+                msg,
+                command,
+                db_pool,
+                &gpt_parameters,
+                &config,
+            )
+            .await
+            {
+                error!("command handler failed: {err}");
+            }
+            respond(())
+        },
     )
 }
 
@@ -186,11 +514,26 @@ pub async fn run(deps: AppDeps) -> anyhow::Result<()> {
         bot,
         db_pool,
         gpt_parameters,
-        mention_parameters,
+        config,
     } = deps;
+    bot.set_my_commands(Command::bot_commands())
+        .await
+        .inspect_err(|err| warn!("Can't register bot commands: {err:?}"))
+        .ok();
+    match chat_repository::migrate_legacy_context_keys(
+        &mut gpt_parameters.redis_connection_manager.clone(),
+    )
+    .await
+    {
+        Ok(0) => {}
+        Ok(moved) => info!("moved {moved} persona contexts to persona id keys"),
+        Err(err) => warn!("Can't migrate legacy persona contexts: {err:?}"),
+    }
+    #[cfg(unix)]
+    spawn_reload_on_sighup(config.clone())?;
     let handler = build_handler();
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![mention_parameters, db_pool, gpt_parameters])
+        .dependencies(dptree::deps![db_pool, gpt_parameters, config])
         .error_handler(LoggingErrorHandler::with_custom_text(
             "An error has occurred in the dispatcher",
         ))
@@ -201,6 +544,24 @@ pub async fn run(deps: AppDeps) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Reload the config whenever the process receives SIGHUP. A config that fails
+/// validation is logged and the running one is kept.
+#[cfg(unix)]
+fn spawn_reload_on_sighup(config: ConfigHandle) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            info!("SIGHUP received, reloading bot config");
+            if let Err(err) = config.reload() {
+                error!("bot config reload failed, keeping the current one: {err}");
+            }
+        }
+    });
+    Ok(())
+}
+
 pub fn message_has_url(regex: &Regex, message_text: &str, text: &MediaText) -> bool {
     let has_url_match = regex.is_match(message_text)
         || text
@@ -218,7 +579,7 @@ pub fn message_has_url(regex: &Regex, message_text: &str, text: &MediaText) -> b
 
 #[cfg(test)]
 mod tests {
-    use super::{message_has_url, CHAT_GPT_REGEX, RUST_REGEX, URL_REGEX};
+    use super::{message_has_url, RUST_REGEX, URL_REGEX};
     use regex::Regex;
     use teloxide::types::MediaText;
 
@@ -231,17 +592,6 @@ mod tests {
         assert!(chat_gpt_regex.is_match("чэ тупо раст тэст"));
     }
 
-    #[test]
-    fn test_chat_gpt_regex() {
-        let chat_gpt_regex = Regex::new(CHAT_GPT_REGEX).expect("Can't compile regex");
-        assert!(chat_gpt_regex.is_match("ухх Федор как дела?"));
-        assert!(chat_gpt_regex.is_match("pFedor tests"));
-        assert!(chat_gpt_regex.is_match("p Felix greate"));
-        assert!(chat_gpt_regex.is_match("Феликс"));
-        assert!(chat_gpt_regex.is_match("[[[Ferris"));
-        assert!(chat_gpt_regex.is_match("[ Фёдор ъ"));
-    }
-
     #[test]
     fn test_url_regex() {
         let url_regex = Regex::new(URL_REGEX).expect("Can't compile regex");
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use sqlx::PgPool;
+
+use crate::chat_settings_repository::{self, ChatSettings};
+use crate::persona::{self, Persona};
+use crate::persona_repository;
+
+/// How long a chat's settings and personas are reused before they are read
+/// again; bounds how long a change made through another instance goes unseen.
+pub(crate) const CHAT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Chat settings and the chats' own personas, regexes compiled, kept between
+/// messages so that a group message doesn't cost Postgres round trips. Commands
+/// that change either call [`ChatCache::invalidate`].
+#[derive(Debug)]
+pub struct ChatCache {
+    settings: Entries<ChatSettings>,
+    /// The personas stored for the chat, before they are merged with the
+    /// configured ones, so a config reload takes effect at once.
+    stored_personas: Entries<Vec<Persona>>,
+}
+
+impl ChatCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            settings: Entries::new(ttl),
+            stored_personas: Entries::new(ttl),
+        }
+    }
+
+    /// The chat's settings; the defaults when they can't be read.
+    pub async fn settings(&self, db_pool: &PgPool, chat_id: i64) -> ChatSettings {
+        if let Some(settings) = self.settings.get(chat_id, Instant::now()) {
+            return settings;
+        }
+        match chat_settings_repository::get_chat_settings(db_pool, chat_id).await {
+            Ok(settings) => {
+                self.settings
+                    .insert(chat_id, settings.clone(), Instant::now());
+                settings
+            }
+            Err(err) => {
+                warn!("Can't fetch chat settings: {err:?}");
+                ChatSettings::default()
+            }
+        }
+    }
+
+    /// The personas available in the chat, like [`persona::chat_personas`].
+    pub async fn personas(
+        &self,
+        db_pool: &PgPool,
+        configured: &[Persona],
+        chat_id: i64,
+    ) -> Vec<Persona> {
+        let stored = match self.stored_personas.get(chat_id, Instant::now()) {
+            Some(stored) => stored,
+            None => match persona_repository::get_personas(db_pool, chat_id).await {
+                Ok(stored) => {
+                    self.stored_personas
+                        .insert(chat_id, stored.clone(), Instant::now());
+                    stored
+                }
+                Err(err) => {
+                    warn!("Can't fetch personas for chat {chat_id}: {err:?}");
+                    Vec::new()
+                }
+            },
+        };
+        persona::merge_personas(configured, stored, chat_id)
+    }
+
+    /// Forget what is cached for the chat, after its settings or personas
+    /// changed.
+    pub fn invalidate(&self, chat_id: i64) {
+        self.settings.remove(chat_id);
+        self.stored_personas.remove(chat_id);
+    }
+}
+
+impl Default for ChatCache {
+    fn default() -> Self {
+        Self::new(CHAT_CACHE_TTL)
+    }
+}
+
+/// Values by chat id that expire `ttl` after they were stored.
+#[derive(Debug)]
+struct Entries<T> {
+    ttl: Duration,
+    values: Mutex<HashMap<i64, (Instant, T)>>,
+}
+
+impl<T: Clone> Entries<T> {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            values: Mutex::default(),
+        }
+    }
+
+    fn get(&self, chat_id: i64, now: Instant) -> Option<T> {
+        let values = self.values.lock().unwrap_or_else(PoisonError::into_inner);
+        values
+            .get(&chat_id)
+            .filter(|(stored_at, _)| now.duration_since(*stored_at) < self.ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Store a value, dropping the expired ones so chats the bot no longer
+    /// hears from don't pile up.
+    fn insert(&self, chat_id: i64, value: T, now: Instant) {
+        let mut values = self.values.lock().unwrap_or_else(PoisonError::into_inner);
+        values.retain(|_, (stored_at, _)| now.duration_since(*stored_at) < self.ttl);
+        values.insert(chat_id, (now, value));
+    }
+
+    fn remove(&self, chat_id: i64) {
+        let mut values = self.values.lock().unwrap_or_else(PoisonError::into_inner);
+        values.remove(&chat_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Entries;
+
+    #[test]
+    fn entries_expire_after_their_ttl() {
+        let entries = Entries::new(Duration::from_secs(60));
+        let now = Instant::now();
+        entries.insert(-1, "settings", now);
+
+        assert_eq!(
+            entries.get(-1, now + Duration::from_secs(59)),
+            Some("settings")
+        );
+        assert_eq!(entries.get(-1, now + Duration::from_secs(60)), None);
+        assert_eq!(entries.get(-2, now), None);
+    }
+
+    #[test]
+    fn inserting_drops_expired_entries_and_remove_forgets_one() {
+        let entries = Entries::new(Duration::from_secs(60));
+        let now = Instant::now();
+        entries.insert(-1, "old", now);
+        entries.insert(-2, "new", now + Duration::from_secs(61));
+        assert_eq!(entries.values.lock().unwrap().len(), 1);
+
+        entries.remove(-2);
+        assert_eq!(entries.get(-2, now + Duration::from_secs(61)), None);
+    }
+}
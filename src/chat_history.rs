@@ -0,0 +1,286 @@
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use redis::aio::ConnectionManager;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use teloxide::types::Message;
+
+use crate::boot::compile_regex;
+use crate::{chat_repository, HistoryParameters};
+
+const LAST_PERIOD_REGEX: &str =
+    r"(?i)(?:за\s+последн\S*|last|past)\s+(\d+)?\s*(мин|час|сут|д[ен]|minute|min|hour|day)";
+const SINCE_LAST_SEEN_REGEX: &str = r"(?i)(с\s+тех\s+пор,?\s+как\s+я|пока\s+меня\s+не\s+было|since\s+i\s+was(\s+last)?\s+here|since\s+my\s+last)";
+
+static LAST_PERIOD_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(LAST_PERIOD_REGEX));
+static SINCE_LAST_SEEN_RE: LazyLock<Regex> = LazyLock::new(|| compile_regex(SINCE_LAST_SEEN_REGEX));
+
+/// A single recorded group chat message, as stored in the per-chat (and
+/// per-topic) history sorted sets.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub message_id: i32,
+    pub user_id: Option<u64>,
+    pub author: String,
+    pub timestamp: i64,
+    pub thread_id: Option<i32>,
+    pub reply_to: Option<i32>,
+    pub text: String,
+}
+
+impl HistoryEntry {
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        let text = msg.text()?;
+        let author = msg
+            .from
+            .as_ref()
+            .map(|user| user.username.clone().unwrap_or_else(|| user.full_name()))
+            .unwrap_or_else(|| "unknown".to_owned());
+        Some(Self {
+            message_id: msg.id.0,
+            user_id: msg.from.as_ref().map(|user| user.id.0),
+            author,
+            timestamp: msg.date.timestamp(),
+            thread_id: topic_thread_id(msg),
+            reply_to: msg.reply_to_message().map(|reply| reply.id.0),
+            text: text.to_owned(),
+        })
+    }
+}
+
+/// Time window a "what's going on" summary is built over.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SummaryWindow {
+    /// Everything posted within the given period before the request.
+    Last(Duration),
+    /// Everything posted since the requesting user's previous message.
+    SinceLastSeen,
+}
+
+impl SummaryWindow {
+    /// Parse the window out of the summary request ("за последние 3 часа",
+    /// "last 30 minutes", "пока меня не было"), falling back to `default`.
+    pub fn parse(text: &str, default: Duration) -> Self {
+        if SINCE_LAST_SEEN_RE.is_match(text) {
+            return Self::SinceLastSeen;
+        }
+        let Some(captures) = LAST_PERIOD_RE.captures(text) else {
+            return Self::Last(default);
+        };
+        let amount = captures
+            .get(1)
+            .and_then(|m| m.as_str().parse::<i64>().ok())
+            .unwrap_or(1);
+        let unit = captures[2].to_lowercase();
+        let window = if unit.starts_with("мин") || unit.starts_with("min") {
+            Duration::try_minutes(amount)
+        } else if unit.starts_with("час") || unit.starts_with("hour") {
+            Duration::try_hours(amount)
+        } else {
+            Duration::try_days(amount)
+        };
+        Self::Last(window.unwrap_or(default))
+    }
+}
+
+/// Redis key of the history a message belongs to: the forum topic when the
+/// message was posted in one, otherwise the whole chat.
+pub fn history_key(chat_id: i64, thread_id: Option<i32>) -> String {
+    match thread_id {
+        Some(thread_id) => format!("history:chat:{chat_id}:thread:{thread_id}"),
+        None => format!("history:chat:{chat_id}"),
+    }
+}
+
+/// Only forum topics get their own history; `message_thread_id` on a plain
+/// reply chain is not a separate conversation.
+pub fn topic_thread_id(msg: &Message) -> Option<i32> {
+    msg.thread_id
+        .filter(|_| msg.is_topic_message)
+        .map(|thread_id| thread_id.0 .0)
+}
+
+/// Record a text message into the chat history and, for forum topics, into the
+/// topic history as well. Failures are logged and otherwise ignored so that
+/// recording never blocks the message routing.
+pub async fn record_message(
+    redis_connection_manager: &mut ConnectionManager,
+    history_parameters: &HistoryParameters,
+    msg: &Message,
+) {
+    let Some(entry) = HistoryEntry::from_message(msg) else {
+        return;
+    };
+    let chat_id = msg.chat.id.0;
+    let mut keys = vec![history_key(chat_id, None)];
+    if let Some(thread_id) = entry.thread_id {
+        keys.push(history_key(chat_id, Some(thread_id)));
+    }
+    for key in keys {
+        chat_repository::push_history_entry(
+            redis_connection_manager,
+            &key,
+            &entry,
+            history_parameters.max_entries,
+            history_parameters.ttl,
+        )
+        .await
+        .inspect_err(|err| warn!("Can't record chat history for {key}: {err:?}"))
+        .ok();
+    }
+}
+
+/// Fetch the history entries that fall into `window`, excluding the request
+/// message itself.
+pub async fn fetch_window(
+    redis_connection_manager: &mut ConnectionManager,
+    msg: &Message,
+    window: SummaryWindow,
+) -> redis::RedisResult<Vec<HistoryEntry>> {
+    let key = history_key(msg.chat.id.0, topic_thread_id(msg));
+    let now = msg.date;
+    let since = match window {
+        SummaryWindow::Last(period) => now
+            .checked_sub_signed(period)
+            .unwrap_or(DateTime::<Utc>::UNIX_EPOCH),
+        SummaryWindow::SinceLastSeen => DateTime::<Utc>::UNIX_EPOCH,
+    };
+    info!("fetching chat history {key} since {since}");
+    let entries =
+        chat_repository::get_history_since(redis_connection_manager, &key, since.timestamp())
+            .await?;
+    let entries = entries
+        .into_iter()
+        .filter(|entry| entry.message_id != msg.id.0);
+    Ok(match window {
+        SummaryWindow::Last(_) => entries.collect(),
+        SummaryWindow::SinceLastSeen => {
+            since_last_message(entries.collect(), msg.from.as_ref().map(|user| user.id.0))
+        }
+    })
+}
+
+/// Drop everything up to and including the user's latest earlier message. When
+/// the user has no message in the retained history, everything is kept.
+fn since_last_message(entries: Vec<HistoryEntry>, user_id: Option<u64>) -> Vec<HistoryEntry> {
+    let last_seen = entries
+        .iter()
+        .rposition(|entry| user_id.is_some() && entry.user_id == user_id);
+    match last_seen {
+        Some(position) => entries.into_iter().skip(position + 1).collect(),
+        None => entries,
+    }
+}
+
+/// Render history entries as a plain-text transcript for the summary prompt.
+pub fn format_transcript(entries: &[HistoryEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| {
+            let time = DateTime::from_timestamp(entry.timestamp, 0)
+                .map(|time| time.format("%H:%M").to_string())
+                .unwrap_or_default();
+            let addressee = entry
+                .reply_to
+                .and_then(|reply_to| entries.iter().find(|e| e.message_id == reply_to))
+                .map(|target| format!(" → {}", target.author))
+                .unwrap_or_default();
+            format!("[{time}] {}{addressee}: {}", entry.author, entry.text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_transcript, history_key, since_last_message, HistoryEntry, SummaryWindow};
+    use chrono::Duration;
+
+    fn entry(message_id: i32, user_id: u64, author: &str, reply_to: Option<i32>) -> HistoryEntry {
+        HistoryEntry {
+            message_id,
+            user_id: Some(user_id),
+            author: author.to_owned(),
+            timestamp: 1_700_000_000 + i64::from(message_id) * 60,
+            thread_id: None,
+            reply_to,
+            text: format!("message {message_id}"),
+        }
+    }
+
+    #[test]
+    fn summary_window_parses_periods() {
+        let default = Duration::hours(2);
+        assert_eq!(
+            SummaryWindow::parse("федор, что происходит?", default),
+            SummaryWindow::Last(default)
+        );
+        assert_eq!(
+            SummaryWindow::parse("федор, что происходит за последние 3 часа?", default),
+            SummaryWindow::Last(Duration::hours(3))
+        );
+        assert_eq!(
+            SummaryWindow::parse("фёдор, что происходит за последний час", default),
+            SummaryWindow::Last(Duration::hours(1))
+        );
+        assert_eq!(
+            SummaryWindow::parse("fedor, что происходит last 30 minutes", default),
+            SummaryWindow::Last(Duration::minutes(30))
+        );
+        assert_eq!(
+            SummaryWindow::parse("федя, шо происходит за последние 2 дня", default),
+            SummaryWindow::Last(Duration::days(2))
+        );
+    }
+
+    #[test]
+    fn summary_window_parses_since_last_seen() {
+        let default = Duration::hours(2);
+        assert_eq!(
+            SummaryWindow::parse("федор, что происходит пока меня не было?", default),
+            SummaryWindow::SinceLastSeen
+        );
+        assert_eq!(
+            SummaryWindow::parse("Федор, что происходит с тех пор как я был здесь", default),
+            SummaryWindow::SinceLastSeen
+        );
+        assert_eq!(
+            SummaryWindow::parse("fedor, что происходит since I was last here", default),
+            SummaryWindow::SinceLastSeen
+        );
+    }
+
+    #[test]
+    fn since_last_message_skips_up_to_users_previous_message() {
+        let entries = vec![
+            entry(1, 10, "alice", None),
+            entry(2, 20, "bob", None),
+            entry(3, 10, "alice", None),
+            entry(4, 20, "bob", None),
+        ];
+        let ids: Vec<i32> = since_last_message(entries.clone(), Some(10))
+            .iter()
+            .map(|e| e.message_id)
+            .collect();
+        assert_eq!(ids, vec![4]);
+        assert_eq!(since_last_message(entries.clone(), Some(99)), entries);
+    }
+
+    #[test]
+    fn transcript_includes_authors_and_reply_targets() {
+        let entries = vec![entry(1, 10, "alice", None), entry(2, 20, "bob", Some(1))];
+        let transcript = format_transcript(&entries);
+        let lines: Vec<&str> = transcript.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("alice: message 1"), "{}", lines[0]);
+        assert!(lines[1].ends_with("bob → alice: message 2"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn history_key_separates_topics() {
+        assert_eq!(history_key(-100, None), "history:chat:-100");
+        assert_eq!(history_key(-100, Some(7)), "history:chat:-100:thread:7");
+    }
+}
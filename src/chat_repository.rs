@@ -1,5 +1,7 @@
-use crate::chat_gpt_handler::BotProfile;
+use crate::chat_history::HistoryEntry;
 use crate::gpt_service::ChatMessage;
+use crate::persona::{self, PersonaId};
+use crate::ContextLimits;
 use log::info;
 use redis::aio::ConnectionManager;
 use redis::{AsyncCommands, FromRedisValue, RedisResult, RedisWrite, ToRedisArgs, Value};
@@ -9,12 +11,15 @@ use tokio::time::{timeout, Duration};
 
 const REDIS_TIMEOUT: Duration = Duration::from_secs(1);
 
+/// Fetch the most recent `max_messages` turns of a persona conversation.
 pub async fn get_bot_context(
     connection_manager: &mut ConnectionManager,
     key: &String,
+    max_messages: usize,
 ) -> RedisResult<Vec<ChatMessage>> {
     info!("fetching  chat bot context for context_key: {}", key);
-    timeout_cmd(connection_manager.lrange(key, 0, 11)).await
+    let keep = isize::try_from(max_messages).unwrap_or(isize::MAX);
+    timeout_cmd(connection_manager.lrange(key, -keep, -1)).await
 }
 
 /// Serialize a value for storage in Redis. Serialization of these plain,
@@ -42,7 +47,7 @@ impl FromRedisValue for ChatMessage {
     }
 }
 
-impl ToRedisArgs for BotProfile {
+impl ToRedisArgs for PersonaId {
     fn write_redis_args<W>(&self, out: &mut W)
     where
         W: ?Sized + RedisWrite,
@@ -51,7 +56,27 @@ impl ToRedisArgs for BotProfile {
     }
 }
 
-impl FromRedisValue for BotProfile {
+/// Identifiers written before personas became data hold a `BotProfile` name
+/// (`"Fedor"`); those are read back as the matching persona id.
+impl FromRedisValue for PersonaId {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        let str_value: String = FromRedisValue::from_redis_value(v)?;
+        serde_json::from_str::<String>(&str_value)
+            .map(|stored| PersonaId::from_stored(&stored))
+            .map_err(deserialize_error::<Self>)
+    }
+}
+
+impl ToRedisArgs for HistoryEntry {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        out.write_arg_fmt(to_redis_json(self))
+    }
+}
+
+impl FromRedisValue for HistoryEntry {
     fn from_redis_value(v: &Value) -> RedisResult<Self> {
         let str_value: String = FromRedisValue::from_redis_value(v)?;
         serde_json::from_str(&str_value).map_err(deserialize_error::<Self>)
@@ -68,43 +93,132 @@ fn deserialize_error<T>(err: serde_json::Error) -> redis::RedisError {
     ))
 }
 
-pub async fn get_chat_history(
+/// Add a message to a history sorted set (scored by its timestamp), keep only
+/// the newest `max_entries` and refresh the key's TTL, all in one transaction.
+pub async fn push_history_entry(
     connection_manager: &mut ConnectionManager,
-    key: i64,
-) -> RedisResult<Vec<String>> {
-    info!("fetching chat history for context_key: {}", key);
-    timeout_cmd(connection_manager.lrange(key, 0, 20)).await
+    key: &str,
+    entry: &HistoryEntry,
+    max_entries: usize,
+    ttl: chrono::Duration,
+) -> RedisResult<()> {
+    let keep = isize::try_from(max_entries).unwrap_or(isize::MAX);
+    redis::pipe()
+        .atomic()
+        .zadd(key, entry, entry.timestamp)
+        .ignore()
+        .zremrangebyrank(key, 0, -keep - 1)
+        .ignore()
+        .expire(key, ttl.num_seconds())
+        .ignore()
+        .query_async(connection_manager)
+        .await
 }
 
+pub async fn get_history_since(
+    connection_manager: &mut ConnectionManager,
+    key: &str,
+    since_timestamp: i64,
+) -> RedisResult<Vec<HistoryEntry>> {
+    info!("fetching chat history for key: {key} since {since_timestamp}");
+    timeout_cmd(connection_manager.zrangebyscore(key, since_timestamp, "+inf")).await
+}
+
+/// Append turns to a persona conversation, trimming it to the newest
+/// `limits.max_messages` entries and restarting its idle expiry.
 pub async fn push_context(
     redis_connection_manager: &mut ConnectionManager,
     key: &String,
     context: Vec<&ChatMessage>,
+    limits: &ContextLimits,
+) -> RedisResult<()> {
+    let keep = isize::try_from(limits.max_messages).unwrap_or(isize::MAX);
+    redis::pipe()
+        .atomic()
+        .rpush(key, context)
+        .ignore()
+        .ltrim(key, -keep, -1)
+        .ignore()
+        .expire(key, limits.ttl.num_seconds())
+        .ignore()
+        .query_async(redis_connection_manager)
+        .await
+}
+
+pub async fn delete_bot_contexts(
+    redis_connection_manager: &mut ConnectionManager,
+    keys: &[String],
 ) -> RedisResult<()> {
-    redis_connection_manager.rpush(key, context).await
+    // `DEL` without keys is a Redis error; a chat without personas has none.
+    if keys.is_empty() {
+        return Ok(());
+    }
+    info!("deleting bot contexts: {keys:?}");
+    timeout_cmd(redis_connection_manager.del(keys)).await
+}
+
+pub async fn history_len(
+    connection_manager: &mut ConnectionManager,
+    key: &str,
+) -> RedisResult<usize> {
+    timeout_cmd(connection_manager.zcard(key)).await
 }
 
 pub async fn push_bot_msg_identifier(
     redis_connection_manager: &mut ConnectionManager,
     chat_key: &String,
     message_key: i32,
-    profile: BotProfile,
+    persona_id: &PersonaId,
 ) -> RedisResult<()> {
     info!("push bot msg identifier for message_key: {}", message_key);
     redis_connection_manager
-        .hset(chat_key, message_key, profile)
+        .hset(chat_key, message_key, persona_id)
         .await
 }
 
-pub async fn get_bot_msg_profile(
+pub async fn get_bot_msg_persona(
     redis_connection_manager: &mut ConnectionManager,
     chat_key: &String,
     message_key: i32,
-) -> RedisResult<BotProfile> {
-    info!("get bot profile for message_key: {}", message_key);
+) -> RedisResult<PersonaId> {
+    info!("get bot persona for message_key: {}", message_key);
     redis_connection_manager.hget(chat_key, message_key).await
 }
 
+/// Rename persona contexts stored under the old `{BotProfile}:chat:{chat_id}`
+/// keys to their persona id keys. A context that already exists under the new
+/// key is kept and the old one left in place to expire. Returns the number of
+/// contexts moved.
+pub async fn migrate_legacy_context_keys(
+    redis_connection_manager: &mut ConnectionManager,
+) -> RedisResult<usize> {
+    let mut moved = 0;
+    for (profile, id) in persona::LEGACY_PROFILES {
+        let prefix = format!("{profile}:chat:");
+        let mut legacy_keys: Vec<String> = Vec::new();
+        let mut keys = redis_connection_manager
+            .scan_match::<_, String>(format!("{prefix}*"))
+            .await?;
+        while let Some(key) = keys.next_item().await {
+            legacy_keys.push(key);
+        }
+        drop(keys);
+        for legacy_key in legacy_keys {
+            let Some(Ok(chat_id)) = legacy_key.strip_prefix(&prefix).map(str::parse::<i64>) else {
+                continue;
+            };
+            let key = persona::context_key(&PersonaId::new(*id), chat_id);
+            if redis_connection_manager
+                .rename_nx(&legacy_key, &key)
+                .await?
+            {
+                moved += 1;
+            }
+        }
+    }
+    Ok(moved)
+}
+
 #[inline]
 async fn timeout_cmd<T>(future: redis::RedisFuture<'_, T>) -> RedisResult<T> {
     timeout(REDIS_TIMEOUT, future)
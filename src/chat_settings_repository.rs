@@ -0,0 +1,266 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::Duration;
+use log::warn;
+use sqlx::postgres::PgQueryResult;
+use sqlx::{Error, PgPool, Postgres};
+
+use crate::gayness_handler::MuteTiers;
+
+/// Per-chat behaviour overrides. Chats without a row get the defaults: every
+/// handler on, global cooldown and mute tiers, mentions announced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatSettings {
+    pub rust_mention_enabled: bool,
+    pub blazing_fast_enabled: bool,
+    pub gayness_enabled: bool,
+    pub gpt_enabled: bool,
+    pub url_summary_enabled: bool,
+    /// Transcribe voice notes and route the transcript like text.
+    pub voice_enabled: bool,
+    /// Overrides the global "since last incident" cooldown when set.
+    pub mention_cooldown: Option<Duration>,
+    pub mute_tiers: MuteTiers,
+    /// Count Rust mentions without announcing them.
+    pub silent_counting: bool,
+    /// Run the "since last incident" timer per forum topic instead of per chat.
+    pub mention_timer_per_topic: bool,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            rust_mention_enabled: true,
+            blazing_fast_enabled: true,
+            gayness_enabled: true,
+            gpt_enabled: true,
+            url_summary_enabled: true,
+            voice_enabled: true,
+            mention_cooldown: None,
+            mute_tiers: MuteTiers::default(),
+            silent_counting: false,
+            mention_timer_per_topic: false,
+        }
+    }
+}
+
+impl ChatSettings {
+    pub fn is_enabled(&self, feature: ChatFeature) -> bool {
+        match feature {
+            ChatFeature::RustMention => self.rust_mention_enabled,
+            ChatFeature::BlazingFast => self.blazing_fast_enabled,
+            ChatFeature::Gayness => self.gayness_enabled,
+            ChatFeature::Gpt => self.gpt_enabled,
+            ChatFeature::UrlSummary => self.url_summary_enabled,
+            ChatFeature::Voice => self.voice_enabled,
+        }
+    }
+}
+
+/// A message handler that can be switched off per chat.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChatFeature {
+    RustMention,
+    BlazingFast,
+    Gayness,
+    Gpt,
+    UrlSummary,
+    Voice,
+}
+
+impl ChatFeature {
+    pub const ALL: [ChatFeature; 6] = [
+        ChatFeature::RustMention,
+        ChatFeature::BlazingFast,
+        ChatFeature::Gayness,
+        ChatFeature::Gpt,
+        ChatFeature::UrlSummary,
+        ChatFeature::Voice,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChatFeature::RustMention => "rust",
+            ChatFeature::BlazingFast => "bf",
+            ChatFeature::Gayness => "gayness",
+            ChatFeature::Gpt => "gpt",
+            ChatFeature::UrlSummary => "url",
+            ChatFeature::Voice => "voice",
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            ChatFeature::RustMention => "rust_mention_enabled",
+            ChatFeature::BlazingFast => "blazing_fast_enabled",
+            ChatFeature::Gayness => "gayness_enabled",
+            ChatFeature::Gpt => "gpt_enabled",
+            ChatFeature::UrlSummary => "url_summary_enabled",
+            ChatFeature::Voice => "voice_enabled",
+        }
+    }
+}
+
+impl FromStr for ChatFeature {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        ChatFeature::ALL
+            .into_iter()
+            .find(|feature| feature.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = ChatFeature::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "unknown handler '{name}', expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for ChatFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+type ChatSettingsRow = (
+    bool,
+    bool,
+    bool,
+    bool,
+    bool,
+    bool,
+    Option<i32>,
+    Option<String>,
+    bool,
+    bool,
+);
+
+pub async fn get_chat_settings(pool: &PgPool, chat_id: i64) -> Result<ChatSettings, Error> {
+    sqlx::query_as(
+        "SELECT rust_mention_enabled, blazing_fast_enabled, gayness_enabled, gpt_enabled, \
+                url_summary_enabled, voice_enabled, mention_cooldown_minutes, mute_tiers, \
+                silent_counting, mention_timer_per_topic \
+                FROM chat_settings WHERE chat_id = $1",
+    )
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await
+    .map(|row: Option<ChatSettingsRow>| {
+        row.map_or_else(ChatSettings::default, |row| settings_from_row(chat_id, row))
+    })
+}
+
+fn settings_from_row(chat_id: i64, row: ChatSettingsRow) -> ChatSettings {
+    let (
+        rust_mention_enabled,
+        blazing_fast_enabled,
+        gayness_enabled,
+        gpt_enabled,
+        url_summary_enabled,
+        voice_enabled,
+        mention_cooldown_minutes,
+        mute_tiers,
+        silent_counting,
+        mention_timer_per_topic,
+    ) = row;
+    let mute_tiers = mute_tiers
+        .map(|spec| {
+            spec.parse().unwrap_or_else(|err| {
+                warn!("chat {chat_id} has invalid mute tiers '{spec}': {err}; using defaults");
+                MuteTiers::default()
+            })
+        })
+        .unwrap_or_default();
+    ChatSettings {
+        rust_mention_enabled,
+        blazing_fast_enabled,
+        gayness_enabled,
+        gpt_enabled,
+        url_summary_enabled,
+        voice_enabled,
+        mention_cooldown: mention_cooldown_minutes.map(|minutes| Duration::minutes(minutes.into())),
+        mute_tiers,
+        silent_counting,
+        mention_timer_per_topic,
+    }
+}
+
+pub async fn set_feature_enabled(
+    pool: &PgPool,
+    chat_id: i64,
+    feature: ChatFeature,
+    enabled: bool,
+) -> Result<PgQueryResult, Error> {
+    upsert_setting(pool, chat_id, feature.column(), enabled).await
+}
+
+pub async fn set_mention_cooldown(
+    pool: &PgPool,
+    chat_id: i64,
+    cooldown_minutes: Option<i32>,
+) -> Result<PgQueryResult, Error> {
+    upsert_setting(pool, chat_id, "mention_cooldown_minutes", cooldown_minutes).await
+}
+
+pub async fn set_mute_tiers(
+    pool: &PgPool,
+    chat_id: i64,
+    mute_tiers: &MuteTiers,
+) -> Result<PgQueryResult, Error> {
+    upsert_setting(pool, chat_id, "mute_tiers", mute_tiers.to_string()).await
+}
+
+pub async fn set_silent_counting(
+    pool: &PgPool,
+    chat_id: i64,
+    silent: bool,
+) -> Result<PgQueryResult, Error> {
+    upsert_setting(pool, chat_id, "silent_counting", silent).await
+}
+
+pub async fn set_mention_timer_per_topic(
+    pool: &PgPool,
+    chat_id: i64,
+    per_topic: bool,
+) -> Result<PgQueryResult, Error> {
+    upsert_setting(pool, chat_id, "mention_timer_per_topic", per_topic).await
+}
+
+/// Make `chat_id` count Rust mentions silently unless it already has settings,
+/// so a later `/silent off` sticks across restarts. Returns whether it did.
+pub async fn seed_silent_chat(pool: &PgPool, chat_id: i64) -> Result<bool, Error> {
+    let result = sqlx::query(
+        "INSERT INTO chat_settings(chat_id, silent_counting) VALUES ($1, TRUE) \
+         ON CONFLICT (chat_id) DO NOTHING",
+    )
+    .bind(chat_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Set one settings column, creating the chat's row with defaults if needed.
+/// `column` is always one of the static names above, never user input.
+async fn upsert_setting<T>(
+    pool: &PgPool,
+    chat_id: i64,
+    column: &'static str,
+    value: T,
+) -> Result<PgQueryResult, Error>
+where
+    T: for<'q> sqlx::Encode<'q, Postgres> + sqlx::Type<Postgres> + Send + 'static,
+{
+    let query = format!(
+        "INSERT INTO chat_settings(chat_id, {column}) VALUES ($1, $2) \
+                ON CONFLICT (chat_id) DO UPDATE \
+                    SET {column} = $2, updated_at = current_timestamp"
+    );
+    sqlx::query(&query)
+        .bind(chat_id)
+        .bind(value)
+        .execute(pool)
+        .await
+}
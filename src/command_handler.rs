@@ -0,0 +1,1058 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use log::{info, warn};
+use sqlx::PgPool;
+use teloxide::prelude::*;
+use teloxide::types::ReplyParameters;
+use teloxide::utils::command::BotCommands;
+
+use crate::chat_settings_repository::{ChatFeature, ChatSettings};
+use crate::config::ConfigHandle;
+use crate::gayness_handler::MuteTiers;
+use crate::gpt_quota::QuotaLimits;
+use crate::mention_repository::MentionRank;
+use crate::persona::{Persona, PersonaFields, PersonaId};
+use crate::usage_repository::UsageRollup;
+use crate::{
+    chat_history, chat_repository, chat_settings_repository, gpt_quota, mention_repository,
+    persona, persona_repository, usage_repository, AppError, GptParameters,
+};
+
+const RUSTBOARD_SIZE: i64 = 10;
+/// Chats listed by `/usage all`, costliest first.
+const USAGE_TOP_CHATS: usize = 10;
+const NOT_AN_ADMIN_REPLY: &str = "Это могут делать только админы чата.";
+/// How many personas of its own a chat may define.
+const MAX_CHAT_PERSONAS: usize = 20;
+const MUTE_TIERS_USAGE: &str = "Использование: /mutetiers <процент>:<минуты>,…,*:<минуты>\n\
+Проценты по возрастанию, * — для всех остальных, например 5:600,39:60,*:30\n\
+Мьют не дольше 366 дней (527040 минут)";
+const USAGE_USAGE: &str = "/usage [ГГГГ-ММ] — расход GPT в чате, для админов\n\
+/usage all [ГГГГ-ММ] — по всем чатам, для владельцев бота в личке";
+const PERSONA_USAGE: &str = "/persona list — персонажи чата\n\
+/persona show <id> — подробности о персонаже\n\
+/persona add <id> — новый персонаж, поля с новой строки:\n\
+name: Санта\n\
+regex: (?i)санта\n\
+prompt: Ты Санта, раздаёшь подарки.\n\
+provider, model, temperature и languages — по желанию\n\
+/persona edit <id> — поменять поля (default сбрасывает provider, model, temperature, languages)\n\
+/persona remove <id> — удалить персонажа чата\n\
+add, edit и remove доступны только админам.";
+
+#[derive(BotCommands, Clone, Debug)]
+#[command(rename_rule = "lowercase", description = "Я понимаю такие команды:")]
+pub enum Command {
+    #[command(description = "показать это сообщение")]
+    Help,
+    #[command(description = "статистика чата")]
+    Stats,
+    #[command(description = "забыть разговоры с персонажами в этом чате")]
+    Reset,
+    #[command(description = "топ упоминающих Rust в чате или топике")]
+    Rustboard,
+    #[command(description = "таймер инцидентов на весь чат или на топик: chat | topic")]
+    MentionTimer(String),
+    #[command(description = "настройки чата")]
+    Settings,
+    #[command(
+        description = "включить или выключить обработчик: rust | bf | gayness | gpt | url | voice"
+    )]
+    Toggle(String),
+    #[command(description = "кулдаун упоминаний Rust в минутах или default")]
+    Cooldown(String),
+    #[command(description = "уровни мьюта: процент:минуты через запятую, *:минуты для остальных")]
+    MuteTiers(String),
+    #[command(description = "считать упоминания Rust молча: on | off")]
+    Silent(String),
+    #[command(description = "перечитать конфигурацию бота (только для владельцев)")]
+    Reload,
+    #[command(description = "персонажи чата: list | show | add | edit | remove")]
+    Persona(String),
+    #[command(
+        description = "расход GPT за сегодня; reset обнуляет чат или, в ответ на сообщение, его автора"
+    )]
+    Quota(String),
+    #[command(
+        description = "расход токенов GPT в чате за месяц ГГГГ-ММ (по умолчанию текущий); all — по всем чатам, для владельцев"
+    )]
+    Usage(String),
+}
+
+impl Command {
+    /// Commands that change chat settings and are reserved for chat admins.
+    fn requires_admin(&self) -> bool {
+        matches!(
+            self,
+            Command::MentionTimer(_)
+                | Command::Toggle(_)
+                | Command::Cooldown(_)
+                | Command::MuteTiers(_)
+                | Command::Silent(_)
+                | Command::Quota(_)
+        )
+    }
+
+    /// Commands bot owners may also give in a private chat with the bot.
+    pub(crate) fn is_private_owner_command(&self) -> bool {
+        matches!(self, Command::Usage(_) | Command::Reload)
+    }
+
+    /// Commands after which the chat's cached settings and personas are stale.
+    fn changes_chat(&self) -> bool {
+        matches!(
+            self,
+            Command::MentionTimer(_)
+                | Command::Toggle(_)
+                | Command::Cooldown(_)
+                | Command::MuteTiers(_)
+                | Command::Silent(_)
+                | Command::Persona(_)
+        )
+    }
+}
+
+pub async fn handle_command(
+    bot: Bot,
+    msg: Message,
+    command: Command,
+    db_pool: PgPool,
+    gpt_parameters: &GptParameters,
+    config: &ConfigHandle,
+) -> Result<(), AppError> {
+    let chat_id = msg.chat.id;
+    info!("command invocation: chat_id: {chat_id}, command: {command:?}");
+    if command.requires_admin() && !is_chat_admin(&bot, &msg).await? {
+        reply(&bot, &msg, NOT_AN_ADMIN_REPLY.to_owned()).await?;
+        return Ok(());
+    }
+    let changes_chat = command.changes_chat();
+    let text = match command {
+        Command::Help => Command::descriptions().to_string(),
+        Command::Stats => chat_stats(&db_pool, gpt_parameters, chat_id).await?,
+        Command::Reset => {
+            let mut redis_cm = gpt_parameters.redis_connection_manager.clone();
+            let keys: Vec<String> =
+                persona::chat_personas(&db_pool, &gpt_parameters.settings.personas, chat_id.0)
+                    .await
+                    .iter()
+                    .map(|persona| persona.context_key(chat_id.0))
+                    .collect();
+            chat_repository::delete_bot_contexts(&mut redis_cm, &keys).await?;
+            "Всё, забыли. Начинаем с чистого листа.".to_owned()
+        }
+        Command::Rustboard => rustboard(&db_pool, &msg).await?,
+        Command::MentionTimer(scope) => set_mention_timer(&db_pool, chat_id, &scope).await?,
+        Command::Settings => {
+            let settings = chat_settings_repository::get_chat_settings(&db_pool, chat_id.0).await?;
+            format_settings(&settings)
+        }
+        Command::Toggle(feature) => toggle_feature(&db_pool, chat_id, &feature).await?,
+        Command::Cooldown(minutes) => set_cooldown(&db_pool, chat_id, &minutes).await?,
+        Command::MuteTiers(spec) => set_mute_tiers(&db_pool, chat_id, &spec).await?,
+        Command::Silent(mode) => set_silent_counting(&db_pool, chat_id, &mode).await?,
+        Command::Reload => reload_config(&msg, config),
+        Command::Persona(args) => {
+            persona_command(&bot, &msg, &db_pool, gpt_parameters, &args).await?
+        }
+        Command::Quota(action) => quota_command(&msg, gpt_parameters, &action).await?,
+        Command::Usage(args) => usage_command(&bot, &msg, &db_pool, config, &args).await?,
+    };
+    if changes_chat {
+        gpt_parameters.chat_cache.invalidate(chat_id.0);
+    }
+    reply(&bot, &msg, text).await?;
+    Ok(())
+}
+
+async fn chat_stats(
+    db_pool: &PgPool,
+    gpt_parameters: &GptParameters,
+    chat_id: ChatId,
+) -> Result<String, AppError> {
+    let mention_stats = mention_repository::chat_mention_stats(db_pool, chat_id.0).await?;
+    let mut redis_cm = gpt_parameters.redis_connection_manager.clone();
+    let history_len =
+        chat_repository::history_len(&mut redis_cm, &chat_history::history_key(chat_id.0, None))
+            .await?;
+    let last_incident = mention_stats
+        .last_mention
+        .map(|time| format!("{} назад", elapsed_since(time)))
+        .unwrap_or_else(|| "ещё не было".to_owned());
+    let week_ago = (Utc::now() - Duration::days(7)).naive_utc();
+    let weekly = mention_repository::mentions_per_user(db_pool, chat_id.0, week_ago).await?;
+    let weekly_total: i64 = weekly.iter().map(|user| user.mentions).sum();
+    let weekly_leader = weekly
+        .first()
+        .map(|user| format!(" (больше всех: {})", user.username))
+        .unwrap_or_default();
+    let longest_gap = mention_repository::longest_mention_gap(db_pool, chat_id.0)
+        .await?
+        .map(|gap| format_elapsed(gap.duration()))
+        .unwrap_or_else(|| "ещё не известен".to_owned());
+    Ok(format!(
+        "Упоминаний Rust: {} от {} участников\n\
+         За неделю: {weekly_total}{weekly_leader}\n\
+         Последний инцидент: {last_incident}\n\
+         Рекорд без Rust: {longest_gap}\n\
+         Сообщений в истории: {history_len}",
+        mention_stats.total_mentions, mention_stats.mentioners,
+    ))
+}
+
+async fn rustboard(db_pool: &PgPool, msg: &Message) -> Result<String, AppError> {
+    // Inside a forum topic the board covers that topic, otherwise the whole chat.
+    let topic_id = chat_history::topic_thread_id(msg).map(i64::from);
+    let ranks =
+        mention_repository::top_mentioners(db_pool, msg.chat.id.0, topic_id, RUSTBOARD_SIZE)
+            .await?;
+    Ok(format_rustboard(&ranks, Utc::now()))
+}
+
+fn format_rustboard(ranks: &[MentionRank], now: DateTime<Utc>) -> String {
+    if ranks.is_empty() {
+        return "Здесь ещё никто не упоминал Rust. Подозрительно.".to_owned();
+    }
+    let lines: Vec<String> = ranks
+        .iter()
+        .enumerate()
+        .map(|(index, rank)| {
+            let place = match index {
+                0 => "🥇".to_owned(),
+                1 => "🥈".to_owned(),
+                2 => "🥉".to_owned(),
+                _ => format!("{}.", index + 1),
+            };
+            format!(
+                "{place} {} — {} ({} с последнего раза)",
+                rank.username,
+                rank.counter,
+                format_elapsed(
+                    now.signed_duration_since(Utc.from_utc_datetime(&rank.last_mention))
+                )
+            )
+        })
+        .collect();
+    format!("🦀 Rustboard:\n{}", lines.join("\n"))
+}
+
+fn elapsed_since(time: NaiveDateTime) -> String {
+    format_elapsed(Utc::now().signed_duration_since(Utc.from_utc_datetime(&time)))
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    format!(
+        "{}d:{}h:{}m",
+        elapsed.num_days(),
+        elapsed.num_hours() % 24,
+        elapsed.num_minutes() % 60
+    )
+}
+
+async fn set_mention_timer(
+    db_pool: &PgPool,
+    chat_id: ChatId,
+    scope: &str,
+) -> Result<String, AppError> {
+    let per_topic = match scope.trim() {
+        "chat" => false,
+        "topic" => true,
+        other => {
+            return Ok(format!(
+                "Не знаю режим {other}. Использование: /mentiontimer chat|topic"
+            ))
+        }
+    };
+    chat_settings_repository::set_mention_timer_per_topic(db_pool, chat_id.0, per_topic).await?;
+    Ok(if per_topic {
+        "Теперь у каждого топика свой таймер инцидентов.".to_owned()
+    } else {
+        "Теперь таймер инцидентов общий на весь чат.".to_owned()
+    })
+}
+
+/// `/quota` shows today's GPT calls of the chat, and of the author of the
+/// message it replies to; `/quota reset` clears those counters.
+async fn quota_command(
+    msg: &Message,
+    gpt_parameters: &GptParameters,
+    action: &str,
+) -> Result<String, AppError> {
+    let chat_id = msg.chat.id.0;
+    let user = msg
+        .reply_to_message()
+        .and_then(|reply| reply.from.as_ref())
+        .filter(|user| !user.is_bot);
+    let user_id = user.map(|user| user.id.0);
+    let mut redis_cm = gpt_parameters.redis_connection_manager.clone();
+    match action.trim() {
+        "" => {
+            let usage = gpt_quota::usage(&mut redis_cm, chat_id, user_id).await?;
+            let user_usage = user.zip(usage.user_today);
+            Ok(format_quota(
+                &gpt_parameters.settings.quota,
+                usage.chat_today,
+                user_usage.map(|(user, used)| (user.first_name.as_str(), used)),
+            ))
+        }
+        "reset" => {
+            gpt_quota::reset(&mut redis_cm, chat_id, user_id).await?;
+            Ok(match user {
+                Some(user) => format!("Лимиты GPT для {} обнулены.", user.first_name),
+                None => "Лимиты GPT чата обнулены.".to_owned(),
+            })
+        }
+        other => Ok(format!(
+            "Не знаю действие {other}. Использование: /quota [reset]"
+        )),
+    }
+}
+
+fn format_quota(limits: &QuotaLimits, chat_today: u32, user: Option<(&str, u32)>) -> String {
+    let of = |used: u32, limit: u32| match limit {
+        0 => format!("{used}, без лимита"),
+        limit => format!("{used} из {limit}"),
+    };
+    let rate = |burst: u32, per_minute: u32| match burst {
+        0 => "без ограничения скорости".to_owned(),
+        burst => format!("до {burst} подряд, потом {per_minute} в минуту"),
+    };
+    let mut text = format!(
+        "GPT за сегодня (UTC) в чате: {}",
+        of(chat_today, limits.chat_daily)
+    );
+    if let Some((name, used)) = user {
+        text.push_str(&format!("\n{name}: {}", of(used, limits.user_daily)));
+    }
+    text.push_str(&format!(
+        "\nЧат: {}\nКаждый участник: {}",
+        rate(limits.chat_burst, limits.chat_per_minute),
+        rate(limits.user_burst, limits.user_per_minute),
+    ));
+    text
+}
+
+/// `/usage [all] [YYYY-MM]`: the GPT usage of a month in this chat, for chat
+/// admins, or in every chat, for bot owners in a private chat with the bot, so
+/// the chat ids and costs aren't posted into a group.
+async fn usage_command(
+    bot: &Bot,
+    msg: &Message,
+    db_pool: &PgPool,
+    config: &ConfigHandle,
+    args: &str,
+) -> Result<String, AppError> {
+    let mut all_chats = false;
+    let mut month = None;
+    for word in args.split_whitespace() {
+        match word {
+            "all" => all_chats = true,
+            word => match parse_month(word) {
+                Some(parsed) => month = Some(parsed),
+                None => {
+                    return Ok(format!(
+                        "Не понял месяц {word}, нужен ГГГГ-ММ, например 2024-06.\n\n{USAGE_USAGE}"
+                    ))
+                }
+            },
+        }
+    }
+    let month = month.unwrap_or_else(|| {
+        let today = Utc::now().date_naive();
+        today.with_day(1).unwrap_or(today)
+    });
+    let is_owner = is_bot_owner(msg, config);
+    if all_chats {
+        if !is_owner {
+            return Ok("Расход по всем чатам видят только владельцы бота.".to_owned());
+        }
+        if !msg.chat.is_private() {
+            return Ok("Расход по всем чатам покажу только в личке.".to_owned());
+        }
+        let rollup = usage_repository::monthly_rollup(db_pool, month, None).await?;
+        return Ok(format_all_usage(month, &rollup));
+    }
+    if msg.chat.is_private() {
+        return Ok(USAGE_USAGE.to_owned());
+    }
+    if !is_owner && !is_chat_admin(bot, msg).await? {
+        return Ok(NOT_AN_ADMIN_REPLY.to_owned());
+    }
+    let rollup = usage_repository::monthly_rollup(db_pool, month, Some(msg.chat.id.0)).await?;
+    Ok(format_chat_usage(month, &rollup))
+}
+
+/// First day of a `YYYY-MM` month.
+fn parse_month(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(&format!("{value}-01"), "%Y-%m-%d").ok()
+}
+
+/// Calls, tokens and cost added up over rollup rows.
+#[derive(Default)]
+struct UsageTotals {
+    calls: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    cost_micros: i64,
+}
+
+impl UsageTotals {
+    fn of<'a>(rows: impl IntoIterator<Item = &'a UsageRollup>) -> Self {
+        rows.into_iter().fold(Self::default(), |mut totals, row| {
+            totals.calls += row.calls;
+            totals.prompt_tokens += row.prompt_tokens;
+            totals.completion_tokens += row.completion_tokens;
+            totals.cost_micros += row.cost_micros;
+            totals
+        })
+    }
+}
+
+impl std::fmt::Display for UsageTotals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "запросов {}, токенов {} + {}, {}",
+            self.calls,
+            self.prompt_tokens,
+            self.completion_tokens,
+            format_cost(self.cost_micros)
+        )
+    }
+}
+
+fn format_cost(cost_micros: i64) -> String {
+    format!("${:.4}", cost_micros as f64 / 1_000_000.0)
+}
+
+fn format_chat_usage(month: NaiveDate, rollup: &[UsageRollup]) -> String {
+    let month = month.format("%Y-%m");
+    if rollup.is_empty() {
+        return format!("За {month} GPT в чате не звали.");
+    }
+    let lines: Vec<String> = rollup
+        .iter()
+        .map(|row| {
+            let persona = row
+                .persona_id
+                .as_deref()
+                .map(|persona| format!(" {persona}"))
+                .unwrap_or_default();
+            format!(
+                "• {}{persona}, {}: {}",
+                row.handler,
+                row.model,
+                UsageTotals::of([row])
+            )
+        })
+        .collect();
+    format!(
+        "GPT в чате за {month}: {}\n(токены: запрос + ответ)\n{}",
+        UsageTotals::of(rollup),
+        lines.join("\n")
+    )
+}
+
+fn format_all_usage(month: NaiveDate, rollup: &[UsageRollup]) -> String {
+    let month = month.format("%Y-%m");
+    if rollup.is_empty() {
+        return format!("За {month} GPT никто не звал.");
+    }
+    let mut handlers: Vec<&str> = rollup.iter().map(|row| row.handler.as_str()).collect();
+    handlers.sort_unstable();
+    handlers.dedup();
+    let by_handler: Vec<String> = handlers
+        .iter()
+        .map(|handler| {
+            let totals = UsageTotals::of(rollup.iter().filter(|row| row.handler == *handler));
+            format!("• {handler}: {totals}")
+        })
+        .collect();
+    let mut chats: Vec<i64> = rollup.iter().map(|row| row.chat_id).collect();
+    chats.sort_unstable();
+    chats.dedup();
+    let mut by_chat: Vec<(i64, UsageTotals)> = chats
+        .into_iter()
+        .map(|chat_id| {
+            let totals = UsageTotals::of(rollup.iter().filter(|row| row.chat_id == chat_id));
+            (chat_id, totals)
+        })
+        .collect();
+    by_chat.sort_by(|(_, a), (_, b)| {
+        b.cost_micros
+            .cmp(&a.cost_micros)
+            .then(b.calls.cmp(&a.calls))
+    });
+    let top_chats: Vec<String> = by_chat
+        .iter()
+        .take(USAGE_TOP_CHATS)
+        .map(|(chat_id, totals)| format!("• {chat_id}: {totals}"))
+        .collect();
+    format!(
+        "GPT за {month} во всех чатах: {}\n(токены: запрос + ответ)\n\
+         По обработчикам:\n{}\n\
+         Самые дорогие чаты из {}:\n{}",
+        UsageTotals::of(rollup),
+        by_handler.join("\n"),
+        by_chat.len(),
+        top_chats.join("\n")
+    )
+}
+
+fn format_settings(settings: &ChatSettings) -> String {
+    let on_off = |enabled: bool| if enabled { "вкл" } else { "выкл" };
+    let handlers: Vec<String> = ChatFeature::ALL
+        .iter()
+        .map(|feature| format!("{feature}: {}", on_off(settings.is_enabled(*feature))))
+        .collect();
+    let cooldown = settings
+        .mention_cooldown
+        .map(|cooldown| format!("{} мин", cooldown.num_minutes()))
+        .unwrap_or_else(|| "по умолчанию".to_owned());
+    let timer = if settings.mention_timer_per_topic {
+        "topic"
+    } else {
+        "chat"
+    };
+    format!(
+        "Обработчики: {}\n\
+         Кулдаун упоминаний: {cooldown}\n\
+         Уровни мьюта: {}\n\
+         Молчаливый подсчёт: {}\n\
+         Таймер инцидентов: {timer}",
+        handlers.join(", "),
+        settings.mute_tiers,
+        on_off(settings.silent_counting),
+    )
+}
+
+async fn toggle_feature(
+    db_pool: &PgPool,
+    chat_id: ChatId,
+    feature: &str,
+) -> Result<String, AppError> {
+    let Ok(feature) = feature.trim().parse::<ChatFeature>() else {
+        let names: Vec<&str> = ChatFeature::ALL.iter().map(|f| f.name()).collect();
+        return Ok(format!(
+            "Не знаю обработчик {}. Использование: /toggle {}",
+            feature.trim(),
+            names.join("|")
+        ));
+    };
+    let settings = chat_settings_repository::get_chat_settings(db_pool, chat_id.0).await?;
+    let enabled = !settings.is_enabled(feature);
+    chat_settings_repository::set_feature_enabled(db_pool, chat_id.0, feature, enabled).await?;
+    Ok(if enabled {
+        format!("Обработчик {feature} включён.")
+    } else {
+        format!("Обработчик {feature} выключен.")
+    })
+}
+
+async fn set_cooldown(
+    db_pool: &PgPool,
+    chat_id: ChatId,
+    minutes: &str,
+) -> Result<String, AppError> {
+    let minutes = match minutes.trim() {
+        "default" => None,
+        other => match other.parse::<i32>() {
+            Ok(minutes) if minutes >= 0 => Some(minutes),
+            _ => {
+                return Ok(format!(
+                    "Не понял {other}. Использование: /cooldown <минуты>|default"
+                ))
+            }
+        },
+    };
+    chat_settings_repository::set_mention_cooldown(db_pool, chat_id.0, minutes).await?;
+    Ok(match minutes {
+        Some(minutes) => format!("Кулдаун упоминаний Rust: {minutes} мин."),
+        None => "Кулдаун упоминаний Rust сброшен на значение по умолчанию.".to_owned(),
+    })
+}
+
+async fn set_mute_tiers(db_pool: &PgPool, chat_id: ChatId, spec: &str) -> Result<String, AppError> {
+    let mute_tiers = match spec.trim().parse::<MuteTiers>() {
+        Ok(mute_tiers) => mute_tiers,
+        Err(err) => return Ok(format!("Не получилось: {err}.\n\n{MUTE_TIERS_USAGE}")),
+    };
+    chat_settings_repository::set_mute_tiers(db_pool, chat_id.0, &mute_tiers).await?;
+    Ok(format!("Новые уровни мьюта: {mute_tiers}"))
+}
+
+async fn set_silent_counting(
+    db_pool: &PgPool,
+    chat_id: ChatId,
+    mode: &str,
+) -> Result<String, AppError> {
+    let Some(silent) = parse_switch(mode) else {
+        return Ok("Использование: /silent on|off".to_owned());
+    };
+    chat_settings_repository::set_silent_counting(db_pool, chat_id.0, silent).await?;
+    Ok(if silent {
+        "Упоминания Rust теперь считаются молча.".to_owned()
+    } else {
+        "Снова объявляю об упоминаниях Rust.".to_owned()
+    })
+}
+
+fn parse_switch(mode: &str) -> Option<bool> {
+    match mode.trim() {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Reload the bot-wide config. Only bot owners may do this, since it affects
+/// every chat; a config that fails validation leaves the current one running.
+fn reload_config(msg: &Message, config: &ConfigHandle) -> String {
+    if !is_bot_owner(msg, config) {
+        return "Перечитывать конфигурацию могут только владельцы бота.".to_owned();
+    }
+    match config.reload() {
+        Ok(_) => "Конфигурация перечитана.".to_owned(),
+        Err(err) => {
+            warn!(
+                "bot config reload requested in chat {} failed: {err}",
+                msg.chat.id
+            );
+            format!("Конфигурация не применена, работаю со старой: {err}")
+        }
+    }
+}
+
+pub(crate) fn is_bot_owner(msg: &Message, config: &ConfigHandle) -> bool {
+    msg.from
+        .as_ref()
+        .is_some_and(|user| config.current().owner_ids.contains(&user.id.0))
+}
+
+/// `/persona <action> <id>`, with the persona fields on the following lines.
+/// Everyone may look at the personas, only chat admins may change them.
+async fn persona_command(
+    bot: &Bot,
+    msg: &Message,
+    db_pool: &PgPool,
+    gpt_parameters: &GptParameters,
+    args: &str,
+) -> Result<String, AppError> {
+    let chat_id = msg.chat.id.0;
+    let configured = &gpt_parameters.settings.personas;
+    let (head, fields) = args.split_once('\n').unwrap_or((args, ""));
+    let mut words = head.split_whitespace();
+    let action = words.next().unwrap_or("list");
+    let id = words.next().map(PersonaId::new);
+    match (action, id) {
+        ("list", None) => {
+            let personas = persona::chat_personas(db_pool, configured, chat_id).await;
+            let own = persona_repository::chat_persona_ids(db_pool, chat_id).await?;
+            Ok(format_persona_list(&personas, &own))
+        }
+        ("show", Some(id)) => Ok(persona::chat_personas(db_pool, configured, chat_id)
+            .await
+            .iter()
+            .find(|persona| persona.id == id)
+            .map_or_else(|| unknown_persona(&id), format_persona)),
+        ("add" | "edit" | "remove", Some(id)) => {
+            if !is_chat_admin(bot, msg).await? {
+                return Ok(NOT_AN_ADMIN_REPLY.to_owned());
+            }
+            match action {
+                "add" => add_persona(db_pool, gpt_parameters, chat_id, &id, fields).await,
+                "edit" => edit_persona(db_pool, gpt_parameters, chat_id, &id, fields).await,
+                _ => remove_persona(db_pool, gpt_parameters, chat_id, &id).await,
+            }
+        }
+        _ => Ok(PERSONA_USAGE.to_owned()),
+    }
+}
+
+async fn add_persona(
+    db_pool: &PgPool,
+    gpt_parameters: &GptParameters,
+    chat_id: i64,
+    id: &PersonaId,
+    fields: &str,
+) -> Result<String, AppError> {
+    let configured = &gpt_parameters.settings.personas;
+    let available = persona::chat_personas(db_pool, configured, chat_id).await;
+    if available.iter().any(|persona| &persona.id == id) {
+        return Ok(format!(
+            "Персонаж {id} уже есть, поменяй его через /persona edit {id}."
+        ));
+    }
+    if persona_repository::chat_persona_ids(db_pool, chat_id)
+        .await?
+        .len()
+        >= MAX_CHAT_PERSONAS
+    {
+        return Ok(format!(
+            "В чате уже {MAX_CHAT_PERSONAS} своих персонажей, сначала удали кого-нибудь."
+        ));
+    }
+    let persona = match fields
+        .parse::<PersonaFields>()
+        .and_then(|fields| fields.into_persona(id.as_str()))
+    {
+        Ok(persona) => persona,
+        Err(err) => return Ok(format!("Не получилось: {err}.\n\n{PERSONA_USAGE}")),
+    };
+    if let Some(problem) = unknown_provider(gpt_parameters, &persona) {
+        return Ok(problem);
+    }
+    persona_repository::upsert_chat_persona(db_pool, chat_id, &persona).await?;
+    Ok(format!(
+        "Персонаж {} ({id}) готов, отзывается на {}.",
+        persona.name, persona.mention_regex
+    ))
+}
+
+/// Change a persona of the chat. Editing a configured or shared persona saves
+/// an edited copy for this chat only.
+async fn edit_persona(
+    db_pool: &PgPool,
+    gpt_parameters: &GptParameters,
+    chat_id: i64,
+    id: &PersonaId,
+    fields: &str,
+) -> Result<String, AppError> {
+    let base = match persona_repository::get_chat_persona(db_pool, chat_id, id).await? {
+        Some(persona) => Some(persona),
+        None => persona::chat_personas(db_pool, &gpt_parameters.settings.personas, chat_id)
+            .await
+            .into_iter()
+            .find(|persona| &persona.id == id),
+    };
+    let Some(base) = base else {
+        return Ok(unknown_persona(id));
+    };
+    let fields = match fields.parse::<PersonaFields>() {
+        Ok(fields) if fields == PersonaFields::default() => {
+            return Ok(format!("Нечего менять.\n\n{PERSONA_USAGE}"))
+        }
+        Ok(fields) => fields,
+        Err(err) => return Ok(format!("Не получилось: {err}.\n\n{PERSONA_USAGE}")),
+    };
+    let mut persona = match fields.apply(base) {
+        Ok(persona) => persona,
+        Err(err) => return Ok(format!("Не получилось: {err}.")),
+    };
+    if let Some(problem) = unknown_provider(gpt_parameters, &persona) {
+        return Ok(problem);
+    }
+    // Chat rows are scoped by their chat_id already.
+    persona.enabled_chats.clear();
+    persona_repository::upsert_chat_persona(db_pool, chat_id, &persona).await?;
+    Ok(format!("Персонаж {id} обновлён."))
+}
+
+async fn remove_persona(
+    db_pool: &PgPool,
+    gpt_parameters: &GptParameters,
+    chat_id: i64,
+    id: &PersonaId,
+) -> Result<String, AppError> {
+    if !persona_repository::delete_chat_persona(db_pool, chat_id, id).await? {
+        let is_configured =
+            persona::chat_personas(db_pool, &gpt_parameters.settings.personas, chat_id)
+                .await
+                .iter()
+                .any(|persona| &persona.id == id);
+        return Ok(if is_configured {
+            format!("Персонаж {id} задан в конфигурации бота, удалить его можно только там.")
+        } else {
+            unknown_persona(id)
+        });
+    }
+    let mut redis_cm = gpt_parameters.redis_connection_manager.clone();
+    chat_repository::delete_bot_contexts(&mut redis_cm, &[persona::context_key(id, chat_id)])
+        .await
+        .inspect_err(|err| warn!("Can't delete context of removed persona {id}: {err:?}"))
+        .ok();
+    Ok(format!("Персонаж {id} удалён."))
+}
+
+/// A complaint when the persona names a provider the bot config lacks.
+fn unknown_provider(gpt_parameters: &GptParameters, persona: &Persona) -> Option<String> {
+    persona
+        .provider
+        .as_deref()
+        .filter(|provider| gpt_parameters.provider_settings(provider).is_none())
+        .map(|provider| format!("Не получилось: провайдера {provider} нет в конфигурации бота."))
+}
+
+fn unknown_persona(id: &PersonaId) -> String {
+    format!("Нет персонажа {id}. Список: /persona list")
+}
+
+fn format_persona_list(personas: &[Persona], own: &[PersonaId]) -> String {
+    if personas.is_empty() {
+        return "В этом чате нет персонажей.".to_owned();
+    }
+    let lines: Vec<String> = personas
+        .iter()
+        .map(|persona| {
+            let mark = if own.contains(&persona.id) {
+                " (свой)"
+            } else {
+                ""
+            };
+            format!("• {} — {}{mark}", persona.id, persona.name)
+        })
+        .collect();
+    format!(
+        "Персонажи чата:\n{}\n\nПодробнее: /persona show <id>",
+        lines.join("\n")
+    )
+}
+
+fn format_persona(persona: &Persona) -> String {
+    let or_default = |value: Option<String>| value.unwrap_or_else(|| "по умолчанию".to_owned());
+    let languages = if persona.languages.is_empty() {
+        "любые".to_owned()
+    } else {
+        persona.languages.join(", ")
+    };
+    format!(
+        "{} ({})\n\
+         regex: {}\n\
+         provider: {}\n\
+         model: {}\n\
+         temperature: {}\n\
+         languages: {languages}\n\
+         prompt:\n{}",
+        persona.name,
+        persona.id,
+        persona.mention_regex,
+        or_default(persona.provider.clone()),
+        or_default(persona.model.as_deref().map(str::to_owned)),
+        or_default(
+            persona
+                .temperature
+                .map(|temperature| temperature.to_string())
+        ),
+        persona.system_prompt,
+    )
+}
+
+/// Whether the command author is the chat owner or an administrator.
+pub async fn is_chat_admin(bot: &Bot, msg: &Message) -> Result<bool, AppError> {
+    let Some(user) = msg.from.as_ref() else {
+        return Ok(false);
+    };
+    let member = bot.get_chat_member(msg.chat.id, user.id).await?;
+    Ok(member.is_privileged())
+}
+
+/// Reply to a command, routing it into the originating message thread when
+/// there is one.
+async fn reply(bot: &Bot, msg: &Message, text: String) -> Result<Message, AppError> {
+    let request = bot
+        .send_message(msg.chat.id, text)
+        .reply_parameters(ReplyParameters::new(msg.id));
+    let sent = match msg.thread_id {
+        Some(thread_id) => request.message_thread_id(thread_id).await?,
+        None => request.await?,
+    };
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        format_all_usage, format_chat_usage, format_persona, format_persona_list, format_quota,
+        format_rustboard, format_settings, parse_month, parse_switch, Command,
+    };
+    use crate::chat_settings_repository::ChatSettings;
+    use crate::gpt_quota::QuotaLimits;
+    use crate::mention_repository::MentionRank;
+    use crate::persona::{default_personas, PersonaId};
+    use crate::usage_repository::UsageRollup;
+    use chrono::{Duration, TimeZone, Utc};
+    use teloxide::utils::command::BotCommands;
+
+    #[test]
+    fn rustboard_ranks_users_with_time_since_last_mention() {
+        let now = Utc.with_ymd_and_hms(2024, 5, 10, 12, 0, 0).unwrap();
+        let rank = |username: &str, counter, ago: Duration| MentionRank {
+            user_id: 1,
+            username: username.to_owned(),
+            counter,
+            last_mention: (now - ago).naive_utc(),
+        };
+        let board = format_rustboard(
+            &[
+                rank("alice", 12, Duration::minutes(5)),
+                rank("bob", 7, Duration::hours(26)),
+                rank("carol", 3, Duration::days(3)),
+                rank("dave", 1, Duration::minutes(61)),
+            ],
+            now,
+        );
+        let lines: Vec<&str> = board.lines().collect();
+        assert_eq!(lines[1], "🥇 alice — 12 (0d:0h:5m с последнего раза)");
+        assert_eq!(lines[2], "🥈 bob — 7 (1d:2h:0m с последнего раза)");
+        assert_eq!(lines[3], "🥉 carol — 3 (3d:0h:0m с последнего раза)");
+        assert_eq!(lines[4], "4. dave — 1 (0d:1h:1m с последнего раза)");
+    }
+
+    #[test]
+    fn empty_rustboard_has_placeholder() {
+        assert!(!format_rustboard(&[], Utc::now()).is_empty());
+    }
+
+    #[test]
+    fn commands_parse_with_and_without_bot_mention() {
+        assert!(matches!(
+            Command::parse("/help", "rust_bot"),
+            Ok(Command::Help)
+        ));
+        assert!(matches!(
+            Command::parse("/stats@rust_bot", "rust_bot"),
+            Ok(Command::Stats)
+        ));
+        assert!(matches!(
+            Command::parse("/reset", "rust_bot"),
+            Ok(Command::Reset)
+        ));
+        assert!(matches!(
+            Command::parse("/mentiontimer topic", "rust_bot"),
+            Ok(Command::MentionTimer(scope)) if scope == "topic"
+        ));
+        assert!(matches!(
+            Command::parse("/toggle gpt", "rust_bot"),
+            Ok(Command::Toggle(feature)) if feature == "gpt"
+        ));
+        assert!(matches!(
+            Command::parse("/mutetiers 5:600,*:30", "rust_bot"),
+            Ok(Command::MuteTiers(spec)) if spec == "5:600,*:30"
+        ));
+        assert!(matches!(
+            Command::parse("/persona add santa\nregex: santa\nprompt: Хо-хо", "rust_bot"),
+            Ok(Command::Persona(args)) if args == "add santa\nregex: santa\nprompt: Хо-хо"
+        ));
+        assert!(Command::parse("/stats@other_bot", "rust_bot").is_err());
+        assert!(Command::parse("rust", "rust_bot").is_err());
+    }
+
+    #[test]
+    fn settings_commands_require_admin() {
+        assert!(Command::Toggle("gpt".to_owned()).requires_admin());
+        assert!(Command::Silent("on".to_owned()).requires_admin());
+        assert!(!Command::Settings.requires_admin());
+        assert!(!Command::Stats.requires_admin());
+    }
+
+    #[test]
+    fn settings_summary_lists_every_handler() {
+        let mut settings = ChatSettings {
+            gayness_enabled: false,
+            silent_counting: true,
+            ..ChatSettings::default()
+        };
+        settings.mention_cooldown = Some(Duration::minutes(5));
+        let summary = format_settings(&settings);
+        assert!(summary.contains("rust: вкл, bf: вкл, gayness: выкл, gpt: вкл, url: вкл"));
+        assert!(summary.contains("Кулдаун упоминаний: 5 мин"));
+        assert!(summary.contains("Молчаливый подсчёт: вкл"));
+    }
+
+    #[test]
+    fn switch_accepts_only_on_and_off() {
+        assert!(parse_switch(" on").unwrap());
+        assert!(!parse_switch("off").unwrap());
+        assert!(parse_switch("yes").is_none());
+    }
+
+    #[test]
+    fn persona_list_marks_chat_personas() {
+        let personas = default_personas();
+        let list = format_persona_list(&personas, &[PersonaId::new("felix")]);
+        assert!(list.contains("• fedor — Федор\n"));
+        assert!(list.contains("• felix — Феликс (свой)"));
+        assert!(format_persona_list(&[], &[]).contains("нет персонажей"));
+    }
+
+    #[test]
+    fn persona_details_show_defaults_and_prompt() {
+        let mut ferris = default_personas().remove(2);
+        ferris.temperature = Some(0.5);
+        let details = format_persona(&ferris);
+        assert!(details.starts_with("Феррис (ferris)\n"));
+        assert!(details.contains("model: по умолчанию"));
+        assert!(details.contains("temperature: 0.5"));
+        assert!(details.contains("languages: любые"));
+        assert!(details.ends_with(&ferris.system_prompt));
+    }
+
+    #[test]
+    fn quota_shows_usage_against_limits() {
+        let limits = QuotaLimits {
+            user_burst: 0,
+            chat_daily: 0,
+            ..QuotaLimits::default()
+        };
+        assert_eq!(
+            format_quota(&limits, 7, Some(("Вася", 3))),
+            "GPT за сегодня (UTC) в чате: 7, без лимита\n\
+             Вася: 3 из 100\n\
+             Чат: до 30 подряд, потом 10 в минуту\n\
+             Каждый участник: без ограничения скорости"
+        );
+        assert!(!format_quota(&limits, 7, None).contains("Вася"));
+    }
+
+    fn rollup(chat_id: i64, handler: &str, calls: i64, cost_micros: i64) -> UsageRollup {
+        UsageRollup {
+            chat_id,
+            handler: handler.to_owned(),
+            persona_id: (handler == "conversation").then(|| "fedor".to_owned()),
+            model: "gpt-4o".to_owned(),
+            calls,
+            prompt_tokens: calls * 100,
+            completion_tokens: calls * 10,
+            cost_micros,
+        }
+    }
+
+    #[test]
+    fn chat_usage_lists_handlers_with_totals() {
+        let month = parse_month("2024-06").unwrap();
+        assert_eq!(
+            format_chat_usage(
+                month,
+                &[
+                    rollup(-1, "conversation", 3, 1_250_000),
+                    rollup(-1, "url_summary", 1, 4_500)
+                ]
+            ),
+            "GPT в чате за 2024-06: запросов 4, токенов 400 + 40, $1.2545\n\
+             (токены: запрос + ответ)\n\
+             • conversation fedor, gpt-4o: запросов 3, токенов 300 + 30, $1.2500\n\
+             • url_summary, gpt-4o: запросов 1, токенов 100 + 10, $0.0045"
+        );
+        assert!(format_chat_usage(month, &[]).contains("не звали"));
+        assert!(parse_month("июнь").is_none());
+        assert!(parse_month("2024-13").is_none());
+    }
+
+    #[test]
+    fn usage_of_all_chats_ranks_chats_by_cost() {
+        let month = parse_month("2024-06").unwrap();
+        let text = format_all_usage(
+            month,
+            &[
+                rollup(-2, "conversation", 5, 3_000),
+                rollup(-1, "url_summary", 1, 2_000),
+                rollup(-1, "conversation", 1, 2_000),
+            ],
+        );
+        assert!(text.starts_with("GPT за 2024-06 во всех чатах: запросов 7,"));
+        assert!(text.contains("• conversation: запросов 6,"));
+        assert!(text.contains("• url_summary: запросов 1,"));
+        let costliest = text.find("• -1: запросов 2").unwrap();
+        let cheaper = text.find("• -2: запросов 5").unwrap();
+        assert!(costliest < cheaper, "{text}");
+    }
+}
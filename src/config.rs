@@ -0,0 +1,1081 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::{env, fs};
+
+use arc_swap::ArcSwap;
+use chrono::Duration;
+use log::info;
+use regex::Regex;
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::boot::{
+    BLAZING_FAST_REGEX, CONTEXT_MAX_MESSAGES, CONTEXT_TTL_HOURS, DEFAULT_SUMMARY_WINDOW_HOURS,
+    GAYNESS_REGEX, GPT_REQUEST_TIMEOUT_SECS, HISTORY_MAX_ENTRIES, HISTORY_TTL_HOURS, MIN_TIME_DIFF,
+    RUST_REGEX, STREAM_EDIT_INTERVAL_MS, URL_REGEX,
+};
+use crate::error::ConfigError;
+use crate::gpt_quota::{
+    QuotaLimits, CHAT_BURST, CHAT_DAILY, CHAT_PER_MINUTE, USER_BURST, USER_DAILY, USER_PER_MINUTE,
+};
+use crate::llm_provider::{
+    ProviderKind, ProviderSettings, DEFAULT_ANTHROPIC_BASE_URL, DEFAULT_OLLAMA_BASE_URL,
+    DEFAULT_PROVIDER,
+};
+use crate::llm_retry::{
+    BreakerPolicy, RetryPolicy, BREAKER_COOLDOWN_SECS, BREAKER_FAILURE_THRESHOLD,
+    RETRY_BASE_DELAY_MS, RETRY_MAX_ATTEMPTS, RETRY_MAX_DELAY_SECS,
+};
+use crate::llm_usage::{self, ModelPrice};
+use crate::persona::{Persona, PersonaId, MAX_TEMPERATURE};
+use crate::rust_mention_handler::DEFAULT_STICKERS;
+use crate::speech::{
+    SpeechKind, SpeechSettings, DEFAULT_TRANSCRIPTION_MODEL, DEFAULT_TRANSCRIPTION_URL,
+    DEFAULT_WHISPER_URL, MAX_RECORDING_SECS,
+};
+use crate::url_summary_cache::SUMMARY_CACHE_TTL_HOURS;
+use crate::vision;
+use crate::{
+    persona, ContextLimits, GptSettings, HistoryParameters, MentionParameters, DEFAULT_GPT_MODEL,
+    DEFAULT_MAX_COMPLETION_TOKENS, DEFAULT_OPENAI_BASE_URL,
+};
+
+/// Env var pointing at the config file.
+pub const CONFIG_PATH_ENV: &str = "BOT_CONFIG";
+/// Used when `BOT_CONFIG` is unset; a missing default file means built-in
+/// defaults, a missing explicitly configured file is an error.
+const DEFAULT_CONFIG_PATH: &str = "bot.toml";
+/// `RUST_BOT__GPT__MODEL=gpt-4o-mini` overrides `gpt.model`.
+const ENV_OVERRIDE_PREFIX: &str = "RUST_BOT__";
+const ENV_OVERRIDE_SEPARATOR: &str = "__";
+
+/// Bot behaviour loaded from the config file: everything the dispatcher needs
+/// except secrets and connections, which stay in env vars.
+#[derive(Clone, Debug, Default)]
+pub struct BotConfig {
+    pub mention_parameters: MentionParameters,
+    pub history_parameters: HistoryParameters,
+    pub gpt_settings: Arc<GptSettings>,
+    /// Telegram user ids allowed to run bot-wide commands such as `/reload`.
+    pub owner_ids: Vec<u64>,
+}
+
+/// Shared, atomically swappable handle to the current [`BotConfig`]. Readers
+/// take a snapshot per update, so a reload never mixes old and new values
+/// within one message.
+#[derive(Clone, Debug)]
+pub struct ConfigHandle(Arc<ArcSwap<BotConfig>>);
+
+impl ConfigHandle {
+    pub fn new(config: BotConfig) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(config)))
+    }
+
+    pub fn current(&self) -> Arc<BotConfig> {
+        self.0.load_full()
+    }
+
+    /// Re-read the config from the file and env. On error the current config
+    /// stays in place.
+    pub fn reload(&self) -> Result<Arc<BotConfig>, ConfigError> {
+        self.reload_with(BotConfig::from_env)
+    }
+
+    pub fn reload_with(
+        &self,
+        load: impl FnOnce() -> Result<BotConfig, ConfigError>,
+    ) -> Result<Arc<BotConfig>, ConfigError> {
+        let config = Arc::new(load()?);
+        self.0.store(Arc::clone(&config));
+        info!("bot config reloaded");
+        Ok(config)
+    }
+}
+
+impl BotConfig {
+    /// Load the file named by `BOT_CONFIG` (or `bot.toml`) and apply the
+    /// `RUST_BOT__*` env overrides.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let (path, required) = match env::var(CONFIG_PATH_ENV) {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_PATH.to_owned(), false),
+        };
+        let path = Path::new(&path);
+        let source = if required || path.exists() {
+            info!("loading bot config from {}", path.display());
+            fs::read_to_string(path).map_err(|source| ConfigError::Read {
+                path: path.display().to_string(),
+                source,
+            })?
+        } else {
+            info!("no {DEFAULT_CONFIG_PATH} found, using built-in defaults");
+            String::new()
+        };
+        Self::parse(&path.display().to_string(), &source, env::vars())
+    }
+
+    /// Parse a TOML config, overlay the env overrides found in `vars` and
+    /// validate the result. Provider API keys are looked up in `vars` too.
+    pub fn parse(
+        path: &str,
+        source: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let mut table: Table = toml::from_str(source).map_err(|source| ConfigError::Parse {
+            path: path.to_owned(),
+            source,
+        })?;
+        let vars: HashMap<String, String> = vars.into_iter().collect();
+        for (name, value) in &vars {
+            if let Some(key) = name.strip_prefix(ENV_OVERRIDE_PREFIX) {
+                apply_override(&mut table, key, value)?;
+            }
+        }
+        let raw: RawConfig = serde_path_to_error::deserialize(Value::Table(table))
+            .map_err(|err| ConfigError::invalid(err.path().to_string(), err.inner()))?;
+        raw.validate(&vars)
+    }
+}
+
+/// Set `SECTION__KEY` (case-insensitive) in the parsed table. The value is
+/// read as a TOML literal when it is one (`15`, `true`, `["a", "b"]`) and as a
+/// plain string otherwise, except that a key the file sets to a string stays a
+/// string. A string that looks like a literal and isn't in the file has to be
+/// quoted: `RUST_BOT__GPT__MODEL='"1"'`.
+fn apply_override(table: &mut Table, key: &str, raw: &str) -> Result<(), ConfigError> {
+    let path: Vec<String> = key
+        .split(ENV_OVERRIDE_SEPARATOR)
+        .map(str::to_lowercase)
+        .collect();
+    let dotted = path.join(".");
+    let Some((leaf, sections)) = path.split_last() else {
+        return Ok(());
+    };
+    let mut current = table;
+    for section in sections {
+        current = current
+            .entry(section.clone())
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| ConfigError::invalid(&dotted, format!("`{section}` is not a table")))?;
+    }
+    let literal = toml::from_str::<Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"));
+    let value = match (current.get(leaf.as_str()), literal) {
+        (Some(Value::String(_)), Some(Value::String(quoted))) => Value::String(quoted),
+        (Some(Value::String(_)), _) | (_, None) => Value::String(raw.to_owned()),
+        (_, Some(literal)) => literal,
+    };
+    info!("config override from env: {dotted}");
+    current.insert(leaf.clone(), value);
+    Ok(())
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    mentions: RawMentions,
+    gpt: RawGpt,
+    history: RawHistory,
+    admin: RawAdmin,
+    quota: RawQuota,
+    speech: RawSpeech,
+    providers: HashMap<String, RawProvider>,
+    /// Model name to price, on top of the built-in ones.
+    prices: HashMap<String, RawPrice>,
+    personas: Option<Vec<RawPersona>>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawMentions {
+    rust_regex: String,
+    blazing_fast_regex: String,
+    gayness_regex: String,
+    url_regex: String,
+    cooldown_minutes: u32,
+    stickers: Vec<String>,
+}
+
+impl Default for RawMentions {
+    fn default() -> Self {
+        Self {
+            rust_regex: RUST_REGEX.to_owned(),
+            blazing_fast_regex: BLAZING_FAST_REGEX.to_owned(),
+            gayness_regex: GAYNESS_REGEX.to_owned(),
+            url_regex: URL_REGEX.to_owned(),
+            cooldown_minutes: MIN_TIME_DIFF as u32,
+            stickers: DEFAULT_STICKERS.iter().map(|&s| s.to_owned()).collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawGpt {
+    base_url: String,
+    model: String,
+    max_completion_tokens: usize,
+    request_timeout_secs: u64,
+    context_max_messages: usize,
+    context_ttl_hours: u32,
+    provider: String,
+    /// Chat id to provider name.
+    chat_providers: HashMap<String, String>,
+    retry_attempts: u32,
+    retry_base_delay_ms: u64,
+    retry_max_delay_secs: u64,
+    breaker_failure_threshold: u32,
+    breaker_cooldown_secs: u64,
+    streaming: bool,
+    stream_edit_interval_ms: u64,
+    vision_models: Vec<String>,
+    /// 0 switches the cache off.
+    summary_cache_ttl_hours: u64,
+}
+
+impl Default for RawGpt {
+    fn default() -> Self {
+        Self {
+            base_url: DEFAULT_OPENAI_BASE_URL.to_owned(),
+            model: DEFAULT_GPT_MODEL.to_owned(),
+            max_completion_tokens: DEFAULT_MAX_COMPLETION_TOKENS,
+            request_timeout_secs: GPT_REQUEST_TIMEOUT_SECS,
+            context_max_messages: CONTEXT_MAX_MESSAGES,
+            context_ttl_hours: CONTEXT_TTL_HOURS as u32,
+            provider: DEFAULT_PROVIDER.to_owned(),
+            chat_providers: HashMap::new(),
+            retry_attempts: RETRY_MAX_ATTEMPTS,
+            retry_base_delay_ms: RETRY_BASE_DELAY_MS,
+            retry_max_delay_secs: RETRY_MAX_DELAY_SECS,
+            breaker_failure_threshold: BREAKER_FAILURE_THRESHOLD,
+            breaker_cooldown_secs: BREAKER_COOLDOWN_SECS,
+            streaming: true,
+            stream_edit_interval_ms: STREAM_EDIT_INTERVAL_MS,
+            vision_models: vision::default_vision_models(),
+            summary_cache_ttl_hours: SUMMARY_CACHE_TTL_HOURS,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawHistory {
+    max_entries: usize,
+    ttl_hours: u32,
+    default_summary_window_hours: u32,
+}
+
+impl Default for RawHistory {
+    fn default() -> Self {
+        Self {
+            max_entries: HISTORY_MAX_ENTRIES,
+            ttl_hours: HISTORY_TTL_HOURS as u32,
+            default_summary_window_hours: DEFAULT_SUMMARY_WINDOW_HOURS as u32,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawAdmin {
+    owner_ids: Vec<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawQuota {
+    user_burst: u32,
+    user_per_minute: u32,
+    chat_burst: u32,
+    chat_per_minute: u32,
+    user_daily: u32,
+    chat_daily: u32,
+}
+
+impl Default for RawQuota {
+    fn default() -> Self {
+        Self {
+            user_burst: USER_BURST,
+            user_per_minute: USER_PER_MINUTE,
+            chat_burst: CHAT_BURST,
+            chat_per_minute: CHAT_PER_MINUTE,
+            user_daily: USER_DAILY,
+            chat_daily: CHAT_DAILY,
+        }
+    }
+}
+
+impl RawQuota {
+    fn validate(self) -> Result<QuotaLimits, ConfigError> {
+        // A bucket that is on has to refill.
+        if self.user_burst > 0 {
+            positive("quota.user_per_minute", self.user_per_minute)?;
+        }
+        if self.chat_burst > 0 {
+            positive("quota.chat_per_minute", self.chat_per_minute)?;
+        }
+        Ok(QuotaLimits {
+            user_burst: self.user_burst,
+            user_per_minute: self.user_per_minute,
+            chat_burst: self.chat_burst,
+            chat_per_minute: self.chat_per_minute,
+            user_daily: self.user_daily,
+            chat_daily: self.chat_daily,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawSpeech {
+    kind: SpeechKind,
+    /// Defaults to the usual endpoint of the kind.
+    base_url: Option<String>,
+    /// Env var holding the API key; `CHAT_GPT_API_TOKEN` when unset.
+    api_key_env: Option<String>,
+    model: String,
+    language: Option<String>,
+    max_duration_secs: u64,
+}
+
+impl Default for RawSpeech {
+    fn default() -> Self {
+        Self {
+            kind: SpeechKind::OpenAi,
+            base_url: None,
+            api_key_env: None,
+            model: DEFAULT_TRANSCRIPTION_MODEL.to_owned(),
+            language: None,
+            max_duration_secs: MAX_RECORDING_SECS,
+        }
+    }
+}
+
+impl RawSpeech {
+    fn validate(self, vars: &HashMap<String, String>) -> Result<SpeechSettings, ConfigError> {
+        let base_url = self.base_url.unwrap_or_else(|| {
+            match self.kind {
+                SpeechKind::OpenAi => DEFAULT_TRANSCRIPTION_URL,
+                SpeechKind::Whisper => DEFAULT_WHISPER_URL,
+            }
+            .to_owned()
+        });
+        http_url("speech.base_url", &base_url)?;
+        let api_key = match self.api_key_env {
+            Some(var) => Some(env_api_key("speech.api_key_env", &var, vars)?),
+            None => None,
+        };
+        if self.model.trim().is_empty() {
+            return Err(ConfigError::invalid("speech.model", "must not be empty"));
+        }
+        Ok(SpeechSettings {
+            kind: self.kind,
+            base_url: Arc::from(base_url),
+            api_key,
+            model: Arc::from(self.model),
+            language: self
+                .language
+                .filter(|language| !language.trim().is_empty())
+                .map(Arc::from),
+            max_duration: std::time::Duration::from_secs(positive(
+                "speech.max_duration_secs",
+                self.max_duration_secs,
+            )?),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProvider {
+    kind: ProviderKind,
+    /// Defaults to the public endpoint of the kind.
+    base_url: Option<String>,
+    /// Env var holding the API key; the key itself never goes in the file.
+    api_key_env: Option<String>,
+    model: Option<String>,
+}
+
+/// USD per million tokens.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPrice {
+    prompt: f64,
+    completion: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPersona {
+    id: String,
+    /// Defaults to the id.
+    name: Option<String>,
+    mention_regex: String,
+    system_prompt: String,
+    provider: Option<String>,
+    model: Option<String>,
+    temperature: Option<f32>,
+    #[serde(default)]
+    languages: Vec<String>,
+    #[serde(default)]
+    enabled_chats: Vec<i64>,
+}
+
+impl RawConfig {
+    fn validate(self, vars: &HashMap<String, String>) -> Result<BotConfig, ConfigError> {
+        let RawConfig {
+            mentions,
+            gpt,
+            history,
+            admin,
+            quota,
+            speech,
+            providers,
+            prices,
+            personas,
+        } = self;
+        if mentions.stickers.is_empty() {
+            return Err(ConfigError::invalid(
+                "mentions.stickers",
+                "at least one sticker id is required",
+            ));
+        }
+        let mention_parameters = MentionParameters {
+            rust_regex: regex("mentions.rust_regex", &mentions.rust_regex)?,
+            blazing_fast_regex: regex("mentions.blazing_fast_regex", &mentions.blazing_fast_regex)?,
+            gayness_regex: regex("mentions.gayness_regex", &mentions.gayness_regex)?,
+            url_regex: regex("mentions.url_regex", &mentions.url_regex)?,
+            req_time_diff: Duration::minutes(mentions.cooldown_minutes.into()),
+            stickers: Arc::from(mentions.stickers),
+        };
+
+        let history_parameters = HistoryParameters {
+            max_entries: positive("history.max_entries", history.max_entries)?,
+            ttl: positive_hours("history.ttl_hours", history.ttl_hours)?,
+            default_summary_window: positive_hours(
+                "history.default_summary_window_hours",
+                history.default_summary_window_hours,
+            )?,
+        };
+
+        http_url("gpt.base_url", &gpt.base_url)?;
+        if gpt.model.trim().is_empty() {
+            return Err(ConfigError::invalid("gpt.model", "must not be empty"));
+        }
+        let providers = validate_providers(providers, vars)?;
+        let provider_exists = |key: String, name: &str| {
+            if name == DEFAULT_PROVIDER || providers.contains_key(name) {
+                Ok(())
+            } else {
+                Err(ConfigError::invalid(
+                    key,
+                    format!("no provider named {name} in [providers]"),
+                ))
+            }
+        };
+        provider_exists("gpt.provider".to_owned(), &gpt.provider)?;
+        let mut chat_providers = HashMap::with_capacity(gpt.chat_providers.len());
+        for (chat_id, provider) in gpt.chat_providers {
+            let key = format!("gpt.chat_providers.{chat_id}");
+            provider_exists(key.clone(), &provider)?;
+            let chat_id = chat_id
+                .parse::<i64>()
+                .map_err(|_| ConfigError::invalid(key, "keys must be chat ids"))?;
+            chat_providers.insert(chat_id, provider);
+        }
+        let personas = match personas {
+            Some(personas) => validate_personas(personas)?,
+            None => persona::default_personas(),
+        };
+        for (index, persona) in personas.iter().enumerate() {
+            if let Some(provider) = &persona.provider {
+                provider_exists(format!("personas[{index}].provider"), provider)?;
+            }
+        }
+        let gpt_settings = GptSettings {
+            openai_base_url: Arc::from(gpt.base_url),
+            model: Arc::from(gpt.model),
+            max_completion_tokens: positive(
+                "gpt.max_completion_tokens",
+                gpt.max_completion_tokens,
+            )?,
+            request_timeout: std::time::Duration::from_secs(positive(
+                "gpt.request_timeout_secs",
+                gpt.request_timeout_secs,
+            )?),
+            context_limits: ContextLimits {
+                max_messages: positive("gpt.context_max_messages", gpt.context_max_messages)?,
+                ttl: positive_hours("gpt.context_ttl_hours", gpt.context_ttl_hours)?,
+            },
+            personas,
+            default_provider: Arc::from(gpt.provider),
+            providers,
+            chat_providers,
+            retry: RetryPolicy {
+                max_attempts: positive("gpt.retry_attempts", gpt.retry_attempts)?,
+                base_delay: std::time::Duration::from_millis(positive(
+                    "gpt.retry_base_delay_ms",
+                    gpt.retry_base_delay_ms,
+                )?),
+                max_delay: std::time::Duration::from_secs(positive(
+                    "gpt.retry_max_delay_secs",
+                    gpt.retry_max_delay_secs,
+                )?),
+            },
+            breaker: BreakerPolicy {
+                failure_threshold: positive(
+                    "gpt.breaker_failure_threshold",
+                    gpt.breaker_failure_threshold,
+                )?,
+                cooldown: std::time::Duration::from_secs(positive(
+                    "gpt.breaker_cooldown_secs",
+                    gpt.breaker_cooldown_secs,
+                )?),
+            },
+            streaming: gpt.streaming,
+            stream_edit_interval: std::time::Duration::from_millis(positive(
+                "gpt.stream_edit_interval_ms",
+                gpt.stream_edit_interval_ms,
+            )?),
+            quota: quota.validate()?,
+            prices: validate_prices(prices)?,
+            vision_models: gpt.vision_models,
+            speech: speech.validate(vars)?,
+            summary_cache_ttl: std::time::Duration::from_secs(
+                gpt.summary_cache_ttl_hours
+                    .checked_mul(60 * 60)
+                    .ok_or_else(|| {
+                        ConfigError::invalid("gpt.summary_cache_ttl_hours", "is too large")
+                    })?,
+            ),
+        };
+
+        Ok(BotConfig {
+            mention_parameters,
+            history_parameters,
+            gpt_settings: Arc::new(gpt_settings),
+            owner_ids: admin.owner_ids,
+        })
+    }
+}
+
+fn validate_prices(
+    prices: HashMap<String, RawPrice>,
+) -> Result<HashMap<String, ModelPrice>, ConfigError> {
+    let mut validated = llm_usage::default_prices();
+    for (model, price) in prices {
+        for (field, value) in [("prompt", price.prompt), ("completion", price.completion)] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(ConfigError::invalid(
+                    format!("prices.{model}.{field}"),
+                    "must be a non-negative number of dollars",
+                ));
+            }
+        }
+        validated.insert(
+            model,
+            ModelPrice {
+                prompt: price.prompt,
+                completion: price.completion,
+            },
+        );
+    }
+    Ok(validated)
+}
+
+fn validate_personas(personas: Vec<RawPersona>) -> Result<Vec<Persona>, ConfigError> {
+    if personas.is_empty() {
+        return Err(ConfigError::invalid(
+            "personas",
+            "at least one persona is required",
+        ));
+    }
+    let mut validated: Vec<Persona> = Vec::with_capacity(personas.len());
+    for (index, raw) in personas.into_iter().enumerate() {
+        let key = |field: &str| format!("personas[{index}].{field}");
+        if !PersonaId::is_valid(&raw.id) {
+            return Err(ConfigError::invalid(
+                key("id"),
+                "must be a lowercase slug of letters, digits, `-` or `_`",
+            ));
+        }
+        let id = PersonaId::new(raw.id);
+        if validated.iter().any(|existing| existing.id == id) {
+            return Err(ConfigError::invalid(
+                key("id"),
+                format!("{id} is defined twice"),
+            ));
+        }
+        if raw.system_prompt.trim().is_empty() {
+            return Err(ConfigError::invalid(
+                key("system_prompt"),
+                "must not be empty",
+            ));
+        }
+        if raw
+            .model
+            .as_ref()
+            .is_some_and(|model| model.trim().is_empty())
+        {
+            return Err(ConfigError::invalid(key("model"), "must not be empty"));
+        }
+        if let Some(temperature) = raw.temperature {
+            if !(0.0..=MAX_TEMPERATURE).contains(&temperature) {
+                return Err(ConfigError::invalid(
+                    key("temperature"),
+                    format!("must be between 0 and {MAX_TEMPERATURE}"),
+                ));
+            }
+        }
+        validated.push(Persona {
+            name: raw.name.unwrap_or_else(|| id.to_string()),
+            id,
+            mention_regex: regex(&key("mention_regex"), &raw.mention_regex)?,
+            system_prompt: raw.system_prompt,
+            provider: raw.provider,
+            model: raw.model.map(Arc::from),
+            temperature: raw.temperature,
+            languages: raw.languages,
+            enabled_chats: raw.enabled_chats,
+        });
+    }
+    Ok(validated)
+}
+
+fn validate_providers(
+    providers: HashMap<String, RawProvider>,
+    vars: &HashMap<String, String>,
+) -> Result<HashMap<String, ProviderSettings>, ConfigError> {
+    providers
+        .into_iter()
+        .map(|(name, raw)| {
+            let key = |field: &str| format!("providers.{name}.{field}");
+            let base_url = raw.base_url.unwrap_or_else(|| {
+                match raw.kind {
+                    ProviderKind::OpenAi => DEFAULT_OPENAI_BASE_URL,
+                    ProviderKind::Anthropic => DEFAULT_ANTHROPIC_BASE_URL,
+                    ProviderKind::Ollama => DEFAULT_OLLAMA_BASE_URL,
+                }
+                .to_owned()
+            });
+            http_url(&key("base_url"), &base_url)?;
+            let api_key = match raw.api_key_env {
+                Some(var) => Some(env_api_key(&key("api_key_env"), &var, vars)?),
+                None => None,
+            };
+            if raw
+                .model
+                .as_ref()
+                .is_some_and(|model| model.trim().is_empty())
+            {
+                return Err(ConfigError::invalid(key("model"), "must not be empty"));
+            }
+            let settings = ProviderSettings {
+                kind: raw.kind,
+                base_url: Arc::from(base_url),
+                api_key,
+                model: raw.model.map(Arc::from),
+            };
+            Ok((name, settings))
+        })
+        .collect()
+}
+
+/// The API key in env var `var`, named by config key `key`.
+fn env_api_key(
+    key: &str,
+    var: &str,
+    vars: &HashMap<String, String>,
+) -> Result<Arc<str>, ConfigError> {
+    vars.get(var)
+        .map(|api_key| Arc::from(api_key.as_str()))
+        .ok_or_else(|| ConfigError::invalid(key, format!("{var} is not set")))
+}
+
+fn http_url(key: &str, value: &str) -> Result<(), ConfigError> {
+    match reqwest::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        Ok(url) => Err(ConfigError::invalid(
+            key,
+            format!("unsupported scheme {}", url.scheme()),
+        )),
+        Err(err) => Err(ConfigError::invalid(key, err)),
+    }
+}
+
+fn regex(key: &str, pattern: &str) -> Result<Regex, ConfigError> {
+    Regex::new(pattern).map_err(|err| ConfigError::invalid(key, err))
+}
+
+fn positive<T: Default + PartialOrd>(key: &str, value: T) -> Result<T, ConfigError> {
+    if value > T::default() {
+        Ok(value)
+    } else {
+        Err(ConfigError::invalid(key, "must be greater than zero"))
+    }
+}
+
+/// A century; billions of hours would take timestamps computed from the
+/// duration past the range chrono can represent.
+const MAX_HOURS: u32 = 100 * 366 * 24;
+
+fn positive_hours(key: &str, hours: u32) -> Result<Duration, ConfigError> {
+    Some(positive(key, hours)?)
+        .filter(|hours| *hours <= MAX_HOURS)
+        .and_then(|hours| Duration::try_hours(hours.into()))
+        .ok_or_else(|| ConfigError::invalid(key, "is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BotConfig, ConfigHandle};
+    use crate::error::ConfigError;
+    use crate::llm_provider::{ProviderKind, DEFAULT_ANTHROPIC_BASE_URL, DEFAULT_OLLAMA_BASE_URL};
+    use crate::speech::{SpeechKind, DEFAULT_TRANSCRIPTION_URL, DEFAULT_WHISPER_URL};
+    use chrono::Duration;
+
+    fn parse(source: &str, vars: &[(&str, &str)]) -> Result<BotConfig, ConfigError> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        BotConfig::parse("bot.toml", source, vars)
+    }
+
+    fn invalid_key(result: Result<BotConfig, ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            Err(other) => panic!("expected an invalid value error, got {other}"),
+            Ok(_) => panic!("expected an invalid value error"),
+        }
+    }
+
+    #[test]
+    fn empty_config_uses_builtin_defaults() {
+        let config = parse("", &[]).unwrap();
+        assert_eq!(
+            config.mention_parameters.req_time_diff,
+            Duration::minutes(15)
+        );
+        assert_eq!(&*config.gpt_settings.model, "gpt-4o");
+        assert_eq!(config.gpt_settings.personas.len(), 3);
+        assert_eq!(config.mention_parameters.stickers.len(), 5);
+    }
+
+    #[test]
+    fn file_values_and_env_overrides_are_applied() {
+        let source = r#"
+            [mentions]
+            cooldown_minutes = 30
+            rust_regex = "(?i)ржавчин"
+
+            [gpt]
+            model = "gpt-4o-mini"
+            retry_attempts = 5
+            breaker_cooldown_secs = 10
+            vision_models = ["llava"]
+            summary_cache_ttl_hours = 0
+
+            [quota]
+            user_burst = 0
+            user_per_minute = 0
+            chat_daily = 50
+
+            [prices."llama3"]
+            prompt = 0.0
+            completion = 0.0
+
+            [prices."gpt-4o-mini"]
+            prompt = 0.2
+            completion = 0.8
+
+            [[personas]]
+            id = "crab"
+            mention_regex = "(?i)краб"
+            system_prompt = "Ты краб."
+            model = "gpt-4o-mini"
+            temperature = 1.2
+            languages = ["be"]
+            enabled_chats = [-100]
+        "#;
+        let config = parse(
+            source,
+            &[
+                ("RUST_BOT__MENTIONS__COOLDOWN_MINUTES", "5"),
+                ("RUST_BOT__GPT__BASE_URL", "http://localhost:8080/v1/chat"),
+                ("UNRELATED", "ignored"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            config.mention_parameters.req_time_diff,
+            Duration::minutes(5)
+        );
+        assert!(config.mention_parameters.rust_regex.is_match("ржавчина"));
+        assert_eq!(&*config.gpt_settings.model, "gpt-4o-mini");
+        assert_eq!(
+            &*config.gpt_settings.openai_base_url,
+            "http://localhost:8080/v1/chat"
+        );
+        assert_eq!(config.gpt_settings.retry.max_attempts, 5);
+        assert!(
+            config.gpt_settings.summary_cache_ttl.is_zero(),
+            "cache switched off"
+        );
+        assert!(config.gpt_settings.sees_images("llava:13b"));
+        assert!(
+            !config.gpt_settings.sees_images("gpt-4o"),
+            "the list replaces the built-in one"
+        );
+        assert_eq!(
+            config.gpt_settings.quota.user_burst, 0,
+            "bucket switched off"
+        );
+        assert_eq!(config.gpt_settings.quota.chat_daily, 50);
+        let prices = &config.gpt_settings.prices;
+        assert_eq!(prices["gpt-4o-mini"].completion, 0.8, "overrides built-in");
+        assert_eq!(prices["llama3"].prompt, 0.0);
+        assert!(prices.contains_key("gpt-4o"), "built-ins stay");
+        assert_eq!(
+            config.gpt_settings.breaker.cooldown,
+            std::time::Duration::from_secs(10)
+        );
+        assert_eq!(config.gpt_settings.personas.len(), 1);
+        let crab = &config.gpt_settings.personas[0];
+        assert_eq!(crab.id.as_str(), "crab");
+        assert_eq!(crab.name, "crab");
+        assert_eq!(crab.model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(crab.temperature, Some(1.2));
+        assert_eq!(crab.languages, vec!["be"]);
+        assert!(crab.is_enabled_in(-100) && !crab.is_enabled_in(-200));
+    }
+
+    #[test]
+    fn env_overrides_keep_the_type_of_the_file_value() {
+        let source = "[gpt]\nmodel = \"gpt-4o\"\nretry_attempts = 2";
+        let vars = [
+            ("RUST_BOT__GPT__MODEL", "1"),
+            ("RUST_BOT__GPT__RETRY_ATTEMPTS", "4"),
+        ];
+        let config = parse(source, &vars).unwrap();
+        assert_eq!(&*config.gpt_settings.model, "1");
+        assert_eq!(config.gpt_settings.retry.max_attempts, 4);
+
+        let config = parse("", &[("RUST_BOT__GPT__MODEL", "\"1\"")]).unwrap();
+        assert_eq!(&*config.gpt_settings.model, "1");
+        assert_eq!(
+            invalid_key(parse("", &[("RUST_BOT__GPT__MODEL", "1")])),
+            "gpt.model"
+        );
+    }
+
+    #[test]
+    fn errors_point_to_the_offending_key() {
+        assert_eq!(
+            invalid_key(parse("[mentions]\nrust_regex = \"(unclosed\"", &[])),
+            "mentions.rust_regex"
+        );
+        assert_eq!(
+            invalid_key(parse("[gpt]\nmax_completion_tokens = \"many\"", &[])),
+            "gpt.max_completion_tokens"
+        );
+        assert_eq!(
+            invalid_key(parse("[gpt]\nmodle = \"gpt-4o\"", &[])),
+            "gpt.modle"
+        );
+        assert_eq!(
+            invalid_key(parse("", &[("RUST_BOT__GPT__BASE_URL", "not a url")])),
+            "gpt.base_url"
+        );
+        assert_eq!(
+            invalid_key(parse("", &[("RUST_BOT__HISTORY__TTL_HOURS", "0")])),
+            "history.ttl_hours"
+        );
+        assert_eq!(
+            invalid_key(parse("[gpt]\nretry_attempts = 0", &[])),
+            "gpt.retry_attempts"
+        );
+        assert_eq!(
+            invalid_key(parse("[quota]\nuser_per_minute = 0", &[])),
+            "quota.user_per_minute"
+        );
+        assert_eq!(
+            invalid_key(parse(
+                &format!("[gpt]\nsummary_cache_ttl_hours = {}", i64::MAX),
+                &[]
+            )),
+            "gpt.summary_cache_ttl_hours"
+        );
+        for key in [
+            "history.ttl_hours",
+            "history.default_summary_window_hours",
+            "gpt.context_ttl_hours",
+        ] {
+            let (section, name) = key.split_once('.').unwrap();
+            assert_eq!(
+                invalid_key(parse(&format!("[{section}]\n{name} = 4000000000"), &[])),
+                key
+            );
+            let var = format!("RUST_BOT__{}__{}", section, name).to_uppercase();
+            assert_eq!(invalid_key(parse("", &[(var.as_str(), "4000000000")])), key);
+        }
+        assert_eq!(
+            invalid_key(parse(
+                "[prices.\"gpt-4o\"]\nprompt = -1.0\ncompletion = 10.0",
+                &[]
+            )),
+            "prices.gpt-4o.prompt"
+        );
+        let personas = r#"
+            [[personas]]
+            id = "fedor"
+            mention_regex = "fedor"
+            system_prompt = "a"
+
+            [[personas]]
+            id = "felix"
+            mention_regex = "[felix"
+            system_prompt = "b"
+        "#;
+        assert_eq!(
+            invalid_key(parse(personas, &[])),
+            "personas[1].mention_regex"
+        );
+        let persona = |id: &str, extra: &str| {
+            format!("[[personas]]\nid = \"{id}\"\nmention_regex = \"f\"\nsystem_prompt = \"a\"\n{extra}")
+        };
+        assert_eq!(
+            invalid_key(parse(&persona("Fedor!", ""), &[])),
+            "personas[0].id"
+        );
+        assert_eq!(
+            invalid_key(parse(&persona("fedor", "temperature = 3.5"), &[])),
+            "personas[0].temperature"
+        );
+    }
+
+    #[test]
+    fn providers_are_resolved_with_keys_from_the_environment() {
+        let source = r#"
+            [gpt]
+            provider = "claude"
+
+            [gpt.chat_providers]
+            "-100" = "local"
+
+            [providers.claude]
+            kind = "anthropic"
+            api_key_env = "ANTHROPIC_API_KEY"
+            model = "claude-3-5-haiku-latest"
+
+            [providers.local]
+            kind = "ollama"
+        "#;
+        let config = parse(source, &[("ANTHROPIC_API_KEY", "secret")]).unwrap();
+        let settings = &config.gpt_settings;
+        assert_eq!(settings.provider_name_for(-100), "local");
+        assert_eq!(settings.provider_name_for(-200), "claude");
+        let claude = &settings.providers["claude"];
+        assert_eq!(claude.kind, ProviderKind::Anthropic);
+        assert_eq!(&*claude.base_url, DEFAULT_ANTHROPIC_BASE_URL);
+        assert_eq!(claude.api_key.as_deref(), Some("secret"));
+        assert_eq!(claude.model.as_deref(), Some("claude-3-5-haiku-latest"));
+        let local = &settings.providers["local"];
+        assert_eq!(&*local.base_url, DEFAULT_OLLAMA_BASE_URL);
+        assert_eq!(local.api_key, None);
+    }
+
+    #[test]
+    fn provider_references_must_exist() {
+        assert_eq!(
+            invalid_key(parse("[gpt]\nprovider = \"claude\"", &[])),
+            "gpt.provider"
+        );
+        assert_eq!(
+            invalid_key(parse("[gpt.chat_providers]\nchat = \"openai\"", &[])),
+            "gpt.chat_providers.chat"
+        );
+        assert_eq!(
+            invalid_key(parse(
+                "[[personas]]\nid = \"a\"\nmention_regex = \"a\"\nsystem_prompt = \"a\"\nprovider = \"x\"",
+                &[]
+            )),
+            "personas[0].provider"
+        );
+        assert_eq!(
+            invalid_key(parse(
+                "[providers.x]\nkind = \"anthropic\"\napi_key_env = \"MISSING_KEY\"",
+                &[]
+            )),
+            "providers.x.api_key_env"
+        );
+    }
+
+    #[test]
+    fn speech_backend_is_configured() {
+        let defaults = parse("", &[]).unwrap().gpt_settings.speech.clone();
+        assert_eq!(defaults.kind, SpeechKind::OpenAi);
+        assert_eq!(&*defaults.base_url, DEFAULT_TRANSCRIPTION_URL);
+        assert_eq!(defaults.api_key, None);
+
+        let source = r#"
+            [speech]
+            kind = "whisper"
+            api_key_env = "WHISPER_KEY"
+            language = "ru"
+            max_duration_secs = 60
+        "#;
+        let config = parse(source, &[("WHISPER_KEY", "secret")]).unwrap();
+        let speech = &config.gpt_settings.speech;
+        assert_eq!(speech.kind, SpeechKind::Whisper);
+        assert_eq!(&*speech.base_url, DEFAULT_WHISPER_URL);
+        assert_eq!(speech.api_key.as_deref(), Some("secret"));
+        assert_eq!(speech.language.as_deref(), Some("ru"));
+        assert_eq!(speech.max_duration, std::time::Duration::from_secs(60));
+
+        assert_eq!(
+            invalid_key(parse("[speech]\nbase_url = \"ftp://x\"", &[])),
+            "speech.base_url"
+        );
+        assert_eq!(
+            invalid_key(parse("[speech]\nmax_duration_secs = 0", &[])),
+            "speech.max_duration_secs"
+        );
+        assert_eq!(
+            invalid_key(parse("[speech]\napi_key_env = \"MISSING_KEY\"", &[])),
+            "speech.api_key_env"
+        );
+    }
+
+    #[test]
+    fn reload_swaps_config_and_keeps_old_one_on_error() {
+        let handle = ConfigHandle::new(parse("", &[]).unwrap());
+        let before = handle.current();
+
+        handle
+            .reload_with(|| parse("[gpt]\nmodel = \"gpt-4o-mini\"", &[]))
+            .unwrap();
+        assert_eq!(&*handle.current().gpt_settings.model, "gpt-4o-mini");
+        assert_eq!(
+            &*before.gpt_settings.model, "gpt-4o",
+            "earlier snapshots are unaffected"
+        );
+
+        let failed = handle.reload_with(|| parse("[gpt]\nmax_completion_tokens = 0", &[]));
+        assert!(failed.is_err());
+        assert_eq!(&*handle.current().gpt_settings.model, "gpt-4o-mini");
+    }
+
+    #[test]
+    fn example_config_is_valid() {
+        let config = parse(include_str!("../bot.example.toml"), &[]).unwrap();
+        assert_eq!(config.gpt_settings.personas.len(), 3);
+    }
+
+    #[test]
+    fn malformed_toml_is_a_parse_error() {
+        assert!(matches!(parse("[gpt", &[]), Err(ConfigError::Parse { .. })));
+    }
+}
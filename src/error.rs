@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
 /// Domain error for rust-bot.
@@ -20,9 +22,149 @@ pub enum AppError {
     #[error("http error: {0}")]
     Http(#[from] reqwest::Error),
 
+    #[error("fetch error: {0}")]
+    Fetch(#[from] FetchError),
+
     #[error("gpt error: {0}")]
     Gpt(String),
 
-    #[error("bad input: {0}")]
-    BadInput(String),
+    #[error("llm error: {0}")]
+    Llm(#[from] LlmError),
+
+    #[error("config error: {0}")]
+    Config(#[from] ConfigError),
+
+    #[error("blocking task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+/// Why an LLM provider did not answer. The class decides whether the call is
+/// retried, whether it counts against the provider's circuit breaker and what
+/// the chat is told.
+#[derive(Debug, Error)]
+pub enum LlmError {
+    #[error("rate limited by the provider")]
+    RateLimited { retry_after: Option<Duration> },
+
+    #[error("provider overloaded (status {0})")]
+    Overloaded(u16),
+
+    #[error("provider rejected the credentials (status {0})")]
+    Auth(u16),
+
+    #[error("provider rejected the request (status {status}): {message}")]
+    BadRequest { status: u16, message: String },
+
+    #[error("provider did not answer in time")]
+    Timeout,
+
+    #[error("provider unreachable: {0}")]
+    Transport(String),
+
+    #[error("unexpected provider response: {0}")]
+    InvalidResponse(String),
+
+    #[error("provider {0} is failing, not calling it for a while")]
+    CircuitOpen(String),
+}
+
+impl LlmError {
+    /// Classify a non-success HTTP status. Anthropic reports overload as 529.
+    pub fn from_status(status: u16, retry_after: Option<Duration>, body: &str) -> Self {
+        match status {
+            429 => Self::RateLimited { retry_after },
+            401 | 403 => Self::Auth(status),
+            408 => Self::Timeout,
+            500..=599 => Self::Overloaded(status),
+            _ => Self::BadRequest {
+                status,
+                message: body.chars().take(200).collect(),
+            },
+        }
+    }
+
+    /// Worth another attempt after a pause. A timeout has already used the
+    /// whole request budget, so it is not retried.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::RateLimited { .. } | Self::Overloaded(_) | Self::Transport(_)
+        )
+    }
+
+    /// Points at the provider rather than the request, and so trips the
+    /// circuit breaker when repeated.
+    pub fn is_provider_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::RateLimited { .. } | Self::Overloaded(_) | Self::Timeout | Self::Transport(_)
+        )
+    }
+}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout
+        } else if err.is_decode() {
+            Self::InvalidResponse(err.to_string())
+        } else if let Some(status) = err.status() {
+            Self::from_status(status.as_u16(), None, "")
+        } else {
+            Self::Transport(err.to_string())
+        }
+    }
+}
+
+/// Why a page a chat member posted was not fetched. Every variant but
+/// `Http` is the fetcher refusing before it reads the page.
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error("not a web URL: {0}")]
+    InvalidUrl(String),
+
+    #[error("{0} is not a public address")]
+    PrivateAddress(String),
+
+    #[error("more than {0} redirects")]
+    TooManyRedirects(usize),
+
+    #[error("page is larger than {0} bytes")]
+    TooLarge(u64),
+
+    #[error("unsupported content type {0:?}")]
+    UnsupportedContentType(String),
+
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+/// Failure to load the bot configuration. Value errors name the offending key
+/// (`gpt.model`, `personas[1].mention_regex`) so a bad deployment is quick to
+/// fix.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("can't read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("can't parse {path}: {source}")]
+    Parse {
+        path: String,
+        source: toml::de::Error,
+    },
+
+    #[error("invalid value for `{key}`: {reason}")]
+    Invalid { key: String, reason: String },
+}
+
+impl ConfigError {
+    pub(crate) fn invalid(key: impl Into<String>, reason: impl ToString) -> Self {
+        Self::Invalid {
+            key: key.into(),
+            reason: reason.to_string(),
+        }
+    }
 }
@@ -1,9 +1,114 @@
+use std::fmt;
+use std::str::FromStr;
+
 use chrono::{Duration, Utc};
 use log::{error, info};
 use teloxide::prelude::*;
 use teloxide::types::{ChatPermissions, ReplyParameters, User};
 
-pub async fn handle_gayness_mention(bot: Bot, msg: Message) {
+const DEFAULT_MUTE_TIERS: &[(u32, i64)] = &[
+    (0, 24 * 60),
+    (5, 10 * 60),
+    (9, 5 * 60),
+    (19, 3 * 60),
+    (29, 2 * 60),
+    (39, 60),
+];
+const DEFAULT_FALLBACK_MUTE_MINUTES: i64 = 30;
+/// Telegram treats longer restrictions as permanent.
+const MAX_MUTE_MINUTES: i64 = 366 * 24 * 60;
+
+/// Mute duration per reported percentage. Each tier applies up to and including
+/// its percentage; anything above the last tier gets the fallback. Written as
+/// `percent:minutes` pairs, e.g. `0:1440,5:600,39:60,*:30`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MuteTiers {
+    tiers: Vec<(u32, Duration)>,
+    fallback: Duration,
+}
+
+impl Default for MuteTiers {
+    fn default() -> Self {
+        Self {
+            tiers: DEFAULT_MUTE_TIERS
+                .iter()
+                .map(|&(percentage, minutes)| (percentage, Duration::minutes(minutes)))
+                .collect(),
+            fallback: Duration::minutes(DEFAULT_FALLBACK_MUTE_MINUTES),
+        }
+    }
+}
+
+impl MuteTiers {
+    fn duration_for(&self, percentage: u32) -> Duration {
+        self.tiers
+            .iter()
+            .find(|(max_percentage, _)| percentage <= *max_percentage)
+            .map_or(self.fallback, |(_, duration)| *duration)
+    }
+
+    fn longest(&self) -> Duration {
+        self.tiers
+            .iter()
+            .map(|(_, duration)| *duration)
+            .fold(self.fallback, Duration::max)
+    }
+}
+
+impl FromStr for MuteTiers {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut tiers: Vec<(u32, Duration)> = Vec::new();
+        let mut fallback = None;
+        for tier in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|tier| !tier.is_empty())
+        {
+            let (percentage, minutes) = tier
+                .split_once(':')
+                .ok_or_else(|| format!("tier '{tier}' must look like percent:minutes"))?;
+            let minutes: i64 = minutes
+                .trim()
+                .parse()
+                .ok()
+                .filter(|minutes| *minutes > 0)
+                .ok_or_else(|| format!("tier '{tier}' needs a positive number of minutes"))?;
+            let duration = Some(minutes)
+                .filter(|minutes| *minutes <= MAX_MUTE_MINUTES)
+                .and_then(Duration::try_minutes)
+                .ok_or_else(|| {
+                    format!("tier '{tier}' mute is longer than {MAX_MUTE_MINUTES} minutes")
+                })?;
+            if percentage.trim() == "*" {
+                fallback = Some(duration);
+                continue;
+            }
+            let percentage: u32 = percentage
+                .trim()
+                .parse()
+                .map_err(|_| format!("tier '{tier}' has an invalid percentage"))?;
+            if tiers.last().is_some_and(|(last, _)| *last >= percentage) {
+                return Err(format!("tier '{tier}' must come after a lower percentage"));
+            }
+            tiers.push((percentage, duration));
+        }
+        let fallback = fallback.ok_or("a '*:minutes' fallback tier is required")?;
+        Ok(Self { tiers, fallback })
+    }
+}
+
+impl fmt::Display for MuteTiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (percentage, duration) in &self.tiers {
+            write!(f, "{percentage}:{},", duration.num_minutes())?;
+        }
+        write!(f, "*:{}", self.fallback.num_minutes())
+    }
+}
+
+pub async fn handle_gayness_mention(bot: Bot, msg: Message, mute_tiers: &MuteTiers) {
     let chat_id = msg.chat.id;
     info!("gayness mention invocation: chat_id: {}", chat_id);
     if let Message {
@@ -11,12 +116,17 @@ pub async fn handle_gayness_mention(bot: Bot, msg: Message) {
         ..
     } = msg
     {
-        let mute_duration: Duration = calculate_mute_duration(msg.text());
-        bot.restrict_chat_member(chat_id, user_id, ChatPermissions::empty())
-            .until_date(Utc::now() + mute_duration)
-            .await
-            .map_err(|err| error!("Can't apply restriction: {:?}", err))
-            .ok();
+        let mute_duration: Duration = calculate_mute_duration(msg.text(), mute_tiers);
+        match Utc::now().checked_add_signed(mute_duration) {
+            Some(until) => {
+                bot.restrict_chat_member(chat_id, user_id, ChatPermissions::empty())
+                    .until_date(until)
+                    .await
+                    .map_err(|err| error!("Can't apply restriction: {:?}", err))
+                    .ok();
+            }
+            None => error!("Can't apply restriction: {mute_duration} is out of range"),
+        }
         let reply_msg = bot
             .send_message(
                 chat_id,
@@ -41,18 +151,13 @@ pub async fn handle_gayness_mention(bot: Bot, msg: Message) {
     }
 }
 
-fn calculate_mute_duration(message: Option<&str>) -> Duration {
+fn calculate_mute_duration(message: Option<&str>, mute_tiers: &MuteTiers) -> Duration {
     match message {
         Some(msg) => match parse_percentage(msg) {
-            Some(_x @ 0..=0) => Duration::hours(24),
-            Some(_x @ 1..=5) => Duration::hours(10),
-            Some(_x @ 6..=9) => Duration::hours(5),
-            Some(_x @ 10..=19) => Duration::hours(3),
-            Some(_x @ 20..=29) => Duration::hours(2),
-            Some(_x @ 30..=39) => Duration::hours(1),
-            _ => Duration::minutes(30),
+            Some(percentage) => mute_tiers.duration_for(percentage),
+            None => mute_tiers.fallback,
         },
-        None => Duration::hours(24),
+        None => mute_tiers.longest(),
     }
 }
 
@@ -66,7 +171,54 @@ fn parse_percentage(msg: &str) -> Option<u32> {
 
 #[cfg(test)]
 mod tests {
-    use crate::gayness_handler::parse_percentage;
+    use crate::gayness_handler::{calculate_mute_duration, parse_percentage, MuteTiers};
+    use chrono::Duration;
+
+    #[test]
+    fn default_mute_tiers_match_historical_durations() {
+        let tiers = MuteTiers::default();
+        let mute = |text| calculate_mute_duration(Some(text), &tiers);
+        assert_eq!(mute("I am 0% gay"), Duration::hours(24));
+        assert_eq!(mute("I am 3% gay"), Duration::hours(10));
+        assert_eq!(mute("I am 7% gay"), Duration::hours(5));
+        assert_eq!(mute("I am 15% gay"), Duration::hours(3));
+        assert_eq!(mute("I am 25% gay"), Duration::hours(2));
+        assert_eq!(mute("I am 35% gay"), Duration::hours(1));
+        assert_eq!(mute("I am 85% gay"), Duration::minutes(30));
+        assert_eq!(calculate_mute_duration(None, &tiers), Duration::hours(24));
+    }
+
+    #[test]
+    fn mute_tiers_round_trip_through_spec() {
+        let tiers = MuteTiers::default();
+        let spec = tiers.to_string();
+        assert_eq!(spec, "0:1440,5:600,9:300,19:180,29:120,39:60,*:30");
+        assert_eq!(spec.parse::<MuteTiers>(), Ok(tiers));
+
+        let custom: MuteTiers = "10:60, *:5".parse().unwrap();
+        assert_eq!(
+            calculate_mute_duration(Some("9% g"), &custom),
+            Duration::hours(1)
+        );
+        assert_eq!(
+            calculate_mute_duration(Some("50% g"), &custom),
+            Duration::minutes(5)
+        );
+    }
+
+    #[test]
+    fn invalid_mute_tiers_are_rejected() {
+        assert!("10:60".parse::<MuteTiers>().is_err(), "fallback required");
+        assert!("10:60,5:30,*:5".parse::<MuteTiers>().is_err(), "ascending");
+        assert!("ten:60,*:5".parse::<MuteTiers>().is_err());
+        assert!("10:0,*:5".parse::<MuteTiers>().is_err());
+        assert!("*:150000000000".parse::<MuteTiers>().is_err(), "too long");
+        assert!(
+            "10:527041,*:5".parse::<MuteTiers>().is_err(),
+            "over 366 days"
+        );
+        assert!("10:527040,*:5".parse::<MuteTiers>().is_ok());
+    }
 
     #[test]
     fn test_percentage_parsing() {
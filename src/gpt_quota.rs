@@ -0,0 +1,325 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use chrono::{NaiveDate, Utc};
+use log::{info, warn};
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, RedisResult, Script};
+use teloxide::types::Message;
+
+use crate::GptParameters;
+
+pub(crate) const USER_BURST: u32 = 5;
+pub(crate) const USER_PER_MINUTE: u32 = 2;
+pub(crate) const CHAT_BURST: u32 = 30;
+pub(crate) const CHAT_PER_MINUTE: u32 = 10;
+pub(crate) const USER_DAILY: u32 = 100;
+pub(crate) const CHAT_DAILY: u32 = 1000;
+/// Daily counters outlive their day a little, so `/quota` right after
+/// midnight UTC still finds yesterday's.
+const DAILY_KEY_TTL_SECS: u64 = 2 * 24 * 60 * 60;
+
+/// GPT call limits of a chat and of each user in it. A burst or daily limit
+/// of 0 switches that limit off.
+#[derive(Clone, Debug)]
+pub struct QuotaLimits {
+    /// Calls a user can make in a row before the per-minute rate applies.
+    pub user_burst: u32,
+    pub user_per_minute: u32,
+    pub chat_burst: u32,
+    pub chat_per_minute: u32,
+    /// Calls per UTC day.
+    pub user_daily: u32,
+    pub chat_daily: u32,
+}
+
+impl Default for QuotaLimits {
+    fn default() -> Self {
+        Self {
+            user_burst: USER_BURST,
+            user_per_minute: USER_PER_MINUTE,
+            chat_burst: CHAT_BURST,
+            chat_per_minute: CHAT_PER_MINUTE,
+            user_daily: USER_DAILY,
+            chat_daily: CHAT_DAILY,
+        }
+    }
+}
+
+/// Why a GPT call was not allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaRefusal {
+    /// The user asks faster than their bucket refills.
+    UserRate {
+        retry_after: Duration,
+    },
+    ChatRate {
+        retry_after: Duration,
+    },
+    UserDaily,
+    ChatDaily,
+}
+
+impl QuotaRefusal {
+    /// What `persona_name` says instead of answering.
+    pub fn reply(&self, persona_name: &str) -> String {
+        match self {
+            QuotaRefusal::UserRate { retry_after } | QuotaRefusal::ChatRate { retry_after } => {
+                format!(
+                    "{persona_name} не успевает за вами, дайте передохнуть ещё {} с.",
+                    retry_after.as_secs().max(1)
+                )
+            }
+            QuotaRefusal::UserDaily => {
+                format!("{persona_name} на сегодня с тобой наговорился. Приходи завтра.")
+            }
+            QuotaRefusal::ChatDaily => {
+                format!("{persona_name} на сегодня выговорился для всего чата. До завтра!")
+            }
+        }
+    }
+}
+
+/// Today's GPT calls of a chat, and of one user in it when asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub chat_today: u32,
+    pub user_today: Option<u32>,
+}
+
+/// Checks every limit and, only if all pass, takes a token from both buckets
+/// and counts the call for the day, so a refused call costs nothing.
+///
+/// KEYS: user bucket, chat bucket, user day counter, chat day counter.
+/// ARGV: user burst, user tokens per ms, chat burst, chat tokens per ms,
+/// user daily, chat daily, day counter ttl (s).
+/// Returns `{code, wait_ms}`: 0 allowed, 1/2 user/chat rate, 3/4 user/chat
+/// daily.
+const ACQUIRE_SCRIPT: &str = r"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local function daily_exhausted(key, limit)
+  return limit > 0 and (tonumber(redis.call('GET', key)) or 0) >= limit
+end
+
+local function tokens(key, burst, rate)
+  local state = redis.call('HMGET', key, 'tokens', 'at')
+  local stored = tonumber(state[1]) or burst
+  local at = tonumber(state[2]) or now
+  return math.min(burst, stored + math.max(0, now - at) * rate)
+end
+
+local user_burst, user_rate = tonumber(ARGV[1]), tonumber(ARGV[2])
+local chat_burst, chat_rate = tonumber(ARGV[3]), tonumber(ARGV[4])
+local user_daily, chat_daily = tonumber(ARGV[5]), tonumber(ARGV[6])
+
+if daily_exhausted(KEYS[3], user_daily) then return {3, 0} end
+if daily_exhausted(KEYS[4], chat_daily) then return {4, 0} end
+
+local user_tokens, chat_tokens
+if user_burst > 0 then
+  user_tokens = tokens(KEYS[1], user_burst, user_rate)
+  if user_tokens < 1 then return {1, math.ceil((1 - user_tokens) / user_rate)} end
+end
+if chat_burst > 0 then
+  chat_tokens = tokens(KEYS[2], chat_burst, chat_rate)
+  if chat_tokens < 1 then return {2, math.ceil((1 - chat_tokens) / chat_rate)} end
+end
+
+local function take(key, left, burst, rate)
+  redis.call('HSET', key, 'tokens', left - 1, 'at', now)
+  redis.call('PEXPIRE', key, math.ceil(burst / rate))
+end
+if user_tokens then take(KEYS[1], user_tokens, user_burst, user_rate) end
+if chat_tokens then take(KEYS[2], chat_tokens, chat_burst, chat_rate) end
+for i = 3, 4 do
+  if KEYS[i] ~= '' then
+    redis.call('INCR', KEYS[i])
+    redis.call('EXPIRE', KEYS[i], ARGV[7])
+  end
+end
+return {0, 0}
+";
+
+static ACQUIRE: LazyLock<Script> = LazyLock::new(|| Script::new(ACQUIRE_SCRIPT));
+
+fn user_bucket_key(chat_id: i64, user_id: u64) -> String {
+    format!("quota:bucket:chat:{chat_id}:user:{user_id}")
+}
+
+fn chat_bucket_key(chat_id: i64) -> String {
+    format!("quota:bucket:chat:{chat_id}")
+}
+
+fn user_daily_key(day: NaiveDate, chat_id: i64, user_id: u64) -> String {
+    format!("quota:{day}:chat:{chat_id}:user:{user_id}")
+}
+
+fn chat_daily_key(day: NaiveDate, chat_id: i64) -> String {
+    format!("quota:{day}:chat:{chat_id}")
+}
+
+/// Tokens per millisecond of a per-minute rate. The config rejects a rate of
+/// 0 for an enabled bucket; the floor keeps the script from dividing by it.
+fn per_ms(per_minute: u32) -> f64 {
+    f64::from(per_minute.max(1)) / 60_000.0
+}
+
+/// Whether the GPT call a message asks for still has to be counted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Charge {
+    Due,
+    /// Counted already, like the transcript of a recording, which paid when it
+    /// was transcribed.
+    Paid,
+}
+
+/// Count the GPT call `msg` asks for. Redis trouble lets the call through:
+/// a broken limiter should not silence the bot.
+pub async fn check_message(
+    gpt_parameters: &GptParameters,
+    msg: &Message,
+    charge: Charge,
+) -> Option<QuotaRefusal> {
+    if charge == Charge::Paid {
+        return None;
+    }
+    let mut redis_cm = gpt_parameters.redis_connection_manager.clone();
+    let user_id = msg.from.as_ref().map(|user| user.id.0);
+    match try_acquire(
+        &mut redis_cm,
+        &gpt_parameters.settings.quota,
+        msg.chat.id.0,
+        user_id,
+    )
+    .await
+    {
+        Ok(Ok(())) => None,
+        Ok(Err(refusal)) => {
+            info!(
+                "gpt call refused in chat {} for user {user_id:?}: {refusal:?}",
+                msg.chat.id
+            );
+            Some(refusal)
+        }
+        Err(err) => {
+            warn!("Can't check gpt quota: {err}");
+            None
+        }
+    }
+}
+
+/// Count one GPT call by `user_id` in `chat_id` against `limits`. Messages
+/// without a sender (channel posts) only count against the chat.
+pub async fn try_acquire(
+    connection_manager: &mut ConnectionManager,
+    limits: &QuotaLimits,
+    chat_id: i64,
+    user_id: Option<u64>,
+) -> RedisResult<Result<(), QuotaRefusal>> {
+    let today = Utc::now().date_naive();
+    // Without a user the user keys are neither read nor written: their
+    // limits are 0 and the script skips empty counter keys.
+    let (user_bucket, user_daily, user_burst, user_daily_limit) = match user_id {
+        Some(user_id) => (
+            user_bucket_key(chat_id, user_id),
+            user_daily_key(today, chat_id, user_id),
+            limits.user_burst,
+            limits.user_daily,
+        ),
+        None => (String::new(), String::new(), 0, 0),
+    };
+    let (code, wait_ms): (u8, u64) = ACQUIRE
+        .key(user_bucket)
+        .key(chat_bucket_key(chat_id))
+        .key(user_daily)
+        .key(chat_daily_key(today, chat_id))
+        .arg(user_burst)
+        .arg(per_ms(limits.user_per_minute))
+        .arg(limits.chat_burst)
+        .arg(per_ms(limits.chat_per_minute))
+        .arg(user_daily_limit)
+        .arg(limits.chat_daily)
+        .arg(DAILY_KEY_TTL_SECS)
+        .invoke_async(connection_manager)
+        .await?;
+    let retry_after = Duration::from_millis(wait_ms);
+    Ok(match code {
+        1 => Err(QuotaRefusal::UserRate { retry_after }),
+        2 => Err(QuotaRefusal::ChatRate { retry_after }),
+        3 => Err(QuotaRefusal::UserDaily),
+        4 => Err(QuotaRefusal::ChatDaily),
+        _ => Ok(()),
+    })
+}
+
+pub async fn usage(
+    connection_manager: &mut ConnectionManager,
+    chat_id: i64,
+    user_id: Option<u64>,
+) -> RedisResult<QuotaUsage> {
+    let today = Utc::now().date_naive();
+    let chat_today: Option<u32> = connection_manager
+        .get(chat_daily_key(today, chat_id))
+        .await?;
+    let user_today = match user_id {
+        Some(user_id) => {
+            let used: Option<u32> = connection_manager
+                .get(user_daily_key(today, chat_id, user_id))
+                .await?;
+            Some(used.unwrap_or(0))
+        }
+        None => None,
+    };
+    Ok(QuotaUsage {
+        chat_today: chat_today.unwrap_or(0),
+        user_today,
+    })
+}
+
+/// Give `user_id` in `chat_id`, or the whole chat, a fresh day and a full
+/// bucket. Resetting the chat leaves its users' own limits alone.
+pub async fn reset(
+    connection_manager: &mut ConnectionManager,
+    chat_id: i64,
+    user_id: Option<u64>,
+) -> RedisResult<()> {
+    let today = Utc::now().date_naive();
+    let keys = match user_id {
+        Some(user_id) => [
+            user_bucket_key(chat_id, user_id),
+            user_daily_key(today, chat_id, user_id),
+        ],
+        None => [chat_bucket_key(chat_id), chat_daily_key(today, chat_id)],
+    };
+    connection_manager.del(&keys).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::QuotaRefusal;
+
+    #[test]
+    fn refusals_are_spoken_by_the_persona() {
+        let rate = QuotaRefusal::UserRate {
+            retry_after: Duration::from_millis(1500),
+        };
+        assert_eq!(
+            rate.reply("Федор"),
+            "Федор не успевает за вами, дайте передохнуть ещё 1 с."
+        );
+        let soon = QuotaRefusal::ChatRate {
+            retry_after: Duration::from_millis(10),
+        };
+        assert!(soon.reply("Феррис").contains("ещё 1 с."));
+        assert!(QuotaRefusal::UserDaily
+            .reply("Феликс")
+            .starts_with("Феликс"));
+        assert!(QuotaRefusal::ChatDaily
+            .reply("Феликс")
+            .contains("всего чата"));
+    }
+}
@@ -1,28 +1,24 @@
-use log::{error, info};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
 use teloxide::types::ChatId;
 
+use crate::error::LlmError;
+use crate::llm_provider::{
+    CompletionRequest, CompletionStream, ProviderSettings, StreamChunk, TokenUsage,
+};
+use crate::llm_retry::{complete_with_retry, stream_with_retry};
+use crate::llm_usage::{Caller, UsageBooking};
+use crate::persona::Persona;
+use crate::vision::Image;
 use crate::{AppError, GptParameters};
 
-const GPT_REQUEST_TIMEOUT: Duration = Duration::from_secs(90);
-
-#[derive(Debug, Deserialize, Serialize)]
-struct ChatRequest<'a> {
-    messages: Vec<ChatMessage>,
-    model: &'a str,
-    max_tokens: i32,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct ChatResponse {
-    pub choices: Vec<Choice>,
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct Choice {
-    pub message: ChatMessage,
-}
+/// Said when the LLM can't answer and no more specific reason applies.
+pub const GPT_BUSY_REPLY: &str = "Братан, давай папазжей, занят сейчас.";
+/// Added to the question when its picture goes to a model that can't see.
+const UNSEEN_IMAGE_NOTE: &str = "\n\n(К сообщению приложена картинка, но ты её не видишь.)";
+const IMAGE_DESCRIPTION_PROMPT: &str = "Опиши эту картинку в двух-трёх предложениях: что на ней \
+    изображено и какой на ней текст, если он есть. Только описание, без оценок.";
 
 #[derive(Debug, Deserialize, Serialize, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -38,55 +34,361 @@ pub struct ChatMessage {
     pub content: String,
 }
 
+/// Per-request overrides of the configured provider, model and the API's
+/// default temperature, the persona the usage is booked to and a picture
+/// attached to the question.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CompletionOptions<'a> {
+    pub persona: Option<&'a str>,
+    pub provider: Option<&'a str>,
+    pub model: Option<&'a str>,
+    pub temperature: Option<f32>,
+    pub image: Option<&'a Image>,
+}
+
+impl<'a> From<&'a Persona> for CompletionOptions<'a> {
+    fn from(persona: &'a Persona) -> Self {
+        Self {
+            persona: Some(persona.id.as_str()),
+            provider: persona.provider.as_deref(),
+            model: persona.model.as_deref(),
+            temperature: persona.temperature,
+            image: None,
+        }
+    }
+}
+
 pub async fn chat_gpt_call(
     params: &GptParameters,
-    chat_id: ChatId,
+    caller: Caller,
     messages: Vec<ChatMessage>,
 ) -> ChatMessage {
-    let fallback = || ChatMessage {
-        role: ChatMessageRole::Assistant,
-        content: "Братан, давай папазжей, занят сейчас.".to_owned(),
-    };
-    match gpt_call(params, chat_id, messages).await {
-        Ok(choices) => choices
-            .into_iter()
-            .next()
-            .map_or_else(fallback, |choice| choice.message),
+    chat_gpt_call_with(params, caller, messages, CompletionOptions::default()).await
+}
+
+pub async fn chat_gpt_call_with(
+    params: &GptParameters,
+    caller: Caller,
+    messages: Vec<ChatMessage>,
+    options: CompletionOptions<'_>,
+) -> ChatMessage {
+    match gpt_call(params, caller, messages, options).await {
+        Ok(message) => message,
         Err(err) => {
             error!("Can't execute chat_gpt_call: {}", err);
-            fallback()
+            fallback_message(&err)
         }
     }
 }
 
-async fn gpt_call(
+/// Like [`chat_gpt_call`], but a failure is the caller's to handle.
+pub async fn try_chat_gpt_call(
     params: &GptParameters,
-    chat_id: ChatId,
+    caller: Caller,
     messages: Vec<ChatMessage>,
-) -> Result<Vec<Choice>, AppError> {
+) -> Result<ChatMessage, AppError> {
+    gpt_call(params, caller, messages, CompletionOptions::default()).await
+}
+
+pub(crate) fn fallback_message(err: &AppError) -> ChatMessage {
+    let content = match err {
+        AppError::Llm(err) => fallback_reply(err),
+        _ => GPT_BUSY_REPLY,
+    };
+    ChatMessage {
+        role: ChatMessageRole::Assistant,
+        content: content.to_owned(),
+    }
+}
+
+/// What the chat is told instead of an answer.
+pub fn fallback_reply(err: &LlmError) -> &'static str {
+    match err {
+        LlmError::RateLimited { .. } => "Слишком много вопросов, дай передохнуть минутку.",
+        LlmError::Timeout => "Что-то я задумался и не успел ответить. Спроси ещё раз.",
+        LlmError::Auth(_) => "Не пускают меня к нейросети. Позовите админа бота.",
+        LlmError::BadRequest { .. } => {
+            "Не смог переварить этот вопрос, попробуй сформулировать иначе."
+        }
+        LlmError::Overloaded(_)
+        | LlmError::Transport(_)
+        | LlmError::InvalidResponse(_)
+        | LlmError::CircuitOpen(_) => GPT_BUSY_REPLY,
+    }
+}
+
+async fn gpt_call(
+    params: &GptParameters,
+    caller: Caller,
+    mut messages: Vec<ChatMessage>,
+    options: CompletionOptions<'_>,
+) -> Result<ChatMessage, AppError> {
+    let chat_id = caller.chat_id;
     info!(
         "gpt call invocation from chat_id: {} with context: {:#?}",
         chat_id, messages
     );
-    let chat_request = ChatRequest {
-        messages,
-        model: "gpt-4o",
-        max_tokens: 1000,
+    let target = Target::resolve(params, chat_id, options)?;
+    let image = target.image(params, &mut messages, options);
+    let messages = params.prompt_builder_for(&target.model).fit(messages);
+    let provider = target
+        .settings
+        .build(params.http_client.clone(), params.settings.request_timeout);
+    let completion = complete_with_retry(
+        provider.as_ref(),
+        target.name,
+        target.request(params, &messages, options, image),
+        &params.settings.retry,
+        &params.circuit_breakers,
+        &params.settings.breaker,
+    )
+    .await?;
+    info!("gpt call invocation for chat_id {} completed", chat_id);
+    target
+        .booking(params, caller, options, &messages)
+        .book(completion.usage, &completion.message.content)
+        .await;
+    Ok(completion.message)
+}
+
+/// A short description of `image` to keep in the conversation context in its
+/// place. `None` when the model can't see pictures or does not answer.
+pub async fn describe_image(
+    params: &GptParameters,
+    caller: Caller,
+    image: &Image,
+    options: CompletionOptions<'_>,
+) -> Option<String> {
+    let target = Target::resolve(params, caller.chat_id, options).ok()?;
+    if !params.settings.sees_images(&target.model) {
+        return None;
+    }
+    let messages = Vec::from([ChatMessage {
+        role: ChatMessageRole::User,
+        content: IMAGE_DESCRIPTION_PROMPT.to_owned(),
+    }]);
+    let options = CompletionOptions {
+        image: Some(image),
+        temperature: None,
+        ..options
     };
-    let response = params
-        .http_client
-        .post(params.openai_base_url.as_ref())
-        .header("Content-Type", "application/json")
-        .header(
-            "Authorization",
-            format!("Bearer {}", params.chat_gpt_api_token.as_ref()),
+    gpt_call(params, caller, messages, options)
+        .await
+        .inspect_err(|err| warn!("Can't describe image: {err}"))
+        .ok()
+        .map(|message| message.content.trim().to_owned())
+        .filter(|description| !description.is_empty())
+}
+
+/// Text deltas of a streamed GPT answer.
+pub type TextStream = BoxStream<'static, Result<String, LlmError>>;
+
+/// A GPT answer, either whole or still arriving.
+pub enum GptReply {
+    Complete(ChatMessage),
+    Streaming(TextStream),
+}
+
+/// Like [`chat_gpt_call_with`], but streams the answer when streaming is
+/// enabled and the provider supports it.
+pub async fn chat_gpt_reply(
+    params: &GptParameters,
+    caller: Caller,
+    messages: Vec<ChatMessage>,
+    options: CompletionOptions<'_>,
+) -> GptReply {
+    if !params.settings.streaming {
+        return GptReply::Complete(chat_gpt_call_with(params, caller, messages, options).await);
+    }
+    match gpt_stream(params, caller, messages.clone(), options).await {
+        Ok(Some(stream)) => GptReply::Streaming(stream),
+        Ok(None) => GptReply::Complete(chat_gpt_call_with(params, caller, messages, options).await),
+        Err(err) => {
+            error!("Can't start chat_gpt stream: {}", err);
+            GptReply::Complete(fallback_message(&err))
+        }
+    }
+}
+
+async fn gpt_stream(
+    params: &GptParameters,
+    caller: Caller,
+    mut messages: Vec<ChatMessage>,
+    options: CompletionOptions<'_>,
+) -> Result<Option<TextStream>, AppError> {
+    let chat_id = caller.chat_id;
+    info!(
+        "gpt stream invocation from chat_id: {} with context: {:#?}",
+        chat_id, messages
+    );
+    let target = Target::resolve(params, chat_id, options)?;
+    let image = target.image(params, &mut messages, options);
+    let messages = params.prompt_builder_for(&target.model).fit(messages);
+    let provider = target
+        .settings
+        .build(params.http_client.clone(), params.settings.request_timeout);
+    let stream = stream_with_retry(
+        provider.as_ref(),
+        target.name,
+        target.request(params, &messages, options, image),
+        &params.settings.retry,
+        &params.circuit_breakers,
+        &params.settings.breaker,
+    )
+    .await?;
+    let booking = target.booking(params, caller, options, &messages);
+    Ok(stream.map(|stream| booked(stream, booking)))
+}
+
+/// The text of `chunks`, booking the usage of the completion once the stream
+/// ends or breaks off.
+fn booked(chunks: CompletionStream, booking: UsageBooking) -> TextStream {
+    struct State {
+        chunks: CompletionStream,
+        booking: Option<UsageBooking>,
+        usage: Option<TokenUsage>,
+        text: String,
+    }
+
+    impl State {
+        async fn book(&mut self) {
+            if let Some(booking) = self.booking.take() {
+                booking.book(self.usage, &self.text).await;
+            }
+        }
+    }
+
+    let state = State {
+        chunks,
+        booking: Some(booking),
+        usage: None,
+        text: String::new(),
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            match state.chunks.next().await {
+                Some(Ok(StreamChunk::Text(text))) => {
+                    state.text.push_str(&text);
+                    return Some((Ok(text), state));
+                }
+                Some(Ok(StreamChunk::Usage(usage))) => {
+                    state.usage = Some(state.usage.map_or(usage, |counted| counted + usage));
+                }
+                Some(Err(err)) => {
+                    state.book().await;
+                    return Some((Err(err), state));
+                }
+                None => {
+                    state.book().await;
+                    return None;
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// The provider and model answering a call.
+struct Target<'a> {
+    name: &'a str,
+    settings: ProviderSettings,
+    model: String,
+}
+
+impl<'a> Target<'a> {
+    fn resolve(
+        params: &'a GptParameters,
+        chat_id: ChatId,
+        options: CompletionOptions<'a>,
+    ) -> Result<Self, AppError> {
+        // A persona stored in Postgres may name a provider the config no
+        // longer has; it then gets the chat's provider like any other persona.
+        let persona_provider =
+            options
+                .provider
+                .and_then(|name| match params.provider_settings(name) {
+                    Some(settings) => Some((name, settings)),
+                    None => {
+                        warn!(
+                            "persona provider {name} is not configured, using the chat's provider"
+                        );
+                        None
+                    }
+                });
+        let chat_provider = params.settings.provider_name_for(chat_id.0);
+        let (name, settings) = persona_provider
+            .or_else(|| {
+                params
+                    .provider_settings(chat_provider)
+                    .map(|settings| (chat_provider, settings))
+            })
+            .ok_or_else(|| {
+                AppError::Gpt(format!("llm provider {chat_provider} is not configured"))
+            })?;
+        let model = options
+            .model
+            .or(settings.model.as_deref())
+            .unwrap_or(&params.settings.model)
+            .to_owned();
+        Ok(Self {
+            name,
+            settings,
+            model,
+        })
+    }
+
+    /// The picture of `options` if the model can see it. A model that can't
+    /// is told in the last user message that there is one.
+    fn image<'i>(
+        &self,
+        params: &GptParameters,
+        messages: &mut [ChatMessage],
+        options: CompletionOptions<'i>,
+    ) -> Option<&'i Image> {
+        let image = options.image?;
+        if params.settings.sees_images(&self.model) {
+            return Some(image);
+        }
+        if let Some(question) = messages
+            .iter_mut()
+            .rev()
+            .find(|message| matches!(message.role, ChatMessageRole::User))
+        {
+            question.content.push_str(UNSEEN_IMAGE_NOTE);
+        }
+        None
+    }
+
+    fn request<'r>(
+        &'r self,
+        params: &GptParameters,
+        messages: &'r [ChatMessage],
+        options: CompletionOptions<'_>,
+        image: Option<&'r Image>,
+    ) -> CompletionRequest<'r> {
+        CompletionRequest {
+            messages,
+            model: &self.model,
+            max_tokens: params.settings.max_completion_tokens,
+            temperature: options.temperature,
+            image,
+        }
+    }
+
+    fn booking(
+        &self,
+        params: &GptParameters,
+        caller: Caller,
+        options: CompletionOptions<'_>,
+        prompt: &[ChatMessage],
+    ) -> UsageBooking {
+        UsageBooking::new(
+            params,
+            caller,
+            options.persona,
+            self.name,
+            &self.model,
+            prompt,
         )
-        .json(&chat_request)
-        .timeout(GPT_REQUEST_TIMEOUT)
-        .send()
-        .await?
-        .json::<ChatResponse>()
-        .await?;
-    info!("gpt call invocation for chat_id {} completed", chat_id);
-    Ok(response.choices)
+    }
 }
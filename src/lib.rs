@@ -5,19 +5,46 @@
 #![deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
 #![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
 
+pub mod article_extractor;
 pub mod bf_mention_handler;
 pub mod boot;
+pub mod canonical_url;
+pub mod chat_cache;
 pub mod chat_gpt_handler;
+pub mod chat_history;
 pub mod chat_repository;
+pub mod chat_settings_repository;
+pub mod command_handler;
+pub mod config;
 pub mod error;
 pub mod gayness_handler;
+pub mod gpt_quota;
 pub mod gpt_service;
+pub mod llm_provider;
+pub mod llm_retry;
+pub mod llm_usage;
 pub mod mention_repository;
+pub mod message_chunker;
+pub mod persona;
+pub mod persona_repository;
+pub mod prompt_builder;
 pub mod rust_mention_handler;
+pub mod safe_fetcher;
+pub mod speech;
+pub mod telegram_file;
+pub mod telegram_html;
+pub mod typing_indicator;
+pub mod url_summary_cache;
 pub mod url_summary_handler;
+pub mod usage_repository;
+pub mod vision;
+pub mod voice_handler;
 
 pub use boot::{
-    build_handler, message_has_url, run, AppDeps, GptParameters, MentionParameters,
+    build_handler, message_has_url, run, AppDeps, ContextLimits, GptParameters, GptSettings,
+    HistoryParameters, MentionParameters, DEFAULT_GPT_MODEL, DEFAULT_MAX_COMPLETION_TOKENS,
     DEFAULT_OPENAI_BASE_URL,
 };
-pub use error::AppError;
+pub use config::{BotConfig, ConfigHandle};
+pub use error::{AppError, ConfigError, FetchError, LlmError};
+pub use persona::{Persona, PersonaId};
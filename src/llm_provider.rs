@@ -0,0 +1,758 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use eventsource_stream::{Event, EventStreamError, Eventsource};
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::LlmError;
+use crate::gpt_service::ChatMessageRole::{Assistant, System, User};
+use crate::gpt_service::{ChatMessage, ChatMessageRole};
+use crate::vision::Image;
+
+/// Name of the provider built from `gpt.base_url` and `CHAT_GPT_API_TOKEN`
+/// when the config does not define one under that name.
+pub const DEFAULT_PROVIDER: &str = "openai";
+pub const DEFAULT_ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1/messages";
+pub const DEFAULT_OLLAMA_BASE_URL: &str = "http://localhost:11434/api/chat";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// One chat completion, independent of the wire format.
+#[derive(Debug, Clone, Copy)]
+pub struct CompletionRequest<'a> {
+    pub messages: &'a [ChatMessage],
+    pub model: &'a str,
+    pub max_tokens: usize,
+    pub temperature: Option<f32>,
+    /// A picture sent along with the last user message.
+    pub image: Option<&'a Image>,
+}
+
+impl<'a> CompletionRequest<'a> {
+    /// The messages paired with the picture each one carries.
+    fn messages_with_image(&self) -> impl Iterator<Item = (&'a ChatMessage, Option<&'a Image>)> {
+        let image_at = self.image.and(
+            self.messages
+                .iter()
+                .rposition(|message| matches!(message.role, User)),
+        );
+        let image = self.image;
+        self.messages
+            .iter()
+            .enumerate()
+            .map(move |(index, message)| (message, image.filter(|_| image_at == Some(index))))
+    }
+}
+
+/// Tokens a completion was billed for, as reported by the provider.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl std::ops::Add for TokenUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            prompt_tokens: self.prompt_tokens + other.prompt_tokens,
+            completion_tokens: self.completion_tokens + other.completion_tokens,
+        }
+    }
+}
+
+/// A whole answer and, when the provider reports it, what it cost.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub message: ChatMessage,
+    pub usage: Option<TokenUsage>,
+}
+
+/// A piece of a streamed completion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamChunk {
+    Text(String),
+    /// Token counts, usually at the end. Several of them add up.
+    Usage(TokenUsage),
+}
+
+/// A completion as the provider produces it.
+pub type CompletionStream = BoxStream<'static, Result<StreamChunk, LlmError>>;
+
+/// A chat completion backend.
+#[async_trait]
+pub trait LlmProvider: Send + Sync + Debug {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, LlmError>;
+
+    /// Start a streamed completion; `None` when the backend only answers in
+    /// one piece. Failures to start are reported here, later ones in the
+    /// stream.
+    async fn stream(
+        &self,
+        _request: CompletionRequest<'_>,
+    ) -> Result<Option<CompletionStream>, LlmError> {
+        Ok(None)
+    }
+}
+
+/// Wire format spoken by a provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// OpenAI chat completions and compatible servers (llama.cpp, vLLM, ...).
+    OpenAi,
+    /// Anthropic Messages API.
+    Anthropic,
+    /// Ollama's native `/api/chat`.
+    Ollama,
+}
+
+/// A configured provider: where it lives and how to authenticate.
+#[derive(Debug, Clone)]
+pub struct ProviderSettings {
+    pub kind: ProviderKind,
+    pub base_url: Arc<str>,
+    /// Sent with every request when set; local servers usually need none.
+    pub api_key: Option<Arc<str>>,
+    /// Model used when the persona does not name one; `gpt.model` otherwise.
+    pub model: Option<Arc<str>>,
+}
+
+impl ProviderSettings {
+    pub fn build(&self, http_client: reqwest::Client, timeout: Duration) -> Box<dyn LlmProvider> {
+        let endpoint = Endpoint {
+            http_client,
+            base_url: Arc::clone(&self.base_url),
+            api_key: self.api_key.clone(),
+            timeout,
+        };
+        match self.kind {
+            ProviderKind::OpenAi => Box::new(OpenAiProvider(endpoint)),
+            ProviderKind::Anthropic => Box::new(AnthropicProvider(endpoint)),
+            ProviderKind::Ollama => Box::new(OllamaProvider(endpoint)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Endpoint {
+    http_client: reqwest::Client,
+    base_url: Arc<str>,
+    api_key: Option<Arc<str>>,
+    timeout: Duration,
+}
+
+impl Endpoint {
+    fn post(&self) -> reqwest::RequestBuilder {
+        self.http_client
+            .post(self.base_url.as_ref())
+            .header("Content-Type", "application/json")
+            .timeout(self.timeout)
+    }
+
+    /// Send `body` and decode the reply, classifying any failure.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+        body: &impl Serialize,
+    ) -> Result<T, LlmError> {
+        Ok(self.send_raw(request, body).await?.json::<T>().await?)
+    }
+
+    /// Send `body` and turn the server-sent events of the reply into stream
+    /// chunks with `parse`.
+    async fn send_streaming(
+        &self,
+        request: reqwest::RequestBuilder,
+        body: &impl Serialize,
+        parse: fn(&Event) -> SseStep,
+    ) -> Result<CompletionStream, LlmError> {
+        let events = self
+            .send_raw(request, body)
+            .await?
+            .bytes_stream()
+            .eventsource();
+        Ok(sse_deltas(events, parse))
+    }
+
+    async fn send_raw(
+        &self,
+        request: reqwest::RequestBuilder,
+        body: &impl Serialize,
+    ) -> Result<reqwest::Response, LlmError> {
+        let response = request.json(body).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = retry_after(response.headers());
+            let body = response.text().await.unwrap_or_default();
+            return Err(LlmError::from_status(status.as_u16(), retry_after, &body));
+        }
+        Ok(response)
+    }
+}
+
+/// What one server-sent event means for the completion.
+enum SseStep {
+    Chunk(StreamChunk),
+    Skip,
+    Done,
+    Fail(LlmError),
+}
+
+fn sse_deltas<S>(events: S, parse: fn(&Event) -> SseStep) -> CompletionStream
+where
+    S: Stream<Item = Result<Event, EventStreamError<reqwest::Error>>> + Send + Unpin + 'static,
+{
+    stream::unfold(Some(events), move |events| async move {
+        let mut events = events?;
+        loop {
+            let step = match events.next().await? {
+                Ok(event) => parse(&event),
+                Err(EventStreamError::Transport(err)) => SseStep::Fail(err.into()),
+                Err(err) => SseStep::Fail(LlmError::InvalidResponse(err.to_string())),
+            };
+            match step {
+                SseStep::Chunk(chunk) => return Some((Ok(chunk), Some(events))),
+                SseStep::Skip => continue,
+                SseStep::Done => return None,
+                SseStep::Fail(err) => return Some((Err(err), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+/// `Retry-After` as either delay seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[derive(Debug)]
+pub struct OpenAiProvider(Endpoint);
+
+#[derive(Serialize)]
+struct OpenAiRequest<'a> {
+    messages: Vec<OpenAiMessage<'a>>,
+    model: &'a str,
+    max_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<OpenAiStreamOptions>,
+}
+
+/// Asks for a last chunk carrying the usage of the whole stream.
+#[derive(Serialize)]
+struct OpenAiStreamOptions {
+    include_usage: bool,
+}
+
+/// Plain text, or text and a picture as content parts.
+#[derive(Serialize)]
+struct OpenAiMessage<'a> {
+    role: ChatMessageRole,
+    content: OpenAiContent<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum OpenAiContent<'a> {
+    Text(&'a str),
+    Parts(Vec<OpenAiPart<'a>>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAiPart<'a> {
+    Text { text: &'a str },
+    ImageUrl { image_url: OpenAiImageUrl },
+}
+
+#[derive(Serialize)]
+struct OpenAiImageUrl {
+    url: String,
+}
+
+impl<'a> OpenAiRequest<'a> {
+    fn new(request: CompletionRequest<'a>, stream: bool) -> Self {
+        let messages = request
+            .messages_with_image()
+            .map(|(message, image)| OpenAiMessage {
+                role: message.role,
+                content: match image {
+                    Some(image) => OpenAiContent::Parts(Vec::from([
+                        OpenAiPart::Text {
+                            text: &message.content,
+                        },
+                        OpenAiPart::ImageUrl {
+                            image_url: OpenAiImageUrl {
+                                url: image.data_url(),
+                            },
+                        },
+                    ])),
+                    None => OpenAiContent::Text(&message.content),
+                },
+            })
+            .collect();
+        Self {
+            messages,
+            model: request.model,
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            stream,
+            stream_options: stream.then_some(OpenAiStreamOptions {
+                include_usage: true,
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    choices: Vec<OpenAiChoice>,
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChunk {
+    #[serde(default)]
+    choices: Vec<OpenAiChunkChoice>,
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChunkChoice {
+    #[serde(default)]
+    delta: OpenAiDelta,
+}
+
+#[derive(Deserialize, Default)]
+struct OpenAiDelta {
+    content: Option<String>,
+}
+
+impl OpenAiProvider {
+    fn request(&self) -> reqwest::RequestBuilder {
+        let http_request = self.0.post();
+        match &self.0.api_key {
+            Some(api_key) => http_request.bearer_auth(api_key),
+            None => http_request,
+        }
+    }
+
+    fn parse_event(event: &Event) -> SseStep {
+        if event.data.trim() == "[DONE]" {
+            return SseStep::Done;
+        }
+        match serde_json::from_str::<OpenAiChunk>(&event.data) {
+            Ok(chunk) => {
+                let text = chunk
+                    .choices
+                    .into_iter()
+                    .next()
+                    .and_then(|choice| choice.delta.content)
+                    .filter(|content| !content.is_empty());
+                match (text, chunk.usage) {
+                    (Some(text), _) => SseStep::Chunk(StreamChunk::Text(text)),
+                    (None, Some(usage)) => SseStep::Chunk(StreamChunk::Usage(usage)),
+                    (None, None) => SseStep::Skip,
+                }
+            }
+            Err(err) => SseStep::Fail(LlmError::InvalidResponse(err.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, LlmError> {
+        let body = OpenAiRequest::new(request, false);
+        let response: OpenAiResponse = self.0.send(self.request(), &body).await?;
+        let message = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .ok_or_else(|| LlmError::InvalidResponse("completion has no choices".to_owned()))?;
+        Ok(Completion {
+            message,
+            usage: response.usage,
+        })
+    }
+
+    async fn stream(
+        &self,
+        request: CompletionRequest<'_>,
+    ) -> Result<Option<CompletionStream>, LlmError> {
+        let body = OpenAiRequest::new(request, true);
+        let stream = self
+            .0
+            .send_streaming(self.request(), &body, Self::parse_event)
+            .await?;
+        Ok(Some(stream))
+    }
+}
+
+#[derive(Debug)]
+pub struct AnthropicProvider(Endpoint);
+
+#[derive(Serialize)]
+struct AnthropicRequest<'a> {
+    model: &'a str,
+    max_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+/// Plain text, or the picture followed by the text as content blocks.
+#[derive(Serialize)]
+struct AnthropicMessage<'a> {
+    role: ChatMessageRole,
+    content: AnthropicMessageContent<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum AnthropicMessageContent<'a> {
+    Text(&'a str),
+    Blocks(Vec<AnthropicBlock<'a>>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicBlock<'a> {
+    Image { source: AnthropicImageSource<'a> },
+    Text { text: &'a str },
+}
+
+#[derive(Serialize)]
+struct AnthropicImageSource<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    media_type: &'a str,
+    data: String,
+}
+
+impl<'a> AnthropicRequest<'a> {
+    fn new(request: CompletionRequest<'a>, stream: bool) -> Self {
+        // The Messages API takes the system prompt as a separate field.
+        let (system, messages): (Vec<_>, Vec<_>) = request
+            .messages_with_image()
+            .partition(|(message, _)| matches!(message.role, System));
+        let system = (!system.is_empty()).then(|| {
+            system
+                .iter()
+                .map(|(message, _)| message.content.as_str())
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        });
+        let messages = messages
+            .into_iter()
+            .map(|(message, image)| AnthropicMessage {
+                role: message.role,
+                content: match image {
+                    Some(image) => AnthropicMessageContent::Blocks(Vec::from([
+                        AnthropicBlock::Image {
+                            source: AnthropicImageSource {
+                                kind: "base64",
+                                media_type: &image.media_type,
+                                data: image.base64(),
+                            },
+                        },
+                        AnthropicBlock::Text {
+                            text: &message.content,
+                        },
+                    ])),
+                    None => AnthropicMessageContent::Text(&message.content),
+                },
+            })
+            .collect();
+        Self {
+            model: request.model,
+            max_tokens: request.max_tokens,
+            system,
+            messages,
+            temperature: request.temperature,
+            stream,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicContent>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize, Default)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+impl From<AnthropicUsage> for TokenUsage {
+    fn from(usage: AnthropicUsage) -> Self {
+        Self {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AnthropicContent {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+/// The events of a streamed message that matter here; `ping` and the block
+/// boundaries are skipped. `message_start` carries the input tokens,
+/// `message_delta` the output tokens.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicEvent {
+    MessageStart {
+        message: AnthropicStartedMessage,
+    },
+    ContentBlockDelta {
+        delta: AnthropicDelta,
+    },
+    MessageDelta {
+        #[serde(default)]
+        usage: AnthropicUsage,
+    },
+    MessageStop,
+    Error {
+        error: AnthropicErrorBody,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct AnthropicStartedMessage {
+    #[serde(default)]
+    usage: AnthropicUsage,
+}
+
+#[derive(Deserialize)]
+struct AnthropicDelta {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct AnthropicErrorBody {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    message: String,
+}
+
+impl AnthropicProvider {
+    fn request(&self) -> reqwest::RequestBuilder {
+        let http_request = self.0.post().header("anthropic-version", ANTHROPIC_VERSION);
+        match &self.0.api_key {
+            Some(api_key) => http_request.header("x-api-key", api_key.as_ref()),
+            None => http_request,
+        }
+    }
+
+    fn parse_event(event: &Event) -> SseStep {
+        match serde_json::from_str::<AnthropicEvent>(&event.data) {
+            Ok(AnthropicEvent::ContentBlockDelta { delta }) if !delta.text.is_empty() => {
+                SseStep::Chunk(StreamChunk::Text(delta.text))
+            }
+            Ok(AnthropicEvent::MessageStart { message }) => {
+                SseStep::Chunk(StreamChunk::Usage(TokenUsage {
+                    prompt_tokens: message.usage.input_tokens,
+                    completion_tokens: 0,
+                }))
+            }
+            Ok(AnthropicEvent::MessageDelta { usage }) => {
+                SseStep::Chunk(StreamChunk::Usage(TokenUsage {
+                    prompt_tokens: 0,
+                    completion_tokens: usage.output_tokens,
+                }))
+            }
+            Ok(AnthropicEvent::MessageStop) => SseStep::Done,
+            Ok(AnthropicEvent::Error { error }) => SseStep::Fail(match error.kind.as_str() {
+                "overloaded_error" => LlmError::Overloaded(529),
+                "rate_limit_error" => LlmError::RateLimited { retry_after: None },
+                _ => LlmError::InvalidResponse(error.message),
+            }),
+            Ok(_) => SseStep::Skip,
+            Err(err) => SseStep::Fail(LlmError::InvalidResponse(err.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, LlmError> {
+        let body = AnthropicRequest::new(request, false);
+        let response: AnthropicResponse = self.0.send(self.request(), &body).await?;
+        let text: String = response
+            .content
+            .into_iter()
+            .filter(|block| block.kind == "text")
+            .map(|block| block.text)
+            .collect();
+        if text.is_empty() {
+            return Err(LlmError::InvalidResponse(
+                "completion has no text content".to_owned(),
+            ));
+        }
+        Ok(Completion {
+            message: ChatMessage {
+                role: Assistant,
+                content: text,
+            },
+            usage: response.usage.map(TokenUsage::from),
+        })
+    }
+
+    async fn stream(
+        &self,
+        request: CompletionRequest<'_>,
+    ) -> Result<Option<CompletionStream>, LlmError> {
+        let body = AnthropicRequest::new(request, true);
+        let stream = self
+            .0
+            .send_streaming(self.request(), &body, Self::parse_event)
+            .await?;
+        Ok(Some(stream))
+    }
+}
+
+/// Answers in one piece: Ollama streams newline-delimited JSON rather than
+/// server-sent events.
+#[derive(Debug)]
+pub struct OllamaProvider(Endpoint);
+
+#[derive(Serialize)]
+struct OllamaRequest<'a> {
+    model: &'a str,
+    messages: Vec<OllamaMessage<'a>>,
+    stream: bool,
+    options: OllamaOptions,
+}
+
+/// Ollama takes pictures as base64 next to the text.
+#[derive(Serialize)]
+struct OllamaMessage<'a> {
+    role: ChatMessageRole,
+    content: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    num_predict: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct OllamaResponse {
+    message: ChatMessage,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, LlmError> {
+        let body = OllamaRequest {
+            model: request.model,
+            messages: request
+                .messages_with_image()
+                .map(|(message, image)| OllamaMessage {
+                    role: message.role,
+                    content: &message.content,
+                    images: image.map(Image::base64).into_iter().collect(),
+                })
+                .collect(),
+            stream: false,
+            options: OllamaOptions {
+                num_predict: request.max_tokens,
+                temperature: request.temperature,
+            },
+        };
+        let mut http_request = self.0.post();
+        if let Some(api_key) = &self.0.api_key {
+            http_request = http_request.bearer_auth(api_key);
+        }
+        let response: OllamaResponse = self.0.send(http_request, &body).await?;
+        // Ollama leaves out `prompt_eval_count` when the prompt was cached.
+        let usage = response.eval_count.map(|completion_tokens| TokenUsage {
+            prompt_tokens: response.prompt_eval_count.unwrap_or(0),
+            completion_tokens,
+        });
+        Ok(Completion {
+            message: response.message,
+            usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+    use super::retry_after;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_dates() {
+        assert_eq!(retry_after(&headers("7")), Some(Duration::from_secs(7)));
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::ZERO),
+            "a date in the past means retry now"
+        );
+        let soon = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let delay = retry_after(&headers(&soon)).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+        assert_eq!(retry_after(&headers("soon")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+}
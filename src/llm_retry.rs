@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::error::LlmError;
+use crate::llm_provider::{Completion, CompletionRequest, CompletionStream, LlmProvider};
+
+pub(crate) const RETRY_MAX_ATTEMPTS: u32 = 3;
+pub(crate) const RETRY_BASE_DELAY_MS: u64 = 500;
+pub(crate) const RETRY_MAX_DELAY_SECS: u64 = 20;
+pub(crate) const BREAKER_FAILURE_THRESHOLD: u32 = 5;
+pub(crate) const BREAKER_COOLDOWN_SECS: u64 = 60;
+
+/// How often and how patiently a failed completion is retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first one included.
+    pub max_attempts: u32,
+    /// Pause before the first retry; doubles with every further one.
+    pub base_delay: Duration,
+    /// Longest pause between attempts. A `Retry-After` asking for more ends
+    /// the retries.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: RETRY_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(RETRY_BASE_DELAY_MS),
+            max_delay: Duration::from_secs(RETRY_MAX_DELAY_SECS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter: a random pause between half and all
+    /// of `base_delay * 2^(retry - 1)`, capped at `max_delay`.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(16);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let half = delay / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+/// When a provider's circuit opens and for how long.
+#[derive(Clone, Debug)]
+pub struct BreakerPolicy {
+    /// Consecutive provider failures that open the circuit.
+    pub failure_threshold: u32,
+    /// How long an open circuit rejects calls before letting one through.
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        Self {
+            failure_threshold: BREAKER_FAILURE_THRESHOLD,
+            cooldown: Duration::from_secs(BREAKER_COOLDOWN_SECS),
+        }
+    }
+}
+
+/// Circuit breakers by provider name, shared by every GPT call of the bot.
+///
+/// After `failure_threshold` consecutive provider failures the circuit opens
+/// and calls fail fast for `cooldown`. The first call after that is a probe:
+/// its success closes the circuit, its failure opens it again. Other calls
+/// keep failing fast while the probe runs.
+#[derive(Debug, Default)]
+pub struct CircuitBreakers(Mutex<HashMap<String, BreakerState>>);
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreakers {
+    /// May `provider` be called at `now`.
+    pub fn acquire(
+        &self,
+        provider: &str,
+        policy: &BreakerPolicy,
+        now: Instant,
+    ) -> Result<(), LlmError> {
+        let mut states = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(state) = states.get_mut(provider) else {
+            return Ok(());
+        };
+        match state.open_until {
+            Some(open_until) if now < open_until => Err(LlmError::CircuitOpen(provider.to_owned())),
+            Some(_) => {
+                // Half-open: this call is the probe. Keep the others out until
+                // it finishes, or for another cooldown if it never reports.
+                state.open_until = Some(now + policy.cooldown);
+                state.consecutive_failures = policy.failure_threshold.saturating_sub(1);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Record the outcome of a call to `provider`.
+    pub fn record<T>(
+        &self,
+        provider: &str,
+        result: &Result<T, LlmError>,
+        policy: &BreakerPolicy,
+        now: Instant,
+    ) {
+        let mut states = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match result {
+            Err(err) if err.is_provider_failure() => {
+                let state = states.entry(provider.to_owned()).or_default();
+                state.consecutive_failures += 1;
+                if state.consecutive_failures >= policy.failure_threshold {
+                    if state.open_until.is_none() {
+                        warn!("llm provider {provider} keeps failing, opening its circuit");
+                    }
+                    state.open_until = Some(now + policy.cooldown);
+                }
+            }
+            // The provider answered, even if it did not like the request.
+            _ => {
+                states.remove(provider);
+            }
+        }
+    }
+}
+
+/// Run `request` against `provider`, retrying transient failures with backoff
+/// and honouring `Retry-After`, behind the circuit breaker of `provider_name`.
+pub async fn complete_with_retry(
+    provider: &dyn LlmProvider,
+    provider_name: &str,
+    request: CompletionRequest<'_>,
+    retry: &RetryPolicy,
+    breakers: &CircuitBreakers,
+    breaker: &BreakerPolicy,
+) -> Result<Completion, LlmError> {
+    with_retry(provider_name, retry, breakers, breaker, || {
+        provider.complete(request)
+    })
+    .await
+}
+
+/// Start a streamed completion with the same retries as
+/// [`complete_with_retry`]. Failures once the stream runs are not retried.
+pub async fn stream_with_retry(
+    provider: &dyn LlmProvider,
+    provider_name: &str,
+    request: CompletionRequest<'_>,
+    retry: &RetryPolicy,
+    breakers: &CircuitBreakers,
+    breaker: &BreakerPolicy,
+) -> Result<Option<CompletionStream>, LlmError> {
+    with_retry(provider_name, retry, breakers, breaker, || {
+        provider.stream(request)
+    })
+    .await
+}
+
+async fn with_retry<T, F, Fut>(
+    provider_name: &str,
+    retry: &RetryPolicy,
+    breakers: &CircuitBreakers,
+    breaker: &BreakerPolicy,
+    mut call: F,
+) -> Result<T, LlmError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, LlmError>>,
+{
+    let mut attempt = 1;
+    loop {
+        breakers.acquire(provider_name, breaker, Instant::now())?;
+        let result = call().await;
+        breakers.record(provider_name, &result, breaker, Instant::now());
+        let err = match result {
+            Ok(value) => return Ok(value),
+            Err(err) if err.is_retryable() && attempt < retry.max_attempts => err,
+            Err(err) => return Err(err),
+        };
+        let delay = match err {
+            LlmError::RateLimited {
+                retry_after: Some(retry_after),
+            } if retry_after > retry.max_delay => return Err(err),
+            LlmError::RateLimited {
+                retry_after: Some(retry_after),
+            } => retry_after,
+            _ => retry.backoff(attempt),
+        };
+        warn!(
+            "llm provider {provider_name} attempt {attempt} failed: {err}, retrying in {delay:?}"
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{BreakerPolicy, CircuitBreakers, RetryPolicy};
+    use crate::error::LlmError;
+
+    const POLICY: BreakerPolicy = BreakerPolicy {
+        failure_threshold: 2,
+        cooldown: Duration::from_secs(60),
+    };
+
+    fn failure() -> Result<(), LlmError> {
+        Err(LlmError::Overloaded(503))
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        };
+        for _ in 0..100 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let third = policy.backoff(3);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+            let capped = policy.backoff(30);
+            assert!(capped >= Duration::from_millis(500) && capped <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn circuit_opens_after_consecutive_failures_and_probes_after_cooldown() {
+        let breakers = CircuitBreakers::default();
+        let start = Instant::now();
+        breakers.record("openai", &failure(), &POLICY, start);
+        assert!(breakers.acquire("openai", &POLICY, start).is_ok());
+        breakers.record("openai", &failure(), &POLICY, start);
+        assert!(matches!(
+            breakers.acquire("openai", &POLICY, start),
+            Err(LlmError::CircuitOpen(_))
+        ));
+        assert!(
+            breakers.acquire("claude", &POLICY, start).is_ok(),
+            "other providers are unaffected"
+        );
+
+        let later = start + POLICY.cooldown;
+        assert!(breakers.acquire("openai", &POLICY, later).is_ok(), "probe");
+        assert!(
+            breakers.acquire("openai", &POLICY, later).is_err(),
+            "only one probe at a time"
+        );
+        breakers.record("openai", &failure(), &POLICY, later);
+        assert!(
+            breakers.acquire("openai", &POLICY, later).is_err(),
+            "a failed probe reopens"
+        );
+
+        let much_later = later + POLICY.cooldown;
+        assert!(breakers.acquire("openai", &POLICY, much_later).is_ok());
+        breakers.record("openai", &Ok(()), &POLICY, much_later);
+        assert!(breakers.acquire("openai", &POLICY, much_later).is_ok());
+        breakers.record("openai", &failure(), &POLICY, much_later);
+        assert!(
+            breakers.acquire("openai", &POLICY, much_later).is_ok(),
+            "success resets the failure count"
+        );
+    }
+
+    #[test]
+    fn rejected_requests_do_not_trip_the_circuit() {
+        let breakers = CircuitBreakers::default();
+        let now = Instant::now();
+        for _ in 0..5 {
+            let rejected: Result<(), _> = Err(LlmError::BadRequest {
+                status: 400,
+                message: String::new(),
+            });
+            breakers.record("openai", &rejected, &POLICY, now);
+        }
+        assert!(breakers.acquire("openai", &POLICY, now).is_ok());
+    }
+}
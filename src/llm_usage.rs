@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::Utc;
+use log::warn;
+use sqlx::PgPool;
+use teloxide::types::{ChatId, Message, UserId};
+
+use crate::gpt_service::ChatMessage;
+use crate::llm_provider::TokenUsage;
+use crate::prompt_builder::PromptBuilder;
+use crate::usage_repository::{self, UsageEntry};
+use crate::GptParameters;
+
+/// List prices of the default models in USD per million prompt and
+/// completion tokens. `[prices]` in the config adds to and overrides them.
+const DEFAULT_PRICES: &[(&str, f64, f64)] = &[("gpt-4o", 2.5, 10.0), ("gpt-4o-mini", 0.15, 0.6)];
+
+/// The part of the bot a GPT call serves, stored as the `handler` of its
+/// usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GptFeature {
+    /// A persona answering a mention or a reply.
+    Conversation,
+    /// A persona summing up the chat history ("что происходит").
+    ChatSummary,
+    UrlSummary,
+    /// Describing a picture sent to a persona for its context.
+    ImageDescription,
+}
+
+impl GptFeature {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GptFeature::Conversation => "conversation",
+            GptFeature::ChatSummary => "chat_summary",
+            GptFeature::UrlSummary => "url_summary",
+            GptFeature::ImageDescription => "image_description",
+        }
+    }
+}
+
+impl fmt::Display for GptFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Who a GPT call is made for; its usage is booked to them.
+#[derive(Debug, Clone, Copy)]
+pub struct Caller {
+    pub chat_id: ChatId,
+    pub user_id: Option<UserId>,
+    pub feature: GptFeature,
+}
+
+impl Caller {
+    /// The chat and sender of `msg`.
+    pub fn of(msg: &Message, feature: GptFeature) -> Self {
+        Self {
+            chat_id: msg.chat.id,
+            user_id: msg.from.as_ref().map(|user| user.id),
+            feature,
+        }
+    }
+}
+
+/// Price of a model in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl ModelPrice {
+    /// Cost of `usage` in millionths of a dollar: a dollar per million
+    /// tokens is a micro-dollar per token.
+    pub fn cost_micros(&self, usage: TokenUsage) -> i64 {
+        let cost = f64::from(usage.prompt_tokens) * self.prompt
+            + f64::from(usage.completion_tokens) * self.completion;
+        cost.round() as i64
+    }
+}
+
+pub fn default_prices() -> HashMap<String, ModelPrice> {
+    DEFAULT_PRICES
+        .iter()
+        .map(|&(model, prompt, completion)| (model.to_owned(), ModelPrice { prompt, completion }))
+        .collect()
+}
+
+/// A GPT call waiting for its answer to be complete so its usage can be
+/// booked.
+pub(crate) struct UsageBooking {
+    db_pool: PgPool,
+    caller: Caller,
+    persona: Option<String>,
+    provider: String,
+    model: String,
+    price: Option<ModelPrice>,
+    /// Prompt tokens as counted by the bot, for providers that report none.
+    prompt_estimate: u32,
+}
+
+impl UsageBooking {
+    pub(crate) fn new(
+        params: &GptParameters,
+        caller: Caller,
+        persona: Option<&str>,
+        provider: &str,
+        model: &str,
+        prompt: &[ChatMessage],
+    ) -> Self {
+        let prompt_builder = PromptBuilder::for_model(model, 0);
+        let prompt_estimate = prompt
+            .iter()
+            .map(|message| prompt_builder.message_tokens(message))
+            .sum::<usize>();
+        Self {
+            db_pool: params.db_pool.clone(),
+            caller,
+            persona: persona.map(str::to_owned),
+            provider: provider.to_owned(),
+            model: model.to_owned(),
+            price: params.settings.prices.get(model).copied(),
+            prompt_estimate: u32::try_from(prompt_estimate).unwrap_or(u32::MAX),
+        }
+    }
+
+    /// Store the usage of the call. Without a reported `usage` the tokens of
+    /// the prompt and of the `completion` text are counted instead. Failures
+    /// are logged: a lost usage row is no reason to lose the answer.
+    pub(crate) async fn book(self, usage: Option<TokenUsage>, completion: &str) {
+        let estimated = usage.is_none();
+        let usage = usage.unwrap_or_else(|| {
+            let completion_tokens =
+                PromptBuilder::for_model(&self.model, 0).count_tokens(completion);
+            TokenUsage {
+                prompt_tokens: self.prompt_estimate,
+                completion_tokens: u32::try_from(completion_tokens).unwrap_or(u32::MAX),
+            }
+        });
+        let cost_micros = match self.price {
+            Some(price) => price.cost_micros(usage),
+            None => {
+                warn!(
+                    "no price for model {}, booking its usage at no cost",
+                    self.model
+                );
+                0
+            }
+        };
+        let entry = UsageEntry {
+            chat_id: self.caller.chat_id.0,
+            user_id: self.caller.user_id.map(|user_id| user_id.0 as i64),
+            persona_id: self.persona.as_deref(),
+            handler: self.caller.feature.as_str(),
+            provider: &self.provider,
+            model: &self.model,
+            prompt_tokens: i32::try_from(usage.prompt_tokens).unwrap_or(i32::MAX),
+            completion_tokens: i32::try_from(usage.completion_tokens).unwrap_or(i32::MAX),
+            cost_micros,
+            estimated,
+            created_at: Utc::now().naive_utc(),
+        };
+        if let Err(err) = usage_repository::insert_usage(&self.db_pool, &entry).await {
+            warn!("Can't book llm usage: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_prices, ModelPrice};
+    use crate::llm_provider::TokenUsage;
+    use crate::DEFAULT_GPT_MODEL;
+
+    #[test]
+    fn cost_is_priced_per_million_tokens() {
+        let price = ModelPrice {
+            prompt: 2.5,
+            completion: 10.0,
+        };
+        let usage = TokenUsage {
+            prompt_tokens: 1_000,
+            completion_tokens: 200,
+        };
+        // $0.0025 for the prompt and $0.002 for the completion.
+        assert_eq!(price.cost_micros(usage), 4_500);
+        let cheap = ModelPrice {
+            prompt: 0.15,
+            completion: 0.6,
+        };
+        assert_eq!(cheap.cost_micros(TokenUsage::default()), 0);
+        assert_eq!(
+            cheap.cost_micros(TokenUsage {
+                prompt_tokens: 3,
+                completion_tokens: 1
+            }),
+            1
+        );
+    }
+
+    #[test]
+    fn default_model_has_a_price() {
+        assert!(default_prices().contains_key(DEFAULT_GPT_MODEL));
+    }
+}
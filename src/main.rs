@@ -9,16 +9,23 @@ use redis::aio::ConnectionManager;
 use sqlx::PgPool;
 use teloxide::prelude::*;
 
-use rust_bot::{AppDeps, GptParameters, MentionParameters, DEFAULT_OPENAI_BASE_URL};
+use rust_bot::safe_fetcher::{FetchSettings, SafeFetcher};
+use rust_bot::{AppDeps, BotConfig, ConfigHandle, GptParameters};
+
+/// The Rust chat, where mentions have always been counted silently.
+const DEFAULT_RUST_CHAT_ID: i64 = -1001228598755;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     pretty_env_logger::init();
     info!("Starting bot...");
 
+    let config = BotConfig::from_env().context("invalid bot configuration")?;
+
     let telegram_token = env::var("TELOXIDE_TOKEN").context("TELOXIDE_TOKEN must be set")?;
     let bot = Bot::new(telegram_token);
     let db_pool = establish_connection().await?;
+    seed_silent_chat(&db_pool).await?;
     let chat_gpt_api_token =
         env::var("CHAT_GPT_API_TOKEN").context("CHAT_GPT_API_TOKEN must be set")?;
     let redis_url = env::var("REDIS_URL").context("REDIS_URL must be set")?;
@@ -30,21 +37,45 @@ async fn main() -> anyhow::Result<()> {
 
     let gpt_parameters = GptParameters {
         chat_gpt_api_token: Arc::from(chat_gpt_api_token),
-        openai_base_url: Arc::from(DEFAULT_OPENAI_BASE_URL),
         http_client: reqwest::Client::new(),
+        url_fetcher: SafeFetcher::new(FetchSettings::default())
+            .context("failed to build the URL fetcher")?,
         redis_connection_manager,
+        db_pool: db_pool.clone(),
+        settings: Arc::clone(&config.gpt_settings),
+        circuit_breakers: Arc::default(),
+        chat_cache: Arc::default(),
     };
 
     let deps = AppDeps {
         bot,
         db_pool,
         gpt_parameters,
-        mention_parameters: MentionParameters::default(),
+        config: ConfigHandle::new(config),
     };
 
     rust_bot::run(deps).await
 }
 
+/// `RUST_CHAT_ID` used to name the chat where mentions are counted but not
+/// announced; it now only seeds that chat's settings the first time. Without
+/// it, the historically hardcoded chat is seeded.
+async fn seed_silent_chat(db_pool: &PgPool) -> anyhow::Result<()> {
+    let chat_id = match env::var("RUST_CHAT_ID") {
+        Ok(raw) => raw
+            .parse()
+            .with_context(|| format!("RUST_CHAT_ID='{raw}' is not a chat id"))?,
+        Err(_) => DEFAULT_RUST_CHAT_ID,
+    };
+    if rust_bot::chat_settings_repository::seed_silent_chat(db_pool, chat_id)
+        .await
+        .context("failed to seed the RUST_CHAT_ID chat settings")?
+    {
+        info!("chat {chat_id} from RUST_CHAT_ID counts Rust mentions silently");
+    }
+    Ok(())
+}
+
 async fn establish_connection() -> anyhow::Result<PgPool> {
     let database_url = env::var("DATABASE_URL").context("DATABASE_URL must be set")?;
     PgPool::connect(&database_url)
@@ -2,16 +2,23 @@ use sqlx::postgres::PgQueryResult;
 use sqlx::types::chrono::NaiveDateTime;
 use sqlx::{Error, PgPool};
 
+/// `thread_id` stored for mentions outside of forum topics.
+const NO_THREAD: i64 = 0;
+
+/// Latest mention time in a chat, or only in one forum topic of it when
+/// `thread_id` is given.
 pub async fn lead_earliest_mention_time(
     pool: &PgPool,
     chat_id: i64,
+    thread_id: Option<i64>,
 ) -> Result<NaiveDateTime, Error> {
     sqlx::query_as(
         "SELECT updated_at FROM mentions \
-                WHERE chat_id = $1 \
+                WHERE chat_id = $1 AND ($2::BIGINT IS NULL OR thread_id = $2) \
                     ORDER BY updated_at DESC LIMIT 1",
     )
     .bind(chat_id)
+    .bind(thread_id)
     .fetch_optional(pool)
     .await
     .map(|v: Option<(NaiveDateTime,)>| v.unwrap_or((NaiveDateTime::MAX,)).0)
@@ -22,15 +29,207 @@ pub async fn insert_mention(
     user_id: i64,
     username: &str,
     chat_id: i64,
+    thread_id: Option<i64>,
 ) -> Result<PgQueryResult, Error> {
     sqlx::query(
-        "INSERT INTO mentions(user_id, username, chat_id) VALUES ($1, $2, $3)  \
-                ON CONFLICT (user_id, chat_id) DO UPDATE \
+        "INSERT INTO mentions(user_id, username, chat_id, thread_id) VALUES ($1, $2, $3, $4)  \
+                ON CONFLICT (user_id, chat_id, thread_id) DO UPDATE \
                     SET updated_at = current_timestamp, counter = mentions.counter + 1",
     )
     .bind(user_id)
     .bind(username)
     .bind(chat_id)
+    .bind(thread_id.unwrap_or(NO_THREAD))
+    .execute(pool)
+    .await
+}
+
+pub struct ChatMentionStats {
+    pub mentioners: i64,
+    pub total_mentions: i64,
+    pub last_mention: Option<NaiveDateTime>,
+}
+
+pub async fn chat_mention_stats(pool: &PgPool, chat_id: i64) -> Result<ChatMentionStats, Error> {
+    sqlx::query_as(
+        "SELECT COUNT(DISTINCT user_id), COALESCE(SUM(counter), 0)::BIGINT, MAX(updated_at) \
+                FROM mentions WHERE chat_id = $1",
+    )
+    .bind(chat_id)
+    .fetch_one(pool)
+    .await
+    .map(
+        |(mentioners, total_mentions, last_mention): (i64, i64, Option<NaiveDateTime>)| {
+            ChatMentionStats {
+                mentioners,
+                total_mentions,
+                last_mention,
+            }
+        },
+    )
+}
+
+pub struct MentionRank {
+    pub user_id: i64,
+    pub username: String,
+    pub counter: i32,
+    pub last_mention: NaiveDateTime,
+}
+
+/// Top Rust mentioners of a chat, or of one forum topic of it when `thread_id`
+/// is given, most mentions first.
+pub async fn top_mentioners(
+    pool: &PgPool,
+    chat_id: i64,
+    thread_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<MentionRank>, Error> {
+    sqlx::query_as(
+        "SELECT user_id, COALESCE(MAX(username), 'unknown'), \
+                       COALESCE(SUM(counter), 0)::INT AS total, MAX(updated_at) \
+                FROM mentions WHERE chat_id = $1 AND ($2::BIGINT IS NULL OR thread_id = $2) \
+                    GROUP BY user_id ORDER BY total DESC, MAX(updated_at) DESC LIMIT $3",
+    )
+    .bind(chat_id)
+    .bind(thread_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map(|rows: Vec<(i64, String, i32, NaiveDateTime)>| {
+        rows.into_iter()
+            .map(|(user_id, username, counter, last_mention)| MentionRank {
+                user_id,
+                username,
+                counter,
+                last_mention,
+            })
+            .collect()
+    })
+}
+
+/// A single detected Rust mention, as appended to `mention_events`.
+pub struct MentionEvent<'a> {
+    pub chat_id: i64,
+    pub thread_id: Option<i64>,
+    pub user_id: i64,
+    /// Not every Telegram user has one.
+    pub username: Option<&'a str>,
+    pub message_id: i64,
+    pub created_at: NaiveDateTime,
+}
+
+pub async fn insert_mention_event(
+    pool: &PgPool,
+    event: &MentionEvent<'_>,
+) -> Result<PgQueryResult, Error> {
+    sqlx::query(
+        "INSERT INTO mention_events(chat_id, thread_id, user_id, username, message_id, created_at) \
+                VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(event.chat_id)
+    .bind(event.thread_id)
+    .bind(event.user_id)
+    .bind(event.username)
+    .bind(event.message_id)
+    .bind(event.created_at)
     .execute(pool)
     .await
 }
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MentionPeriod {
+    Day,
+    Week,
+}
+
+impl MentionPeriod {
+    fn as_date_trunc_field(self) -> &'static str {
+        match self {
+            MentionPeriod::Day => "day",
+            MentionPeriod::Week => "week",
+        }
+    }
+}
+
+/// Number of mentions in a chat per day or week since `since`, oldest first.
+pub async fn mentions_per_period(
+    pool: &PgPool,
+    chat_id: i64,
+    period: MentionPeriod,
+    since: NaiveDateTime,
+) -> Result<Vec<(NaiveDateTime, i64)>, Error> {
+    sqlx::query_as(
+        "SELECT date_trunc($2, created_at) AS period, COUNT(*) FROM mention_events \
+                WHERE chat_id = $1 AND created_at >= $3 \
+                    GROUP BY period ORDER BY period",
+    )
+    .bind(chat_id)
+    .bind(period.as_date_trunc_field())
+    .bind(since)
+    .fetch_all(pool)
+    .await
+}
+
+pub struct UserMentionCount {
+    pub user_id: i64,
+    pub username: String,
+    pub mentions: i64,
+}
+
+/// Number of mentions per user in a chat since `since`, most mentions first.
+pub async fn mentions_per_user(
+    pool: &PgPool,
+    chat_id: i64,
+    since: NaiveDateTime,
+) -> Result<Vec<UserMentionCount>, Error> {
+    sqlx::query_as(
+        "SELECT user_id, COALESCE(MAX(username), 'unknown'), COUNT(*) AS mentions \
+                FROM mention_events WHERE chat_id = $1 AND created_at >= $2 \
+                    GROUP BY user_id ORDER BY mentions DESC, user_id",
+    )
+    .bind(chat_id)
+    .bind(since)
+    .fetch_all(pool)
+    .await
+    .map(|rows: Vec<(i64, String, i64)>| {
+        rows.into_iter()
+            .map(|(user_id, username, mentions)| UserMentionCount {
+                user_id,
+                username,
+                mentions,
+            })
+            .collect()
+    })
+}
+
+/// The longest stretch between two consecutive mentions in a chat.
+pub struct MentionGap {
+    pub started_at: NaiveDateTime,
+    pub ended_at: NaiveDateTime,
+}
+
+impl MentionGap {
+    pub fn duration(&self) -> chrono::Duration {
+        self.ended_at.signed_duration_since(self.started_at)
+    }
+}
+
+pub async fn longest_mention_gap(pool: &PgPool, chat_id: i64) -> Result<Option<MentionGap>, Error> {
+    sqlx::query_as(
+        "SELECT started_at, ended_at FROM ( \
+                SELECT LAG(created_at) OVER (ORDER BY created_at) AS started_at, \
+                       created_at AS ended_at \
+                    FROM mention_events WHERE chat_id = $1 \
+                ) gaps WHERE started_at IS NOT NULL \
+                    ORDER BY ended_at - started_at DESC LIMIT 1",
+    )
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await
+    .map(|row: Option<(NaiveDateTime, NaiveDateTime)>| {
+        row.map(|(started_at, ended_at)| MentionGap {
+            started_at,
+            ended_at,
+        })
+    })
+}
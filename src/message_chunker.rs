@@ -0,0 +1,334 @@
+/// A fenced code block's opening line and the marker that closes it.
+#[derive(Debug, Clone)]
+struct Fence {
+    opener: String,
+    marker: String,
+}
+
+impl Fence {
+    fn opened_by(line: &str) -> Option<Self> {
+        let trimmed = line.trim_start();
+        if line.len() - trimmed.len() > 3 {
+            return None;
+        }
+        let fence_char = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+        let length = trimmed.chars().take_while(|&c| c == fence_char).count();
+        let info = &trimmed[length..];
+        if length < 3 || (fence_char == '`' && info.contains('`')) {
+            return None;
+        }
+        Some(Self {
+            opener: line.to_owned(),
+            marker: trimmed[..length].to_owned(),
+        })
+    }
+
+    fn is_closed_by(&self, line: &str) -> bool {
+        let trimmed = line.trim();
+        trimmed.starts_with(&self.marker) && trimmed.chars().all(|c| self.marker.starts_with(c))
+    }
+}
+
+/// A paragraph or fenced code block, with the separator it had from the
+/// block before.
+#[derive(Debug)]
+struct Block {
+    separator: &'static str,
+    text: String,
+    fence: Option<Fence>,
+}
+
+/// Split `markdown` into messages of at most `limit` UTF-16 code units, the
+/// way Telegram measures them. Messages
+/// break between paragraphs and code blocks where they can; a block too long
+/// for one message is split between lines (between words for prose), and a
+/// code block split that way is closed and opened again with its language, so
+/// every part renders on its own. Always returns at least one message.
+pub fn split(markdown: &str, limit: usize) -> Vec<String> {
+    if text_len(markdown) <= limit {
+        return vec![markdown.to_owned()];
+    }
+    let mut messages = Vec::new();
+    let mut message = String::new();
+    for (separator, piece) in blocks(markdown)
+        .into_iter()
+        .flat_map(|block| split_block(block, limit))
+    {
+        if message.is_empty() {
+            message = piece;
+        } else if text_len(&message) + text_len(separator) + text_len(&piece) <= limit {
+            message.push_str(separator);
+            message.push_str(&piece);
+        } else {
+            messages.push(std::mem::replace(&mut message, piece));
+        }
+    }
+    messages.push(message);
+    messages
+}
+
+fn blocks(markdown: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut lines: Vec<&str> = Vec::new();
+    let mut separator = "";
+    let mut fence: Option<Fence> = None;
+    let mut flush =
+        |lines: &mut Vec<&str>, separator: &mut &'static str, fence: Option<Fence>, next| {
+            if lines.is_empty() {
+                if next == "\n\n" {
+                    *separator = next;
+                }
+                return;
+            }
+            blocks.push(Block {
+                separator,
+                text: lines.join("\n"),
+                fence,
+            });
+            lines.clear();
+            *separator = next;
+        };
+    for line in markdown.lines() {
+        if let Some(open) = &fence {
+            lines.push(line);
+            if open.is_closed_by(line) {
+                flush(&mut lines, &mut separator, fence.take(), "\n");
+            }
+        } else if line.trim().is_empty() {
+            flush(&mut lines, &mut separator, None, "\n\n");
+        } else if let Some(open) = Fence::opened_by(line) {
+            flush(&mut lines, &mut separator, None, "\n");
+            lines.push(line);
+            fence = Some(open);
+        } else {
+            lines.push(line);
+        }
+    }
+    flush(&mut lines, &mut separator, fence, "");
+    blocks
+}
+
+/// `block` in pieces of at most `limit` code units, each with the separator
+/// that goes before it.
+fn split_block(block: Block, limit: usize) -> Vec<(&'static str, String)> {
+    if text_len(&block.text) <= limit {
+        return vec![(block.separator, block.text)];
+    }
+    let pieces = match &block.fence {
+        Some(fence) => split_code(&block.text, fence, limit),
+        None => pack(
+            block
+                .text
+                .lines()
+                .flat_map(|line| split_line(line, limit, true)),
+            limit,
+        ),
+    };
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(i, piece)| (if i == 0 { block.separator } else { "\n" }, piece))
+        .collect()
+}
+
+fn split_code(text: &str, fence: &Fence, limit: usize) -> Vec<String> {
+    // The opener, the closing marker and the newlines after and before them.
+    let frame = text_len(&fence.opener) + text_len(&fence.marker) + 2;
+    let Some(budget) = limit.checked_sub(frame).filter(|&budget| budget > 0) else {
+        return pack(
+            text.lines().flat_map(|line| split_line(line, limit, false)),
+            limit,
+        );
+    };
+    let mut lines: Vec<&str> = text.lines().skip(1).collect();
+    if lines.last().is_some_and(|line| fence.is_closed_by(line)) {
+        lines.pop();
+    }
+    pack(
+        lines
+            .into_iter()
+            .flat_map(|line| split_line(line, budget, false)),
+        budget,
+    )
+    .into_iter()
+    .map(|code| format!("{}\n{code}\n{}", fence.opener, fence.marker))
+    .collect()
+}
+
+/// Join `lines` back into as few pieces of at most `limit` code units as fit.
+fn pack(lines: impl Iterator<Item = String>, limit: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut piece: Option<String> = None;
+    for line in lines {
+        piece = Some(match piece {
+            Some(mut piece) if text_len(&piece) + 1 + text_len(&line) <= limit => {
+                piece.push('\n');
+                piece.push_str(&line);
+                piece
+            }
+            Some(full) => {
+                pieces.push(full);
+                line
+            }
+            None => line,
+        });
+    }
+    pieces.extend(piece);
+    pieces
+}
+
+/// `line` in parts of at most `limit` code units: between words when
+/// `at_words` and a word allows, else anywhere.
+fn split_line(line: &str, limit: usize, at_words: bool) -> Vec<String> {
+    if text_len(line) <= limit {
+        return vec![line.to_owned()];
+    }
+    if !at_words {
+        return hard_split(line, limit);
+    }
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    for word in line.split(' ') {
+        if text_len(&part) + 1 + text_len(word) <= limit {
+            if !part.is_empty() {
+                part.push(' ');
+            }
+            part.push_str(word);
+            continue;
+        }
+        if !part.is_empty() {
+            parts.push(std::mem::take(&mut part));
+        }
+        let mut pieces = hard_split(word, limit);
+        part = pieces.pop().unwrap_or_default();
+        parts.extend(pieces);
+    }
+    if !part.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+/// `text` in parts of at most `limit` code units, never splitting a
+/// character; one wider than `limit` gets a part of its own.
+fn hard_split(text: &str, limit: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut part_len = 0;
+    for c in text.chars() {
+        if part_len + c.len_utf16() > limit && !part.is_empty() {
+            parts.push(std::mem::take(&mut part));
+            part_len = 0;
+        }
+        part.push(c);
+        part_len += c.len_utf16();
+    }
+    if !part.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+/// The length Telegram limits messages by: UTF-16 code units, so an emoji
+/// outside the Basic Multilingual Plane counts twice.
+fn text_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+
+    fn assert_fits(messages: &[String], limit: usize) {
+        for message in messages {
+            let len = message.encode_utf16().count();
+            assert!(len <= limit, "{len} code units: {message:?}");
+        }
+    }
+
+    #[test]
+    fn short_text_is_one_message() {
+        assert_eq!(split("привет\n\n\nмир", 20), vec!["привет\n\n\nмир"]);
+        assert_eq!(split("", 20), vec![""]);
+    }
+
+    #[test]
+    fn messages_break_between_paragraphs() {
+        let text = "Первый абзац.\n\nВторой абзац.\n\nТретий.";
+        let messages = split(text, 30);
+        assert_eq!(messages, vec!["Первый абзац.\n\nВторой абзац.", "Третий."]);
+        assert_eq!(messages.join("\n\n"), text);
+    }
+
+    #[test]
+    fn code_blocks_are_kept_whole_when_they_fit() {
+        let text = "Смотри:\n```rust\nfn main() {}\n```\nИ ещё немного текста после.";
+        assert_eq!(
+            split(text, 40),
+            vec![
+                "Смотри:\n```rust\nfn main() {}\n```",
+                "И ещё немного текста после."
+            ]
+        );
+    }
+
+    #[test]
+    fn long_code_block_is_closed_and_reopened() {
+        let code: Vec<String> = (0..6).map(|i| format!("let x{i} = {i};")).collect();
+        let text = format!("```rust\n{}\n```", code.join("\n"));
+        let messages = split(&text, 40);
+        assert_fits(&messages, 40);
+        assert_eq!(
+            messages,
+            vec![
+                "```rust\nlet x0 = 0;\nlet x1 = 1;\n```",
+                "```rust\nlet x2 = 2;\nlet x3 = 3;\n```",
+                "```rust\nlet x4 = 4;\nlet x5 = 5;\n```",
+            ]
+        );
+        let unfinished = split("```rust\nlet x0 = 0;\nlet x1 = 1;\nlet x2 = 2;", 40);
+        assert_eq!(unfinished[1], "```rust\nlet x2 = 2;\n```");
+    }
+
+    #[test]
+    fn long_paragraph_breaks_between_lines_then_words() {
+        let text = "раз два три четыре пять шесть\nсемь";
+        let messages = split(text, 12);
+        assert_fits(&messages, 12);
+        assert_eq!(messages, vec!["раз два три", "четыре пять", "шесть\nсемь"]);
+        let word = "а".repeat(25);
+        let messages = split(&format!("x {word}"), 10);
+        assert_fits(&messages, 10);
+        assert_eq!(messages.concat(), format!("x{word}"));
+    }
+
+    #[test]
+    fn every_message_fits_a_long_answer() {
+        let paragraph = "Строка ответа про владение и заимствование. ".repeat(30);
+        let code = "    println!(\"{}\", value);\n".repeat(120);
+        let text = format!("{paragraph}\n\n```rust\n{code}```\n\n{paragraph}");
+        let messages = split(&text, 4096);
+        assert!(messages.len() > 1);
+        assert_fits(&messages, 4096);
+        for message in &messages {
+            assert_eq!(
+                message.matches("```").count() % 2,
+                0,
+                "unbalanced fence: {message}"
+            );
+        }
+    }
+
+    #[test]
+    fn emoji_count_as_two_code_units() {
+        let text = "🦀".repeat(3000);
+        let messages = split(&text, 4096);
+        assert_fits(&messages, 4096);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages.concat(), text);
+
+        let messages = split("ab🦀cd", 3);
+        assert_eq!(messages, vec!["ab", "🦀c", "d"]);
+        assert_eq!(split("🦀", 1), vec!["🦀"]);
+    }
+}
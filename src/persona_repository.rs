@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use log::warn;
+use regex::Regex;
+use sqlx::postgres::PgQueryResult;
+use sqlx::{Error, PgPool};
+
+use crate::persona::{Persona, PersonaId};
+
+/// `personas.chat_id` of the personas shared by every chat.
+pub const GLOBAL_PERSONAS_CHAT_ID: i64 = 0;
+
+type PersonaRow = (
+    String,
+    String,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<f32>,
+    Vec<String>,
+    Vec<i64>,
+);
+
+/// Personas stored for every chat and for `chat_id`, shared ones first so the
+/// chat's own personas win when the ids collide. Rows with a mention regex
+/// that no longer compiles are skipped.
+pub async fn get_personas(pool: &PgPool, chat_id: i64) -> Result<Vec<Persona>, Error> {
+    let rows: Vec<PersonaRow> = sqlx::query_as(
+        "SELECT id, name, mention_regex, system_prompt, provider, model, temperature, \
+                languages, enabled_chats \
+                FROM personas WHERE chat_id IN ($1, $2) \
+                ORDER BY chat_id <> $1, id",
+    )
+    .bind(GLOBAL_PERSONAS_CHAT_ID)
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().filter_map(persona_from_row).collect())
+}
+
+/// A persona defined by the chat itself, without the shared ones.
+pub async fn get_chat_persona(
+    pool: &PgPool,
+    chat_id: i64,
+    id: &PersonaId,
+) -> Result<Option<Persona>, Error> {
+    let row: Option<PersonaRow> = sqlx::query_as(
+        "SELECT id, name, mention_regex, system_prompt, provider, model, temperature, \
+                languages, enabled_chats \
+                FROM personas WHERE chat_id = $1 AND id = $2",
+    )
+    .bind(chat_id)
+    .bind(id.as_str())
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.and_then(persona_from_row))
+}
+
+/// Ids of the personas the chat defined itself.
+pub async fn chat_persona_ids(pool: &PgPool, chat_id: i64) -> Result<Vec<PersonaId>, Error> {
+    let ids: Vec<(String,)> =
+        sqlx::query_as("SELECT id FROM personas WHERE chat_id = $1 ORDER BY id")
+            .bind(chat_id)
+            .fetch_all(pool)
+            .await?;
+    Ok(ids.into_iter().map(|(id,)| PersonaId::new(id)).collect())
+}
+
+/// Create or replace a persona of the chat.
+pub async fn upsert_chat_persona(
+    pool: &PgPool,
+    chat_id: i64,
+    persona: &Persona,
+) -> Result<PgQueryResult, Error> {
+    sqlx::query(
+        "INSERT INTO personas (chat_id, id, name, mention_regex, system_prompt, provider, \
+                model, temperature, languages, enabled_chats, updated_at) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, NOW()) \
+                ON CONFLICT (chat_id, id) DO UPDATE SET name = $3, mention_regex = $4, \
+                system_prompt = $5, provider = $6, model = $7, temperature = $8, \
+                languages = $9, enabled_chats = $10, updated_at = NOW()",
+    )
+    .bind(chat_id)
+    .bind(persona.id.as_str())
+    .bind(&persona.name)
+    .bind(persona.mention_regex.as_str())
+    .bind(&persona.system_prompt)
+    .bind(persona.provider.as_deref())
+    .bind(persona.model.as_deref())
+    .bind(persona.temperature)
+    .bind(&persona.languages)
+    .bind(&persona.enabled_chats)
+    .execute(pool)
+    .await
+}
+
+/// Delete a persona of the chat; `false` when the chat had no such persona.
+pub async fn delete_chat_persona(
+    pool: &PgPool,
+    chat_id: i64,
+    id: &PersonaId,
+) -> Result<bool, Error> {
+    let result = sqlx::query("DELETE FROM personas WHERE chat_id = $1 AND id = $2")
+        .bind(chat_id)
+        .bind(id.as_str())
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+fn persona_from_row(row: PersonaRow) -> Option<Persona> {
+    let (
+        id,
+        name,
+        mention_regex,
+        system_prompt,
+        provider,
+        model,
+        temperature,
+        languages,
+        enabled_chats,
+    ) = row;
+    let mention_regex = Regex::new(&mention_regex)
+        .inspect_err(|err| warn!("persona {id} has an invalid mention regex: {err}; skipping"))
+        .ok()?;
+    Some(Persona {
+        id: PersonaId::new(id),
+        name,
+        mention_regex,
+        system_prompt,
+        provider,
+        model: model.map(Arc::from),
+        temperature,
+        languages,
+        enabled_chats,
+    })
+}
@@ -0,0 +1,206 @@
+use log::{info, warn};
+use tiktoken_rs::model::get_context_size;
+use tiktoken_rs::{bpe_for_model, o200k_base_singleton, CoreBPE};
+
+use crate::gpt_service::ChatMessage;
+use crate::gpt_service::ChatMessageRole::System;
+
+/// Fallback context window for models `tiktoken-rs` does not know about.
+const DEFAULT_CONTEXT_WINDOW: usize = 8_192;
+/// Per-message framing overhead of the chat format (role, separators).
+const TOKENS_PER_MESSAGE: usize = 4;
+/// Tokens the API adds to prime the assistant reply.
+const REPLY_PRIMING_TOKENS: usize = 3;
+
+/// Token-aware assembly of chat prompts: counts tokens with the tokenizer of the
+/// configured model family and shrinks prompts to fit the model's context
+/// window, leaving room for the completion.
+pub struct PromptBuilder {
+    bpe: &'static CoreBPE,
+    context_window: usize,
+    max_completion_tokens: usize,
+}
+
+impl PromptBuilder {
+    /// Build for `model`, falling back to the `o200k_base` tokenizer and a
+    /// conservative context window for unknown model names.
+    pub fn for_model(model: &str, max_completion_tokens: usize) -> Self {
+        let bpe = bpe_for_model(model).unwrap_or_else(|err| {
+            warn!("no tokenizer for model {model}: {err}; using o200k_base");
+            o200k_base_singleton()
+        });
+        Self {
+            bpe,
+            context_window: get_context_size(model).unwrap_or(DEFAULT_CONTEXT_WINDOW),
+            max_completion_tokens,
+        }
+    }
+
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+
+    pub fn message_tokens(&self, message: &ChatMessage) -> usize {
+        self.count_tokens(&message.content) + TOKENS_PER_MESSAGE
+    }
+
+    /// Tokens available to the prompt once the completion is reserved.
+    pub fn prompt_budget(&self) -> usize {
+        self.context_window
+            .saturating_sub(self.max_completion_tokens)
+            .saturating_sub(REPLY_PRIMING_TOKENS)
+    }
+
+    /// Fit a prompt into the budget. Leading system messages and the final
+    /// (current) message are always kept; the turns between them are dropped
+    /// oldest first, and if that is still not enough the final message is
+    /// truncated.
+    pub fn fit(&self, messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+        let budget = self.prompt_budget();
+        let mut messages = messages;
+        let Some(mut last) = messages.pop() else {
+            return messages;
+        };
+        let pinned = messages
+            .iter()
+            .take_while(|message| matches!(message.role, System))
+            .count();
+        let history = messages.split_off(pinned);
+        let system = messages;
+
+        let pinned_tokens: usize = system
+            .iter()
+            .chain(std::iter::once(&last))
+            .map(|message| self.message_tokens(message))
+            .sum();
+        let history = self.keep_latest(history, budget.saturating_sub(pinned_tokens), |m| {
+            self.message_tokens(m)
+        });
+
+        let system_tokens: usize = system.iter().map(|m| self.message_tokens(m)).sum();
+        let available = budget
+            .saturating_sub(system_tokens)
+            .saturating_sub(TOKENS_PER_MESSAGE);
+        if self.count_tokens(&last.content) > available {
+            info!("truncating prompt message to {available} tokens");
+            last.content = self.truncate(&last.content, available);
+        }
+
+        [system, history, vec![last]].concat()
+    }
+
+    /// Keep the newest `items` whose summed `cost` fits into `budget`.
+    pub fn keep_latest<T>(
+        &self,
+        items: Vec<T>,
+        budget: usize,
+        cost: impl Fn(&T) -> usize,
+    ) -> Vec<T> {
+        let mut spent = 0;
+        let keep = items
+            .iter()
+            .rev()
+            .take_while(|item| {
+                spent += cost(item);
+                spent <= budget
+            })
+            .count();
+        let dropped = items.len() - keep;
+        if dropped > 0 {
+            info!("dropping {dropped} oldest prompt entries to fit {budget} tokens");
+        }
+        items.into_iter().skip(dropped).collect()
+    }
+
+    /// Cut `text` down to at most `max_tokens` tokens, on a character boundary.
+    pub fn truncate(&self, text: &str, max_tokens: usize) -> String {
+        let tokens = self.bpe.encode_with_special_tokens(text);
+        if tokens.len() <= max_tokens {
+            return text.to_owned();
+        }
+        match self.bpe.decode_bytes(&tokens[..max_tokens]) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes)
+                .trim_end_matches(char::REPLACEMENT_CHARACTER)
+                .to_owned(),
+            Err(err) => {
+                warn!("can't decode truncated prompt: {err:?}");
+                text.chars().take(max_tokens).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PromptBuilder;
+    use crate::gpt_service::ChatMessage;
+    use crate::gpt_service::ChatMessageRole::{Assistant, System, User};
+
+    fn message(role: crate::gpt_service::ChatMessageRole, content: &str) -> ChatMessage {
+        ChatMessage {
+            role,
+            content: content.to_owned(),
+        }
+    }
+
+    #[test]
+    fn known_model_uses_its_context_window() {
+        let builder = PromptBuilder::for_model("gpt-4o", 1000);
+        assert_eq!(builder.prompt_budget(), 128_000 - 1000 - 3);
+    }
+
+    #[test]
+    fn fit_keeps_prompt_untouched_when_within_budget() {
+        let builder = PromptBuilder::for_model("gpt-4o", 1000);
+        let prompt = vec![
+            message(System, "system"),
+            message(User, "first"),
+            message(Assistant, "second"),
+            message(User, "question"),
+        ];
+        let fitted = builder.fit(prompt);
+        let contents: Vec<&str> = fitted.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["system", "first", "second", "question"]);
+    }
+
+    #[test]
+    fn fit_drops_oldest_turns_first() {
+        // 8192-token fallback window minus a huge completion leaves ~60 tokens.
+        let builder = PromptBuilder::for_model("unknown-model", 8_192 - 64);
+        let filler = "word ".repeat(40);
+        let prompt = vec![
+            message(System, "system"),
+            message(User, &format!("oldest {filler}")),
+            message(Assistant, &format!("middle {filler}")),
+            message(User, "newest"),
+            message(User, "question"),
+        ];
+        let fitted = builder.fit(prompt);
+        let contents: Vec<&str> = fitted.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["system", "newest", "question"]);
+    }
+
+    #[test]
+    fn fit_truncates_oversized_final_message() {
+        let builder = PromptBuilder::for_model("unknown-model", 8_192 - 64);
+        let article = "очень длинная статья ".repeat(500);
+        let fitted = builder.fit(vec![message(System, "system"), message(User, &article)]);
+        assert_eq!(fitted.len(), 2);
+        let total: usize = fitted.iter().map(|m| builder.message_tokens(m)).sum();
+        assert!(
+            total <= builder.prompt_budget(),
+            "total {total} over budget"
+        );
+        assert!(article.starts_with(&fitted[1].content));
+    }
+
+    #[test]
+    fn truncate_respects_token_limit_and_char_boundaries() {
+        let builder = PromptBuilder::for_model("gpt-4o", 1000);
+        let text = "Фёдор любит Rust 🦀 ".repeat(50);
+        let truncated = builder.truncate(&text, 10);
+        assert!(builder.count_tokens(&truncated) <= 10);
+        assert!(text.starts_with(&truncated));
+        assert_eq!(builder.truncate("short", 10), "short");
+    }
+}
@@ -2,11 +2,14 @@ use chrono::{DateTime, Duration, TimeZone, Utc};
 use log::{info, warn};
 use sqlx::PgPool;
 use teloxide::prelude::*;
-use teloxide::types::{InputFile, MessageId, ReplyParameters, ThreadId, User};
+use teloxide::types::{InputFile, MessageId, ReplyParameters, ThreadId};
 
-use crate::{mention_repository, AppError};
+use crate::chat_settings_repository::ChatSettings;
+use crate::mention_repository::MentionEvent;
+use crate::{chat_history, mention_repository, AppError, MentionParameters};
 
-const STICKERS: &[&str; 5] = &[
+/// Stickers sent along with the mention reply unless the config overrides them.
+pub(crate) const DEFAULT_STICKERS: &[&str] = &[
     "CAACAgEAAx0CTdy33AAD3mQO6sc3rzklybqG4MMI4MLXpXJIAAKCAQACaXoxBT0NGBN6KJNELwQ",
     "CAACAgEAAx0CTdy33AACAQFkF6KoodtDg4KfcPHlUk_7SRFN7QACkQEAAml6MQW86C1JCZcTkS8E",
     "CAACAgEAAx0CTdy33AACAQxkF6Mu7nPaIs9rmMBfXs71BBPxfgACnAEAAml6MQVkU_PxsG8GmS8E",
@@ -20,8 +23,8 @@ pub async fn handle_rust_matched_mention(
     bot: Bot,
     message: Message,
     db_pool: PgPool,
-    req_time_diff: Duration,
-    rust_chat_id: i64,
+    mention_parameters: &MentionParameters,
+    settings: &ChatSettings,
 ) -> Result<(), AppError> {
     let message_date = message.date.timestamp();
     let Some(curr_date) = DateTime::from_timestamp(message_date, 0) else {
@@ -32,46 +35,59 @@ pub async fn handle_rust_matched_mention(
         "rust mention invocation: chat_id: {}, time: {}",
         message.chat.id, curr_date
     );
+    let topic_id = chat_history::topic_thread_id(&message).map(i64::from);
+    let Some(user) = &message.from else {
+        return Ok(());
+    };
+    let chat_id = message.chat.id;
+    let user_id = user.id.0 as i64;
 
-    if let Message {
-        from:
-            Some(User {
-                id: user_id,
-                username: Some(username),
-                ..
-            }),
-        ..
-    } = message
-    {
-        // pool the latest mention time from db
-        let chat_id = message.chat.id;
-        if let Ok(last_mention_time) =
-            mention_repository::lead_earliest_mention_time(&db_pool, chat_id.0)
-                .await
-                .inspect_err(|err| warn!("Can't fetch latest mention time: {err:?}"))
-        {
-            let last_update_time = Utc.from_utc_datetime(&last_mention_time);
-            info!("latest update time: {}", last_update_time);
-
-            let time_diff = curr_date.signed_duration_since(last_update_time);
-            if time_diff > req_time_diff && chat_id.0 != rust_chat_id {
-                let message_ids = (message.id, chat_id, message.thread_id);
-                send_rust_mention_response(bot, message_ids, time_diff, &username).await;
-            }
+    // The event log keeps every incident for the aggregations, whether or not
+    // the user has a username or the counter below can be read.
+    mention_repository::insert_mention_event(
+        &db_pool,
+        &MentionEvent {
+            chat_id: chat_id.0,
+            thread_id: topic_id,
+            user_id,
+            username: user.username.as_deref(),
+            message_id: message.id.0 as i64,
+            created_at: curr_date.naive_utc(),
+        },
+    )
+    .await
+    .inspect_err(|err| warn!("Can't insert mention event: {err:?}"))
+    .ok();
 
-            mention_repository::insert_mention(
-                &db_pool,
-                user_id.0 as i64,
-                &username,
-                message
-                    .thread_id
-                    .map_or_else(|| chat_id.0, |id| id.0 .0 as i64),
-            )
+    let Some(username) = &user.username else {
+        return Ok(());
+    };
+    // pool the latest mention time from db
+    let timer_scope = topic_id.filter(|_| settings.mention_timer_per_topic);
+    let Ok(last_mention_time) =
+        mention_repository::lead_earliest_mention_time(&db_pool, chat_id.0, timer_scope)
             .await
-            .inspect_err(|err| warn!("Can't insert mention: {err:?}"))
-            .ok();
-        }
+            .inspect_err(|err| warn!("Can't fetch latest mention time: {err:?}"))
+    else {
+        return Ok(());
+    };
+    let last_update_time = Utc.from_utc_datetime(&last_mention_time);
+    info!("latest update time: {}", last_update_time);
+
+    let time_diff = curr_date.signed_duration_since(last_update_time);
+    let cooldown = settings
+        .mention_cooldown
+        .unwrap_or(mention_parameters.req_time_diff);
+    if time_diff > cooldown && !settings.silent_counting {
+        let message_ids = (message.id, chat_id, message.thread_id);
+        let sticker_id = fetch_sticker_id(time_diff, &mention_parameters.stickers);
+        send_rust_mention_response(bot, message_ids, time_diff, username, sticker_id).await;
     }
+
+    mention_repository::insert_mention(&db_pool, user_id, username, chat_id.0, topic_id)
+        .await
+        .inspect_err(|err| warn!("Can't insert mention: {err:?}"))
+        .ok();
     Ok(())
 }
 
@@ -80,6 +96,7 @@ async fn send_rust_mention_response(
     message_ids: (MessageId, ChatId, Option<ThreadId>),
     time_diff: Duration,
     username: &str,
+    sticker_id: Option<&str>,
 ) {
     let reply_msg = bot
         .send_message(
@@ -101,42 +118,44 @@ async fn send_rust_mention_response(
             .await
             .inspect_err(|err| warn!("Can't send reply: {err:?}"))
             .ok();
-        bot.send_sticker(
-            message_ids.1,
-            InputFile::file_id(fetch_sticker_id(time_diff)),
-        )
-        .message_thread_id(thread_id)
-        .await
-        .inspect_err(|err| warn!("Can't send a sticker: {err:?}"))
-        .ok();
+        if let Some(sticker_id) = sticker_id {
+            bot.send_sticker(message_ids.1, InputFile::file_id(sticker_id))
+                .message_thread_id(thread_id)
+                .await
+                .inspect_err(|err| warn!("Can't send a sticker: {err:?}"))
+                .ok();
+        }
     } else {
         reply_msg
             .await
             .inspect_err(|err| warn!("Can't send reply: {err:?}"))
             .ok();
-        bot.send_sticker(
-            message_ids.1,
-            InputFile::file_id(fetch_sticker_id(time_diff)),
-        )
-        .await
-        .inspect_err(|err| warn!("Can't send a sticker: {err:?}"))
-        .ok();
+        if let Some(sticker_id) = sticker_id {
+            bot.send_sticker(message_ids.1, InputFile::file_id(sticker_id))
+                .await
+                .inspect_err(|err| warn!("Can't send a sticker: {err:?}"))
+                .ok();
+        }
     }
 }
 
-pub fn fetch_sticker_id(time_diff: Duration) -> &'static str {
-    let sticker_index = time_diff.num_minutes().rem_euclid(STICKERS.len() as i64) as usize;
-    STICKERS[sticker_index]
+pub fn fetch_sticker_id(time_diff: Duration, stickers: &[String]) -> Option<&str> {
+    let sticker_index = time_diff
+        .num_minutes()
+        .rem_euclid(stickers.len().max(1) as i64) as usize;
+    stickers.get(sticker_index).map(String::as_str)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::rust_mention_handler::{fetch_sticker_id, STICKERS};
+    use crate::rust_mention_handler::{fetch_sticker_id, DEFAULT_STICKERS};
     use chrono::Duration;
 
     #[test]
     fn test_fetch_sticker_id() {
-        let sticker_id = fetch_sticker_id(Duration::minutes(7));
-        assert_eq!(sticker_id, STICKERS[2]);
+        let stickers: Vec<String> = DEFAULT_STICKERS.iter().map(|s| s.to_string()).collect();
+        let sticker_id = fetch_sticker_id(Duration::minutes(7), &stickers);
+        assert_eq!(sticker_id, Some(DEFAULT_STICKERS[2]));
+        assert_eq!(fetch_sticker_id(Duration::minutes(7), &[]), None);
     }
 }
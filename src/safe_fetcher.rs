@@ -0,0 +1,346 @@
+use std::error::Error as StdError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use reqwest::{Response, Url};
+
+use crate::error::FetchError;
+
+pub(crate) const DEFAULT_MAX_REDIRECTS: usize = 5;
+/// Articles are well under this; anything bigger is a file, not a page.
+pub(crate) const DEFAULT_MAX_BYTES: u64 = 2 * 1024 * 1024;
+pub(crate) const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Content types a page can be summarized from.
+const ACCEPTED_CONTENT_TYPES: &[&str] = &["text/html", "application/xhtml+xml", "text/plain"];
+const ACCEPT_HEADER: &str = "text/html,application/xhtml+xml,text/plain;q=0.9";
+
+/// Limits on fetching the pages chat members post.
+#[derive(Debug, Clone)]
+pub struct FetchSettings {
+    pub max_redirects: usize,
+    /// Pages over this many bytes are refused, whether they say so up front
+    /// or not.
+    pub max_bytes: u64,
+    /// For every request of a fetch, redirects included.
+    pub timeout: Duration,
+    /// Let the fetcher reach 127.0.0.1 and `::1`, for tests against a local
+    /// server. Other private addresses stay refused.
+    pub allow_loopback: bool,
+}
+
+impl Default for FetchSettings {
+    fn default() -> Self {
+        Self {
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            max_bytes: DEFAULT_MAX_BYTES,
+            timeout: DEFAULT_FETCH_TIMEOUT,
+            allow_loopback: false,
+        }
+    }
+}
+
+/// A fetched page.
+#[derive(Debug, Clone)]
+pub struct Page {
+    /// Where the redirects ended.
+    pub url: Url,
+    /// The media type, without parameters.
+    pub content_type: String,
+    pub text: String,
+}
+
+/// Fetches web pages for anyone in a chat without letting them reach the
+/// bot's own network: only http(s) URLs, only public addresses (every name is
+/// resolved and checked, on every redirect), a bounded number of redirects
+/// and bytes, and only HTML or plain text.
+#[derive(Clone)]
+pub struct SafeFetcher {
+    client: reqwest::Client,
+    settings: FetchSettings,
+}
+
+impl SafeFetcher {
+    pub fn new(settings: FetchSettings) -> Result<Self, FetchError> {
+        let client = reqwest::Client::builder()
+            // Redirects are followed by hand, so each one is checked.
+            .redirect(Policy::none())
+            // A proxy would resolve the names itself, past the checks.
+            .no_proxy()
+            .dns_resolver(Arc::new(PublicResolver {
+                allow_loopback: settings.allow_loopback,
+            }))
+            .timeout(settings.timeout)
+            .build()?;
+        Ok(Self { client, settings })
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Page, FetchError> {
+        let mut url = Url::parse(url).map_err(|_| FetchError::InvalidUrl(url.to_owned()))?;
+        let mut redirects = 0;
+        let response = loop {
+            self.check_url(&url)?;
+            let response = self
+                .client
+                .get(url.clone())
+                .header(ACCEPT, ACCEPT_HEADER)
+                .send()
+                .await
+                .map_err(refusal_or_http)?;
+            let Some(location) = redirect_location(&response) else {
+                break response;
+            };
+            redirects += 1;
+            if redirects > self.settings.max_redirects {
+                return Err(FetchError::TooManyRedirects(self.settings.max_redirects));
+            }
+            url = url
+                .join(&location)
+                .map_err(|_| FetchError::InvalidUrl(location))?;
+        };
+        self.read_page(response.error_for_status()?).await
+    }
+
+    /// Refuse other schemes and private addresses written as IPs; names are
+    /// checked when they are resolved.
+    fn check_url(&self, url: &Url) -> Result<(), FetchError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(FetchError::InvalidUrl(url.to_string()));
+        }
+        let Some(host) = url.host_str() else {
+            return Err(FetchError::InvalidUrl(url.to_string()));
+        };
+        let Ok(ip) = host.trim_matches(['[', ']']).parse::<IpAddr>() else {
+            return Ok(());
+        };
+        if is_allowed(ip, self.settings.allow_loopback) {
+            Ok(())
+        } else {
+            Err(FetchError::PrivateAddress(ip.to_string()))
+        }
+    }
+
+    async fn read_page(&self, mut response: Response) -> Result<Page, FetchError> {
+        let header = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        let content_type = header
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if !ACCEPTED_CONTENT_TYPES.contains(&content_type.as_str()) {
+            return Err(FetchError::UnsupportedContentType(header));
+        }
+        let max_bytes = self.settings.max_bytes;
+        if response
+            .content_length()
+            .is_some_and(|length| length > max_bytes)
+        {
+            return Err(FetchError::TooLarge(max_bytes));
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if (body.len() + chunk.len()) as u64 > max_bytes {
+                return Err(FetchError::TooLarge(max_bytes));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        let encoding = charset(&header)
+            .and_then(|label| Encoding::for_label(label.as_bytes()))
+            .unwrap_or(UTF_8);
+        let (text, _, _) = encoding.decode(&body);
+        Ok(Page {
+            url: response.url().clone(),
+            content_type,
+            text: text.into_owned(),
+        })
+    }
+}
+
+fn redirect_location(response: &Response) -> Option<String> {
+    if !response.status().is_redirection() {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    Some(location.to_owned())
+}
+
+fn charset(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// The resolver's refusal comes back wrapped in the connect error.
+fn refusal_or_http(err: reqwest::Error) -> FetchError {
+    let mut source = err.source();
+    while let Some(cause) = source {
+        if let Some(FetchError::PrivateAddress(address)) = cause.downcast_ref::<FetchError>() {
+            return FetchError::PrivateAddress(address.clone());
+        }
+        source = cause.source();
+    }
+    FetchError::Http(err)
+}
+
+/// Resolves names like the system does, and refuses those with any private
+/// address, so a name can't point the bot at its own network, however many
+/// redirects in.
+struct PublicResolver {
+    allow_loopback: bool,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_owned();
+        let allow_loopback = self.allow_loopback;
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if let Some(private) = addrs
+                .iter()
+                .find(|addr| !is_allowed(addr.ip(), allow_loopback))
+            {
+                let refused = FetchError::PrivateAddress(format!("{host} ({})", private.ip()));
+                return Err(Box::new(refused) as Box<dyn StdError + Send + Sync>);
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_allowed(ip: IpAddr, allow_loopback: bool) -> bool {
+    is_public(ip) || (allow_loopback && ip.is_loopback())
+}
+
+/// Whether `ip` is on the public internet: not loopback, private, link-local
+/// (where cloud metadata lives), shared carrier NAT, multicast, documentation
+/// or otherwise reserved. An IPv4 address inside an IPv6 one is judged as
+/// IPv4.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    let reserved = a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (b == 18 || b == 19))
+        || a >= 240;
+    !(reserved
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_multicast()
+        || ip.is_documentation())
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_public_v4(ip);
+    }
+    let [s0, s1, s2, s3, s4, s5, s6, s7] = ip.segments();
+    let embedded_v4 = Ipv4Addr::from((u32::from(s6) << 16) | u32::from(s7));
+    match (s0, s1) {
+        // NAT64 reaches the IPv4 address it ends with.
+        (0x64, 0xff9b) if [s2, s3, s4, s5] == [0; 4] => return is_public_v4(embedded_v4),
+        // 6to4 carries the IPv4 address right after the prefix.
+        (0x2002, _) => return is_public_v4(Ipv4Addr::from((u32::from(s1) << 16) | u32::from(s2))),
+        // Documentation.
+        (0x2001, 0x0db8) => return false,
+        _ => {}
+    }
+    // Deprecated site-local addresses, fec0::/10.
+    let site_local = s0 & 0xffc0 == 0xfec0;
+    // IPv4-compatible addresses, ::a.b.c.d.
+    let compatible = [s0, s1, s2, s3, s4, s5] == [0; 6];
+    !(site_local
+        || compatible
+        || ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{charset, is_public};
+
+    #[test]
+    fn private_and_reserved_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "224.0.0.1",
+            "192.0.2.1",
+            "198.18.0.1",
+            "::",
+            "::1",
+            "fe80::1",
+            "fdaa:0:1::2",
+            "fec0::1",
+            "ff02::1",
+            "2001:db8::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "::10.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:0a00:0001::1",
+        ] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert!(!is_public(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for ip in [
+            "1.1.1.1",
+            "8.8.8.8",
+            "172.32.0.1",
+            "100.128.0.1",
+            "2a00:1450:4001:82a::200e",
+            "::ffff:93.184.216.34",
+            "64:ff9b::808:808",
+            "2002:0808:0808::1",
+        ] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert!(is_public(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn charset_is_read_from_the_content_type() {
+        assert_eq!(
+            charset("text/html; charset=\"windows-1251\""),
+            Some("windows-1251")
+        );
+        assert_eq!(charset("text/plain;Charset=UTF-8"), Some("UTF-8"));
+        assert_eq!(charset("text/html"), None);
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+
+use crate::error::LlmError;
+
+pub const DEFAULT_TRANSCRIPTION_URL: &str = "https://api.openai.com/v1/audio/transcriptions";
+/// whisper.cpp's `server` example.
+pub const DEFAULT_WHISPER_URL: &str = "http://localhost:8080/inference";
+pub(crate) const DEFAULT_TRANSCRIPTION_MODEL: &str = "whisper-1";
+/// Longer recordings are left alone: they cost the most and are rarely meant
+/// for the bot.
+pub(crate) const MAX_RECORDING_SECS: u64 = 300;
+
+/// Wire format spoken by a speech-to-text backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechKind {
+    /// OpenAI `/audio/transcriptions` and compatible servers.
+    OpenAi,
+    /// A local whisper.cpp server's `/inference`.
+    Whisper,
+}
+
+/// Where voice messages are transcribed.
+#[derive(Debug, Clone)]
+pub struct SpeechSettings {
+    pub kind: SpeechKind,
+    pub base_url: Arc<str>,
+    /// Sent when set. Without one the OpenAI kind uses `CHAT_GPT_API_TOKEN`.
+    pub api_key: Option<Arc<str>>,
+    /// Ignored by whisper.cpp, which serves the model it was started with.
+    pub model: Arc<str>,
+    /// ISO-639-1 hint; the backend detects the language when unset.
+    pub language: Option<Arc<str>>,
+    pub max_duration: Duration,
+}
+
+impl Default for SpeechSettings {
+    fn default() -> Self {
+        Self {
+            kind: SpeechKind::OpenAi,
+            base_url: Arc::from(DEFAULT_TRANSCRIPTION_URL),
+            api_key: None,
+            model: Arc::from(DEFAULT_TRANSCRIPTION_MODEL),
+            language: None,
+            max_duration: Duration::from_secs(MAX_RECORDING_SECS),
+        }
+    }
+}
+
+impl SpeechSettings {
+    /// The backend, authenticated with `api_key` or else `fallback_api_key`
+    /// for the OpenAI kind.
+    pub fn build(
+        &self,
+        http_client: reqwest::Client,
+        fallback_api_key: &Arc<str>,
+        timeout: Duration,
+    ) -> Box<dyn SpeechToText> {
+        let api_key = match (&self.api_key, self.kind) {
+            (Some(api_key), _) => Some(Arc::clone(api_key)),
+            (None, SpeechKind::OpenAi) => Some(Arc::clone(fallback_api_key)),
+            (None, SpeechKind::Whisper) => None,
+        };
+        let client = TranscriptionClient {
+            http_client,
+            base_url: Arc::clone(&self.base_url),
+            api_key,
+            timeout,
+            language: self.language.clone(),
+        };
+        match self.kind {
+            SpeechKind::OpenAi => Box::new(OpenAiTranscriber {
+                client,
+                model: Arc::clone(&self.model),
+            }),
+            SpeechKind::Whisper => Box::new(WhisperTranscriber(client)),
+        }
+    }
+}
+
+/// A recording to transcribe.
+#[derive(Clone)]
+pub struct Recording {
+    pub file_name: String,
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+/// A speech-to-text backend.
+#[async_trait]
+pub trait SpeechToText: Send + Sync + Debug {
+    async fn transcribe(&self, recording: Recording) -> Result<String, LlmError>;
+}
+
+#[derive(Debug)]
+struct TranscriptionClient {
+    http_client: reqwest::Client,
+    base_url: Arc<str>,
+    api_key: Option<Arc<str>>,
+    timeout: Duration,
+    language: Option<Arc<str>>,
+}
+
+/// Both backends answer `{"text": ...}` when asked for JSON.
+#[derive(Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
+
+impl TranscriptionClient {
+    /// The recording as the `file` part, plus the language hint.
+    fn form(&self, recording: Recording) -> Result<Form, LlmError> {
+        let file = Part::bytes(recording.data)
+            .file_name(recording.file_name)
+            .mime_str(&recording.media_type)
+            .map_err(|err| LlmError::BadRequest {
+                status: 0,
+                message: err.to_string(),
+            })?;
+        let form = Form::new()
+            .part("file", file)
+            .text("response_format", "json");
+        Ok(match &self.language {
+            Some(language) => form.text("language", language.to_string()),
+            None => form,
+        })
+    }
+
+    async fn send(&self, form: Form) -> Result<String, LlmError> {
+        let mut request = self
+            .http_client
+            .post(self.base_url.as_ref())
+            .timeout(self.timeout)
+            .multipart(form);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(LlmError::from_status(status.as_u16(), None, &body));
+        }
+        let response: TranscriptionResponse = response.json().await?;
+        Ok(response.text.trim().to_owned())
+    }
+}
+
+#[derive(Debug)]
+pub struct OpenAiTranscriber {
+    client: TranscriptionClient,
+    model: Arc<str>,
+}
+
+#[async_trait]
+impl SpeechToText for OpenAiTranscriber {
+    async fn transcribe(&self, recording: Recording) -> Result<String, LlmError> {
+        let form = self
+            .client
+            .form(recording)?
+            .text("model", self.model.to_string());
+        self.client.send(form).await
+    }
+}
+
+#[derive(Debug)]
+pub struct WhisperTranscriber(TranscriptionClient);
+
+#[async_trait]
+impl SpeechToText for WhisperTranscriber {
+    async fn transcribe(&self, recording: Recording) -> Result<String, LlmError> {
+        // Greedy decoding: the most likely words, not creative ones.
+        let form = self.0.form(recording)?.text("temperature", "0");
+        self.0.send(form).await
+    }
+}
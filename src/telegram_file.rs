@@ -0,0 +1,16 @@
+use teloxide::net::Download;
+use teloxide::prelude::*;
+use teloxide::RequestError;
+
+use crate::AppError;
+
+/// The content of the Telegram file `file_id`. Bots may download files of up
+/// to 20 MB.
+pub async fn download(bot: &Bot, file_id: &str) -> Result<Vec<u8>, AppError> {
+    let file = bot.get_file(file_id).await?;
+    let mut data = Vec::new();
+    bot.download_file(&file.path, &mut data)
+        .await
+        .map_err(RequestError::from)?;
+    Ok(data)
+}
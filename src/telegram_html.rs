@@ -0,0 +1,322 @@
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use teloxide::{ApiError, RequestError};
+
+/// Link schemes Telegram accepts in `<a href>`; other links keep only their text.
+const LINK_SCHEMES: &[&str] = &["http://", "https://", "tg://", "mailto:"];
+const BULLET: &str = "• ";
+const RULE: &str = "———";
+
+/// Render the Markdown a model answers with as Telegram HTML: emphasis, code
+/// and links become tags, headings turn bold, lists get bullets or numbers,
+/// and everything else is escaped text. Telegram HTML has no nested
+/// blockquotes, so only the outermost one is kept.
+pub fn from_markdown(markdown: &str) -> String {
+    let mut renderer = Renderer::default();
+    for event in Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH) {
+        renderer.event(event);
+    }
+    renderer.out.trim_end().to_owned()
+}
+
+/// Telegram refused the markup of a message, so it is better sent as plain
+/// text.
+pub fn is_rejected(err: &RequestError) -> bool {
+    matches!(
+        err,
+        RequestError::Api(ApiError::CantParseEntities(_) | ApiError::CantParseUrl)
+    )
+}
+
+#[derive(Default)]
+struct Renderer {
+    out: String,
+    /// Open lists, innermost last, with the number of the next item of the
+    /// ordered ones.
+    lists: Vec<Option<u64>>,
+    /// Whether each open link made it into the output as `<a>`.
+    links: Vec<bool>,
+    quote_depth: usize,
+    /// The open code block was given a `<code>` for its language.
+    code_language: bool,
+    /// Nothing written since a blockquote or list item opened, so the next
+    /// block needs no separation.
+    fresh: bool,
+}
+
+impl Renderer {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::Html(text) | Event::InlineHtml(text) => self.text(&text),
+            Event::Code(code) | Event::InlineMath(code) | Event::DisplayMath(code) => {
+                self.write("<code>");
+                self.text(&code);
+                self.write("</code>");
+            }
+            Event::FootnoteReference(label) => self.text(&format!("[{label}]")),
+            Event::SoftBreak | Event::HardBreak => self.write("\n"),
+            Event::Rule => {
+                self.block_break();
+                self.write(RULE);
+            }
+            Event::TaskListMarker(done) => self.write(if done { "☑ " } else { "☐ " }),
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::HtmlBlock => self.block_break(),
+            Tag::Heading { .. } => {
+                self.block_break();
+                self.write("<b>");
+            }
+            Tag::BlockQuote(_) => {
+                self.block_break();
+                if self.quote_depth == 0 {
+                    self.write("<blockquote>");
+                }
+                self.quote_depth += 1;
+                self.fresh = true;
+            }
+            Tag::CodeBlock(kind) => {
+                self.block_break();
+                let language = match &kind {
+                    CodeBlockKind::Fenced(info) => code_language(info),
+                    CodeBlockKind::Indented => "",
+                };
+                self.code_language = !language.is_empty();
+                if self.code_language {
+                    self.write(&format!("<pre><code class=\"language-{language}\">"));
+                } else {
+                    self.write("<pre>");
+                }
+            }
+            Tag::List(first_number) => {
+                if self.lists.is_empty() {
+                    self.block_break();
+                }
+                self.lists.push(first_number);
+            }
+            Tag::Item => {
+                self.new_line();
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{indent}{}. ", *number - 1)
+                    }
+                    _ => format!("{indent}{BULLET}"),
+                };
+                self.write(&marker);
+                self.fresh = true;
+            }
+            Tag::Emphasis => self.write("<i>"),
+            Tag::Strong => self.write("<b>"),
+            Tag::Strikethrough => self.write("<s>"),
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                let linked = LINK_SCHEMES
+                    .iter()
+                    .any(|scheme| dest_url.starts_with(scheme));
+                if linked {
+                    self.write("<a href=\"");
+                    escape_into(&mut self.out, &dest_url);
+                    self.write("\">");
+                }
+                self.links.push(linked);
+            }
+            // Parsed only with options that are not switched on.
+            Tag::FootnoteDefinition(_)
+            | Tag::DefinitionList
+            | Tag::DefinitionListTitle
+            | Tag::DefinitionListDefinition
+            | Tag::Table(_)
+            | Tag::TableHead
+            | Tag::TableRow
+            | Tag::TableCell
+            | Tag::Superscript
+            | Tag::Subscript
+            | Tag::MetadataBlock(_) => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Heading(_) => self.write("</b>"),
+            TagEnd::BlockQuote(_) => {
+                self.quote_depth = self.quote_depth.saturating_sub(1);
+                if self.quote_depth == 0 {
+                    self.trim_trailing_newlines();
+                    self.write("</blockquote>");
+                }
+            }
+            TagEnd::CodeBlock => {
+                self.trim_trailing_newlines();
+                self.write(if self.code_language {
+                    "</code></pre>"
+                } else {
+                    "</pre>"
+                });
+            }
+            TagEnd::HtmlBlock => self.trim_trailing_newlines(),
+            TagEnd::List(_) => {
+                self.lists.pop();
+            }
+            TagEnd::Emphasis => self.write("</i>"),
+            TagEnd::Strong => self.write("</b>"),
+            TagEnd::Strikethrough => self.write("</s>"),
+            TagEnd::Link | TagEnd::Image => {
+                let linked = self.links.pop().unwrap_or_default();
+                if linked {
+                    self.write("</a>");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        self.fresh = false;
+        escape_into(&mut self.out, text);
+    }
+
+    fn write(&mut self, markup: &str) {
+        self.fresh = false;
+        self.out.push_str(markup);
+    }
+
+    fn new_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// Separate the next block from what came before: a blank line between
+    /// top-level blocks, a new indented line inside a list item.
+    fn block_break(&mut self) {
+        if self.fresh || self.out.is_empty() {
+            return;
+        }
+        self.new_line();
+        if self.lists.is_empty() {
+            if !self.out.ends_with("\n\n") {
+                self.out.push('\n');
+            }
+        } else {
+            self.out.push_str(&"  ".repeat(self.lists.len()));
+        }
+    }
+
+    fn trim_trailing_newlines(&mut self) {
+        let trimmed = self.out.trim_end_matches('\n').len();
+        self.out.truncate(trimmed);
+    }
+}
+
+/// The language of a fence's info string, as far as it is safe in a class
+/// name: `rust,ignore` is `rust`.
+fn code_language(info: &str) -> &str {
+    let end = info
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "+-_#.".contains(c)))
+        .unwrap_or(info.len());
+    &info[..end]
+}
+
+fn escape_into(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::{ApiError, RequestError};
+
+    use super::{from_markdown, is_rejected};
+
+    #[test]
+    fn inline_markup_becomes_tags_and_text_is_escaped() {
+        assert_eq!(
+            from_markdown("**Vec<T>** is *not* `&mut [T]` & ~~slow~~"),
+            "<b>Vec&lt;T&gt;</b> is <i>not</i> <code>&amp;mut [T]</code> &amp; <s>slow</s>"
+        );
+        assert_eq!(
+            from_markdown("use <Box<dyn Error>> or <br>"),
+            "use &lt;Box&lt;dyn Error&gt;&gt; or &lt;br&gt;"
+        );
+        assert_eq!(
+            from_markdown("2 * 3 * 4 and snake_case_name"),
+            "2 * 3 * 4 and snake_case_name"
+        );
+    }
+
+    #[test]
+    fn code_blocks_keep_their_language_and_contents() {
+        let markdown = "Смотри:\n\n```rust,ignore\nfn f<'a>(x: &'a str) -> bool {\n    x == \"**\"\n}\n```\n\n    indented <code>\n";
+        assert_eq!(
+            from_markdown(markdown),
+            "Смотри:\n\n<pre><code class=\"language-rust\">fn f&lt;'a&gt;(x: &amp;'a str) -&gt; bool {\n    x == &quot;**&quot;\n}</code></pre>\n\n<pre>indented &lt;code&gt;</pre>"
+        );
+        assert_eq!(
+            from_markdown("```\"><script>\nx\n```"),
+            "<pre>x</pre>",
+            "an info string cannot break out of the class attribute"
+        );
+        assert_eq!(
+            from_markdown("```rust\nlet x = 1;"),
+            "<pre><code class=\"language-rust\">let x = 1;</code></pre>",
+            "a fence still streaming in is closed"
+        );
+    }
+
+    #[test]
+    fn blocks_are_separated_and_lists_are_marked() {
+        let markdown = "# Итог\n\nТекст\nдальше.\n\n- один\n- два\n  1. first\n  2. second\n\n3. три\n4. четыре\n\n---\n> цитата\n>> вложенная";
+        assert_eq!(
+            from_markdown(markdown),
+            "<b>Итог</b>\n\nТекст\nдальше.\n\n• один\n• два\n  1. first\n  2. second\n\n3. три\n4. четыре\n\n———\n\n<blockquote>цитата\n\nвложенная</blockquote>"
+        );
+        assert_eq!(
+            from_markdown("- loose\n\n  more\n- items"),
+            "• loose\n  more\n• items"
+        );
+    }
+
+    #[test]
+    fn only_web_links_are_kept() {
+        assert_eq!(
+            from_markdown(
+                "[docs](https://doc.rust-lang.org/?a=1&b=\"2\") and [bad](javascript:alert(1))"
+            ),
+            "<a href=\"https://doc.rust-lang.org/?a=1&amp;b=&quot;2&quot;\">docs</a> and bad"
+        );
+        assert_eq!(
+            from_markdown("![crab](https://rustacean.net/crab.png)"),
+            "<a href=\"https://rustacean.net/crab.png\">crab</a>"
+        );
+    }
+
+    #[test]
+    fn plain_text_is_left_alone() {
+        assert_eq!(from_markdown("Привет, мир!"), "Привет, мир!");
+        assert_eq!(from_markdown(""), "");
+        assert_eq!(from_markdown("…"), "…");
+    }
+
+    #[test]
+    fn markup_errors_are_recognized() {
+        let rejected = RequestError::Api(ApiError::CantParseEntities(
+            "Bad Request: can't parse entities: unexpected end tag".to_owned(),
+        ));
+        assert!(is_rejected(&rejected));
+        assert!(!is_rejected(&RequestError::Api(
+            ApiError::MessageTextIsEmpty
+        )));
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use log::debug;
+use teloxide::prelude::*;
+use teloxide::types::{ChatAction, ThreadId};
+use tokio::task::JoinHandle;
+
+/// Telegram shows a chat action for about five seconds; refresh it before it
+/// fades.
+pub const TYPING_REFRESH_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Shows "typing…" in a chat, or in one forum topic of it, for as long as the
+/// guard lives. Hold it across slow work and drop it once the reply is sent;
+/// an early return or `?` drops it as well.
+#[derive(Debug)]
+pub struct TypingIndicator(JoinHandle<()>);
+
+impl TypingIndicator {
+    pub fn start(bot: &Bot, chat_id: ChatId, thread_id: Option<ThreadId>) -> Self {
+        Self::with_interval(bot, chat_id, thread_id, TYPING_REFRESH_INTERVAL)
+    }
+
+    pub fn with_interval(
+        bot: &Bot,
+        chat_id: ChatId,
+        thread_id: Option<ThreadId>,
+        interval: Duration,
+    ) -> Self {
+        let bot = bot.clone();
+        Self(tokio::spawn(async move {
+            loop {
+                let action = bot.send_chat_action(chat_id, ChatAction::Typing);
+                let sent = match thread_id {
+                    Some(thread_id) => action.message_thread_id(thread_id).await,
+                    None => action.await,
+                };
+                // Only cosmetic: the handler carries on either way.
+                if let Err(err) = sent {
+                    debug!("Can't send typing action to {chat_id}: {err:?}");
+                }
+                tokio::time::sleep(interval).await;
+            }
+        }))
+    }
+}
+
+impl Drop for TypingIndicator {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
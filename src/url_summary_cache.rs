@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use log::info;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, RedisResult};
+
+/// Summaries outlive the news they are about by a day or two.
+pub(crate) const SUMMARY_CACHE_TTL_HOURS: u64 = 72;
+
+fn summary_key(canonical_url: &str, prompt_version: u32) -> String {
+    format!("url_summary:v{prompt_version}:{canonical_url}")
+}
+
+fn post_key(chat_id: i64, canonical_url: &str) -> String {
+    format!("url_summary:chat:{chat_id}:{canonical_url}")
+}
+
+/// The summary made of the page with the prompt of `prompt_version`.
+pub async fn cached_summary(
+    connection_manager: &mut ConnectionManager,
+    canonical_url: &str,
+    prompt_version: u32,
+) -> RedisResult<Option<String>> {
+    connection_manager
+        .get(summary_key(canonical_url, prompt_version))
+        .await
+}
+
+/// Keep `summary` for each of the URLs of one page, the one posted and the
+/// one its redirects ended at.
+pub async fn cache_summary(
+    connection_manager: &mut ConnectionManager,
+    canonical_urls: &[&str],
+    prompt_version: u32,
+    summary: &str,
+    ttl: Duration,
+) -> RedisResult<()> {
+    info!("caching url summary for {canonical_urls:?}");
+    let mut pipe = redis::pipe();
+    for canonical_url in canonical_urls {
+        pipe.set_ex(
+            summary_key(canonical_url, prompt_version),
+            summary,
+            ttl.as_secs(),
+        )
+        .ignore();
+    }
+    pipe.query_async(connection_manager).await
+}
+
+/// The bot message that summarized the page in the chat before.
+pub async fn earlier_post(
+    connection_manager: &mut ConnectionManager,
+    chat_id: i64,
+    canonical_url: &str,
+) -> RedisResult<Option<i32>> {
+    connection_manager
+        .get(post_key(chat_id, canonical_url))
+        .await
+}
+
+pub async fn remember_post(
+    connection_manager: &mut ConnectionManager,
+    chat_id: i64,
+    canonical_urls: &[&str],
+    message_id: i32,
+    ttl: Duration,
+) -> RedisResult<()> {
+    let mut pipe = redis::pipe();
+    for canonical_url in canonical_urls {
+        pipe.set_ex(post_key(chat_id, canonical_url), message_id, ttl.as_secs())
+            .ignore();
+    }
+    pipe.query_async(connection_manager).await
+}
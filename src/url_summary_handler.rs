@@ -1,17 +1,30 @@
+use crate::article_extractor::Article;
+use crate::gpt_quota::Charge;
 use crate::gpt_service::ChatMessage;
 use crate::gpt_service::ChatMessageRole::{System, User};
-use crate::{gpt_service, AppError, GptParameters};
-use log::{info, warn};
+use crate::llm_usage::{Caller, GptFeature};
+use crate::typing_indicator::TypingIndicator;
+use crate::{
+    article_extractor, canonical_url, chat_gpt_handler, gpt_quota, gpt_service, url_summary_cache,
+    AppError, GptParameters,
+};
+use log::{error, info, warn};
 use regex::Regex;
-use reqwest::Client;
-use std::time::Duration;
 use teloxide::prelude::*;
 use teloxide::types::MediaKind::Text;
 use teloxide::types::MessageEntityKind::TextLink;
 use teloxide::types::MessageKind::Common;
-use teloxide::types::{MediaText, MessageCommon, ReplyParameters};
+use teloxide::types::{MediaText, MessageCommon, MessageId};
 
-const ARTICLE_EXTRACTION_TIMEOUT: Duration = Duration::from_secs(30);
+/// Upper bound on the article text sent for summarization; the TLDR only
+/// needs the gist, and whole pages can run to tens of thousands of tokens.
+const ARTICLE_MAX_TOKENS: usize = 6_000;
+/// Part of the summary cache key: bump it along with the prompt so summaries
+/// made with the old one are not reused.
+const SUMMARY_PROMPT_VERSION: u32 = 2;
+/// Pages with less text than this are not articles worth a TLDR.
+const ARTICLE_MIN_CHARS: usize = 1000;
+const ALREADY_SUMMARIZED: &str = "Эту ссылку уже пересказывали выше";
 const ARTICLE_SUMMARY_SYSTEM_CONTEXT: &str = "Проанализируй статью и дай краткое содержание. Применяй юмор в анализе. Ответ должен быть структурированным, разбитым на пункты и содержать максимум 300 симвалов.";
 
 pub async fn handle_url_summary(
@@ -19,11 +32,12 @@ pub async fn handle_url_summary(
     msg: Message,
     url_regex: Regex,
     gpt_parameters: &GptParameters,
+    charge: Charge,
 ) -> Result<(), AppError> {
     let Common(MessageCommon {
         media_kind: Text(media_text),
         ..
-    }) = msg.kind
+    }) = &msg.kind
     else {
         return Ok(());
     };
@@ -37,39 +51,185 @@ pub async fn handle_url_summary(
     let url = url_regex
         .find(msg_text)
         .map(|m| m.as_str().to_string())
-        .or(find_link(&media_text));
+        .or(find_link(media_text));
     let Some(url) = url else {
         info!("No URL found in message: {}", msg_text);
         return Ok(());
     };
 
-    let content = get_content_call(&gpt_parameters.http_client, &url).await?;
-    let clean_content = html2text::from_read(content.as_bytes(), 120)
-        .map_err(|err| AppError::BadInput(format!("failed to parse article HTML: {err}")))?;
-    // Check if the content is long enough to summarize
-    if clean_content.len() < 1000 {
+    let cache_ttl = gpt_parameters.settings.summary_cache_ttl;
+    let canonical = canonical_url::canonicalize(&url).filter(|_| !cache_ttl.is_zero());
+    let mut redis_cm = gpt_parameters.redis_connection_manager.clone();
+    if let Some(canonical) = &canonical {
+        let earlier = url_summary_cache::earlier_post(&mut redis_cm, chat_id.0, canonical)
+            .await
+            .inspect_err(|err| warn!("Can't read url summary posts: {err:?}"))
+            .ok()
+            .flatten();
+        if let Some(earlier) = earlier {
+            reply_already_summarized(&bot, &msg, MessageId(earlier)).await;
+            return Ok(());
+        }
+        let cached =
+            url_summary_cache::cached_summary(&mut redis_cm, canonical, SUMMARY_PROMPT_VERSION)
+                .await
+                .inspect_err(|err| warn!("Can't read url summary cache: {err:?}"))
+                .ok()
+                .flatten();
+        if let Some(summary) = cached {
+            info!("url summary cache hit: {canonical}");
+            post_summary(&bot, &msg, &summary, &[canonical], gpt_parameters).await;
+            return Ok(());
+        }
+    }
+
+    let typing = TypingIndicator::start(&bot, chat_id, msg.thread_id);
+    let page = gpt_parameters.url_fetcher.fetch(&url).await?;
+    let page_url = page.url;
+    // Parsing up to 2 MB of HTML is CPU work the runtime threads shouldn't do.
+    let article =
+        tokio::task::spawn_blocking(move || article_extractor::extract(&page.text)).await?;
+    info!(
+        "extracted article from {}: {:?}, {} characters",
+        page_url,
+        article.title,
+        article.body.chars().count()
+    );
+    if article.body.chars().count() < ARTICLE_MIN_CHARS {
         return Ok(());
     }
-    let summary = get_gpt_summary(gpt_parameters, chat_id, clean_content).await;
+    let prompt_builder = gpt_parameters.prompt_builder();
+    let article_budget = ARTICLE_MAX_TOKENS.min(prompt_builder.prompt_budget());
+    let article_text = prompt_builder.truncate(&article.body, article_budget);
+    // Links are summarized unasked, so going over quota is not worth a reply.
+    if gpt_quota::check_message(gpt_parameters, &msg, charge)
+        .await
+        .is_some()
+    {
+        return Ok(());
+    }
+    let summary = get_gpt_summary(
+        gpt_parameters,
+        Caller::of(&msg, GptFeature::UrlSummary),
+        article_text,
+    )
+    .await;
 
-    let reply_msg = bot
-        .send_message(chat_id, format!("TLDR:\n{}", summary))
-        .reply_parameters(ReplyParameters::new(msg.id));
-    if let Some(thread_id) = msg.thread_id {
-        reply_msg
-            .message_thread_id(thread_id)
-            .await
-            .inspect_err(|err| warn!("Can't send reply: {err:?}"))
-            .ok();
-    } else {
-        reply_msg
-            .await
-            .inspect_err(|err| warn!("Can't send reply: {err:?}"))
-            .ok();
+    match summary {
+        Ok(summary) => {
+            let summary = with_heading(&article, &summary);
+            // A short link is known by the page it redirects to as well.
+            let redirected = canonical
+                .as_ref()
+                .and_then(|_| canonical_url::canonicalize(page_url.as_str()));
+            let mut canonical_urls: Vec<&str> = canonical
+                .iter()
+                .chain(&redirected)
+                .map(String::as_str)
+                .collect();
+            canonical_urls.dedup();
+            if !canonical_urls.is_empty() {
+                url_summary_cache::cache_summary(
+                    &mut redis_cm,
+                    &canonical_urls,
+                    SUMMARY_PROMPT_VERSION,
+                    &summary,
+                    cache_ttl,
+                )
+                .await
+                .inspect_err(|err| warn!("Can't cache url summary: {err:?}"))
+                .ok();
+            }
+            post_summary(&bot, &msg, &summary, &canonical_urls, gpt_parameters).await;
+        }
+        Err(err) => {
+            error!("Can't summarize {url}: {err}");
+            let fallback = gpt_service::fallback_message(&err).content;
+            post_summary(&bot, &msg, &fallback, &[], gpt_parameters).await;
+        }
     }
+    drop(typing);
     Ok(())
 }
 
+/// The article's title and byline above its summary, so the chat sees what
+/// was read before what it says.
+fn with_heading(article: &Article, summary: &str) -> String {
+    let Some(title) = &article.title else {
+        return summary.to_owned();
+    };
+    let mut heading = format!("**{}**", escape_markdown(title));
+    if let Some(byline) = article.byline() {
+        heading.push('\n');
+        heading.push_str(&escape_markdown(&byline));
+    }
+    format!("{heading}\n\n{summary}")
+}
+
+/// Backslash the characters Markdown would read as markup in page text.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '_' | '`' | '~' | '[' | ']' | '<' | '>' | '#'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Post the TLDR and remember it as the chat's summary of `canonical_urls`.
+async fn post_summary(
+    bot: &Bot,
+    msg: &Message,
+    summary: &str,
+    canonical_urls: &[&str],
+    gpt_parameters: &GptParameters,
+) {
+    let sent = chat_gpt_handler::send_gpt_reply(
+        bot,
+        msg.chat.id,
+        msg.id,
+        msg.thread_id,
+        &format!("TLDR:\n{summary}"),
+    )
+    .await;
+    let first = match sent {
+        Ok(sent) => sent.into_iter().next(),
+        Err(err) => {
+            warn!("Can't send reply: {err:?}");
+            None
+        }
+    };
+    if let (Some(first), false) = (first, canonical_urls.is_empty()) {
+        url_summary_cache::remember_post(
+            &mut gpt_parameters.redis_connection_manager.clone(),
+            msg.chat.id.0,
+            canonical_urls,
+            first.id.0,
+            gpt_parameters.settings.summary_cache_ttl,
+        )
+        .await
+        .inspect_err(|err| warn!("Can't remember url summary post: {err:?}"))
+        .ok();
+    }
+}
+
+/// Point at the TLDR the chat already has instead of posting another one.
+async fn reply_already_summarized(bot: &Bot, msg: &Message, earlier: MessageId) {
+    let reply = match Message::url_of(msg.chat.id, msg.chat.username(), earlier) {
+        Some(link) => format!("[{ALREADY_SUMMARIZED}]({link})."),
+        None => format!("{ALREADY_SUMMARIZED}."),
+    };
+    chat_gpt_handler::send_gpt_reply(bot, msg.chat.id, msg.id, msg.thread_id, &reply)
+        .await
+        .inspect_err(|err| warn!("Can't send reply: {err:?}"))
+        .ok();
+}
+
 fn find_link(media_text: &MediaText) -> Option<String> {
     media_text
         .entities
@@ -84,18 +244,11 @@ fn find_link(media_text: &MediaText) -> Option<String> {
         .find_map(|el| el)
 }
 
-async fn get_content_call(client: &Client, url: &str) -> Result<String, AppError> {
-    let response = client
-        .get(url)
-        .timeout(ARTICLE_EXTRACTION_TIMEOUT)
-        .send()
-        .await?
-        .text()
-        .await?;
-    Ok(response)
-}
-
-pub async fn get_gpt_summary(params: &GptParameters, chat_id: ChatId, message: String) -> String {
+pub async fn get_gpt_summary(
+    params: &GptParameters,
+    caller: Caller,
+    message: String,
+) -> Result<String, AppError> {
     let system_message = ChatMessage {
         role: System,
         content: ARTICLE_SUMMARY_SYSTEM_CONTEXT.to_string(),
@@ -106,7 +259,32 @@ pub async fn get_gpt_summary(params: &GptParameters, chat_id: ChatId, message: S
     };
 
     let context = Vec::from([system_message, content_message]);
-    gpt_service::chat_gpt_call(params, chat_id, context)
-        .await
-        .content
+    Ok(gpt_service::try_chat_gpt_call(params, caller, context)
+        .await?
+        .content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::with_heading;
+    use crate::article_extractor::Article;
+
+    #[test]
+    fn summary_is_headed_with_the_title_and_byline() {
+        let article = Article {
+            title: Some("Vec<T> *is* fast".to_owned()),
+            site_name: Some("Ferris_Writes".to_owned()),
+            published: Some("2025-02-20".to_owned()),
+            ..Article::default()
+        };
+        assert_eq!(
+            with_heading(&article, "- пункт"),
+            "**Vec\\<T\\> \\*is\\* fast**\nFerris\\_Writes · 2025-02-20\n\n- пункт"
+        );
+        assert_eq!(
+            crate::telegram_html::from_markdown(&with_heading(&article, "итог")),
+            "<b>Vec&lt;T&gt; *is* fast</b>\nFerris_Writes · 2025-02-20\n\nитог"
+        );
+        assert_eq!(with_heading(&Article::default(), "итог"), "итог");
+    }
 }
@@ -0,0 +1,104 @@
+use sqlx::postgres::PgQueryResult;
+use sqlx::types::chrono::{NaiveDate, NaiveDateTime};
+use sqlx::{Error, PgPool};
+
+/// One LLM call, as appended to `llm_usage`.
+pub struct UsageEntry<'a> {
+    pub chat_id: i64,
+    pub user_id: Option<i64>,
+    pub persona_id: Option<&'a str>,
+    pub handler: &'a str,
+    pub provider: &'a str,
+    pub model: &'a str,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    /// Millionths of a US dollar.
+    pub cost_micros: i64,
+    /// The tokens were counted by the bot, not reported by the provider.
+    pub estimated: bool,
+    pub created_at: NaiveDateTime,
+}
+
+pub async fn insert_usage(pool: &PgPool, entry: &UsageEntry<'_>) -> Result<PgQueryResult, Error> {
+    sqlx::query(
+        "INSERT INTO llm_usage(chat_id, user_id, persona_id, handler, provider, model, \
+                prompt_tokens, completion_tokens, cost_micros, estimated, created_at) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+    )
+    .bind(entry.chat_id)
+    .bind(entry.user_id)
+    .bind(entry.persona_id)
+    .bind(entry.handler)
+    .bind(entry.provider)
+    .bind(entry.model)
+    .bind(entry.prompt_tokens)
+    .bind(entry.completion_tokens)
+    .bind(entry.cost_micros)
+    .bind(entry.estimated)
+    .bind(entry.created_at)
+    .execute(pool)
+    .await
+}
+
+/// LLM calls of one chat, handler, persona and model over a month.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageRollup {
+    pub chat_id: i64,
+    pub handler: String,
+    pub persona_id: Option<String>,
+    pub model: String,
+    pub calls: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub cost_micros: i64,
+}
+
+type RollupRow = (i64, String, Option<String>, String, i64, i64, i64, i64);
+
+/// Usage in the calendar month starting at `month`, of one chat or of every
+/// chat when `chat_id` is `None`, costliest first.
+pub async fn monthly_rollup(
+    pool: &PgPool,
+    month: NaiveDate,
+    chat_id: Option<i64>,
+) -> Result<Vec<UsageRollup>, Error> {
+    sqlx::query_as(
+        "SELECT chat_id, handler, persona_id, model, COUNT(*), \
+                       SUM(prompt_tokens)::BIGINT, SUM(completion_tokens)::BIGINT, \
+                       SUM(cost_micros)::BIGINT AS cost \
+                FROM llm_usage \
+                WHERE created_at >= $1::DATE AND created_at < $1::DATE + INTERVAL '1 month' \
+                    AND ($2::BIGINT IS NULL OR chat_id = $2) \
+                GROUP BY chat_id, handler, persona_id, model \
+                ORDER BY cost DESC, COUNT(*) DESC, chat_id, handler",
+    )
+    .bind(month)
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await
+    .map(|rows: Vec<RollupRow>| {
+        rows.into_iter()
+            .map(
+                |(
+                    chat_id,
+                    handler,
+                    persona_id,
+                    model,
+                    calls,
+                    prompt_tokens,
+                    completion_tokens,
+                    cost_micros,
+                )| UsageRollup {
+                    chat_id,
+                    handler,
+                    persona_id,
+                    model,
+                    calls,
+                    prompt_tokens,
+                    completion_tokens,
+                    cost_micros,
+                },
+            )
+            .collect()
+    })
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use teloxide::prelude::*;
+use teloxide::types::PhotoSize;
+
+use crate::{telegram_file, AppError};
+
+/// Prefixes of the model names that can look at pictures;
+/// `gpt.vision_models` replaces them.
+pub(crate) const VISION_MODELS: &[&str] = &[
+    "gpt-4o",
+    "gpt-4.1",
+    "gpt-5",
+    "claude",
+    "llava",
+    "llama3.2-vision",
+];
+/// Anthropic rejects images over 5 MB; Telegram photos stay well below.
+pub(crate) const MAX_IMAGE_BYTES: u32 = 5 * 1024 * 1024;
+/// Telegram re-encodes every photo as JPEG.
+const PHOTO_MEDIA_TYPE: &str = "image/jpeg";
+/// Document types every vision provider accepts.
+const IMAGE_MEDIA_TYPES: &[&str] = &["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// A picture shown to the model along with a question.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Image {
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn base64(&self) -> String {
+        STANDARD.encode(&self.data)
+    }
+
+    /// The picture inlined as a `data:` URL, the way OpenAI takes it.
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.media_type, self.base64())
+    }
+}
+
+// Requests are logged; the bytes of the picture would drown the log.
+impl fmt::Debug for Image {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Image")
+            .field("media_type", &self.media_type)
+            .field("bytes", &self.data.len())
+            .finish()
+    }
+}
+
+/// A picture attached to a Telegram message, not downloaded yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageAttachment {
+    pub file_id: String,
+    pub media_type: String,
+}
+
+/// The picture of `msg`: the largest size of a photo that is small enough, or
+/// a document that is a JPEG, PNG, GIF or WebP image.
+pub fn message_image(msg: &Message) -> Option<ImageAttachment> {
+    if let Some(sizes) = msg.photo() {
+        return largest_photo(sizes).map(|size| ImageAttachment {
+            file_id: size.file.id.clone(),
+            media_type: PHOTO_MEDIA_TYPE.to_owned(),
+        });
+    }
+    let document = msg.document()?;
+    let media_type = document.mime_type.as_ref()?.essence_str();
+    (IMAGE_MEDIA_TYPES.contains(&media_type) && document.file.size <= MAX_IMAGE_BYTES).then(|| {
+        ImageAttachment {
+            file_id: document.file.id.clone(),
+            media_type: media_type.to_owned(),
+        }
+    })
+}
+
+/// Telegram keeps a photo in several sizes; the model gets the largest one
+/// under [`MAX_IMAGE_BYTES`].
+fn largest_photo(sizes: &[PhotoSize]) -> Option<&PhotoSize> {
+    sizes
+        .iter()
+        .filter(|size| size.file.size <= MAX_IMAGE_BYTES)
+        .max_by_key(|size| u64::from(size.width) * u64::from(size.height))
+}
+
+pub async fn download(bot: &Bot, attachment: &ImageAttachment) -> Result<Image, AppError> {
+    Ok(Image {
+        media_type: attachment.media_type.clone(),
+        data: telegram_file::download(bot, &attachment.file_id).await?,
+    })
+}
+
+pub fn default_vision_models() -> Vec<String> {
+    VISION_MODELS
+        .iter()
+        .map(|&model| model.to_owned())
+        .collect()
+}
+
+/// Does `model` start with one of the `vision_models` prefixes.
+pub fn sees_images(vision_models: &[String], model: &str) -> bool {
+    vision_models
+        .iter()
+        .any(|prefix| model.starts_with(prefix.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::{FileMeta, PhotoSize};
+
+    use super::{default_vision_models, largest_photo, sees_images, Image, MAX_IMAGE_BYTES};
+
+    fn photo(id: &str, width: u32, height: u32, size: u32) -> PhotoSize {
+        PhotoSize {
+            file: FileMeta {
+                id: id.to_owned(),
+                unique_id: id.to_owned(),
+                size,
+            },
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn largest_photo_that_fits_is_picked() {
+        let sizes = [
+            photo("small", 90, 51, 1_000),
+            photo("large", 1280, 720, 120_000),
+            photo("medium", 320, 180, 12_000),
+        ];
+        assert_eq!(largest_photo(&sizes).unwrap().file.id, "large");
+        let huge = [
+            photo("medium", 320, 180, 12_000),
+            photo("huge", 5000, 5000, MAX_IMAGE_BYTES + 1),
+        ];
+        assert_eq!(largest_photo(&huge).unwrap().file.id, "medium");
+        assert!(largest_photo(&[]).is_none());
+    }
+
+    #[test]
+    fn vision_models_match_by_prefix() {
+        let models = default_vision_models();
+        assert!(sees_images(&models, "gpt-4o"));
+        assert!(sees_images(&models, "gpt-4o-mini"));
+        assert!(sees_images(&models, "claude-3-5-sonnet-latest"));
+        assert!(!sees_images(&models, "gpt-3.5-turbo"));
+        assert!(!sees_images(&models, "llama3"));
+        assert!(!sees_images(&[], "gpt-4o"));
+    }
+
+    #[test]
+    fn image_is_inlined_as_a_data_url() {
+        let image = Image {
+            media_type: "image/png".to_owned(),
+            data: b"png".to_vec(),
+        };
+        assert_eq!(image.base64(), "cG5n");
+        assert_eq!(image.data_url(), "data:image/png;base64,cG5n");
+        assert_eq!(
+            format!("{image:?}"),
+            r#"Image { media_type: "image/png", bytes: 3 }"#
+        );
+    }
+}
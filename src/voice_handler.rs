@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use log::info;
+use teloxide::prelude::*;
+use teloxide::types::{MediaKind, MediaText, MessageKind};
+
+use crate::gpt_quota::{self, Charge};
+use crate::speech::Recording;
+use crate::typing_indicator::TypingIndicator;
+use crate::{chat_gpt_handler, telegram_file, AppError, GptParameters};
+
+/// The largest file the Bot API lets a bot download.
+const MAX_RECORDING_BYTES: u32 = 20 * 1024 * 1024;
+const TRANSCRIPT_PREFIX: &str = "Расшифровка: ";
+
+/// A voice note or audio file attached to a message, not downloaded yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RecordingAttachment {
+    file_id: String,
+    file_name: String,
+    media_type: String,
+    duration: Duration,
+    size: u32,
+}
+
+fn message_recording(msg: &Message) -> Option<RecordingAttachment> {
+    if let Some(voice) = msg.voice() {
+        return Some(RecordingAttachment {
+            file_id: voice.file.id.clone(),
+            file_name: "voice.ogg".to_owned(),
+            media_type: voice
+                .mime_type
+                .as_ref()
+                .map_or("audio/ogg", |mime| mime.essence_str())
+                .to_owned(),
+            duration: voice.duration.duration(),
+            size: voice.file.size,
+        });
+    }
+    let audio = msg.audio()?;
+    Some(RecordingAttachment {
+        file_id: audio.file.id.clone(),
+        file_name: audio
+            .file_name
+            .clone()
+            .unwrap_or_else(|| "audio.mp3".to_owned()),
+        media_type: audio
+            .mime_type
+            .as_ref()
+            .map_or("audio/mpeg", |mime| mime.essence_str())
+            .to_owned(),
+        duration: audio.duration.duration(),
+        size: audio.file.size,
+    })
+}
+
+pub fn has_recording(msg: &Message) -> bool {
+    msg.voice().is_some() || msg.audio().is_some()
+}
+
+/// Transcribe the voice note or audio file of `msg` and post the transcript
+/// in reply. Returns `msg` with the transcript as its text, for the text
+/// handlers to route, or `None` when there is nothing to route.
+pub async fn handle_voice(
+    bot: &Bot,
+    msg: &Message,
+    gpt_parameters: &GptParameters,
+) -> Result<Option<Message>, AppError> {
+    let Some(recording) = message_recording(msg) else {
+        return Ok(None);
+    };
+    let chat_id = msg.chat.id;
+    let speech = &gpt_parameters.settings.speech;
+    if recording.duration > speech.max_duration || recording.size > MAX_RECORDING_BYTES {
+        info!(
+            "not transcribing {:?} of {} bytes in chat {chat_id}",
+            recording.duration, recording.size
+        );
+        return Ok(None);
+    }
+    // Voice notes are transcribed unasked, like links are summarized.
+    if gpt_quota::check_message(gpt_parameters, msg, Charge::Due)
+        .await
+        .is_some()
+    {
+        return Ok(None);
+    }
+    info!(
+        "voice transcription: chat_id: {chat_id}, file: {}",
+        recording.file_id
+    );
+    let typing = TypingIndicator::start(bot, chat_id, msg.thread_id);
+    let data = telegram_file::download(bot, &recording.file_id).await?;
+    let transcriber = speech.build(
+        gpt_parameters.http_client.clone(),
+        &gpt_parameters.chat_gpt_api_token,
+        gpt_parameters.settings.request_timeout,
+    );
+    let transcript = transcriber
+        .transcribe(Recording {
+            file_name: recording.file_name,
+            media_type: recording.media_type,
+            data,
+        })
+        .await?;
+    drop(typing);
+    if transcript.is_empty() {
+        info!("empty transcript in chat {chat_id}");
+        return Ok(None);
+    }
+
+    // A long transcript goes out as a chain of replies, like a long answer.
+    chat_gpt_handler::send_gpt_reply(
+        bot,
+        chat_id,
+        msg.id,
+        msg.thread_id,
+        &format!("{TRANSCRIPT_PREFIX}{transcript}"),
+    )
+    .await?;
+    Ok(Some(as_text_message(msg, transcript)))
+}
+
+/// `msg` as if its transcript had been typed.
+fn as_text_message(msg: &Message, transcript: String) -> Message {
+    let mut text_msg = msg.clone();
+    if let MessageKind::Common(common) = &mut text_msg.kind {
+        common.media_kind = MediaKind::Text(MediaText {
+            text: transcript,
+            entities: Vec::new(),
+            link_preview_options: None,
+        });
+    }
+    text_msg
+}
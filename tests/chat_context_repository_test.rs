@@ -0,0 +1,120 @@
+//! Coverage of the persona conversation context in `chat_repository` against a
+//! real Redis (testcontainers): the list is trimmed to the newest turns, reads
+//! return the most recent ones, idle conversations expire, and contexts stored
+//! under the old `BotProfile` keys are moved to persona id keys.
+
+mod common;
+
+use chrono::Duration;
+use common::spawn_redis;
+use redis::AsyncCommands;
+use rust_bot::chat_repository;
+use rust_bot::gpt_service::{ChatMessage, ChatMessageRole};
+use rust_bot::ContextLimits;
+
+fn turn(n: usize) -> ChatMessage {
+    ChatMessage {
+        role: ChatMessageRole::User,
+        content: format!("turn {n}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn push_context_keeps_only_latest_turns_and_sets_ttl() {
+    let redis = spawn_redis().await;
+    let mut cm = redis.connection_manager.clone();
+    let key = "persona:fedor:chat:-1".to_string();
+    let limits = ContextLimits {
+        max_messages: 4,
+        ttl: Duration::minutes(10),
+    };
+
+    let turns: Vec<ChatMessage> = (0..10).map(turn).collect();
+    for pair in turns.chunks(2) {
+        chat_repository::push_context(&mut cm, &key, pair.iter().collect(), &limits)
+            .await
+            .expect("push context");
+    }
+
+    let stored: isize = cm.llen(&key).await.expect("llen");
+    assert_eq!(stored, 4, "list should be trimmed to max_messages");
+
+    let context = chat_repository::get_bot_context(&mut cm, &key, limits.max_messages)
+        .await
+        .expect("get context");
+    let contents: Vec<&str> = context.iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(contents, vec!["turn 6", "turn 7", "turn 8", "turn 9"]);
+
+    let ttl: i64 = cm.ttl(&key).await.expect("ttl");
+    assert!(ttl > 0 && ttl <= 600, "unexpected ttl {ttl}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_bot_context_reads_most_recent_entries() {
+    let redis = spawn_redis().await;
+    let mut cm = redis.connection_manager.clone();
+    let key = "persona:ferris:chat:-2".to_string();
+    let turns: Vec<ChatMessage> = (0..6).map(turn).collect();
+    let _: () = cm.rpush(&key, &turns).await.expect("seed context");
+
+    let context = chat_repository::get_bot_context(&mut cm, &key, 2)
+        .await
+        .expect("get context");
+    let contents: Vec<&str> = context.iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(contents, vec!["turn 4", "turn 5"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn legacy_profile_contexts_move_to_persona_id_keys() {
+    let redis = spawn_redis().await;
+    let mut cm = redis.connection_manager.clone();
+    let _: () = cm
+        .rpush("Fedor:chat:-3", turn(1))
+        .await
+        .expect("seed legacy context");
+    let _: () = cm
+        .rpush("Ferris:chat:-3", turn(2))
+        .await
+        .expect("seed legacy context");
+    // A conversation already under the new key wins over the legacy one.
+    let _: () = cm
+        .rpush("persona:ferris:chat:-3", turn(3))
+        .await
+        .expect("seed migrated context");
+
+    let moved = chat_repository::migrate_legacy_context_keys(&mut cm)
+        .await
+        .expect("migrate contexts");
+    assert_eq!(moved, 1);
+
+    let fedor = chat_repository::get_bot_context(&mut cm, &"persona:fedor:chat:-3".to_string(), 10)
+        .await
+        .expect("get context");
+    assert_eq!(fedor[0].content, "turn 1");
+    let exists: bool = cm.exists("Fedor:chat:-3").await.expect("exists");
+    assert!(!exists, "legacy key should be renamed");
+    let ferris =
+        chat_repository::get_bot_context(&mut cm, &"persona:ferris:chat:-3".to_string(), 10)
+            .await
+            .expect("get context");
+    assert_eq!(ferris[0].content, "turn 3");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deleting_contexts_of_a_chat_without_personas_is_a_no_op() {
+    let redis = spawn_redis().await;
+    let mut cm = redis.connection_manager.clone();
+    chat_repository::delete_bot_contexts(&mut cm, &[])
+        .await
+        .expect("no keys to delete is not an error");
+
+    let key = "persona:fedor:chat:-1".to_string();
+    chat_repository::push_context(&mut cm, &key, vec![&turn(1)], &ContextLimits::default())
+        .await
+        .expect("push context");
+    chat_repository::delete_bot_contexts(&mut cm, std::slice::from_ref(&key))
+        .await
+        .expect("delete contexts");
+    let exists: bool = cm.exists(&key).await.expect("exists");
+    assert!(!exists);
+}
@@ -1,7 +1,15 @@
 mod common;
 
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use common::*;
 use redis::AsyncCommands;
+use rust_bot::gpt_quota::QuotaLimits;
+use rust_bot::gpt_service::{ChatMessage, ChatMessageRole};
+use rust_bot::llm_provider::{ProviderKind, ProviderSettings};
+use rust_bot::speech::SpeechSettings;
+use rust_bot::{ContextLimits, GptSettings};
 
 #[tokio::test(flavor = "multi_thread")]
 async fn chat_gpt_routes_to_openai_and_writes_redis_context() {
@@ -10,7 +18,11 @@ async fn chat_gpt_routes_to_openai_and_writes_redis_context() {
     let (telegram, bot) = spawn_telegram().await;
     let canned = "Привет, дружище!";
     let (openai, openai_url) = spawn_openai(canned).await;
-    let gpt = gpt_parameters(redis.connection_manager.clone(), openai_url);
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
 
     let chat_id = -1002000_i64;
     let user_id = 11_i64;
@@ -56,7 +68,7 @@ async fn chat_gpt_routes_to_openai_and_writes_redis_context() {
     );
 
     let mut cm = redis.connection_manager.clone();
-    let key = format!("Fedor:chat:{chat_id}");
+    let key = format!("persona:fedor:chat:{chat_id}");
     let entries: Vec<String> = cm.lrange(&key, 0, -1).await.expect("redis lrange");
     assert_eq!(
         entries.len(),
@@ -66,3 +78,807 @@ async fn chat_gpt_routes_to_openai_and_writes_redis_context() {
         entries
     );
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chat_summary_is_built_from_recorded_history() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (openai, openai_url) = spawn_openai("Обсуждали borrow checker.").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_002_100_i64;
+
+    // Plain messages route nowhere but are still recorded into the history.
+    for (message_id, text) in [(1, "кто трогал borrow checker"), (2, "lifetimes опять")]
+    {
+        let update = text_message_update(text, chat_id, 12, message_id);
+        dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), update).await;
+    }
+
+    let mut cm = redis.connection_manager.clone();
+    let recorded: Vec<String> = cm
+        .zrange(format!("history:chat:{chat_id}"), 0, -1)
+        .await
+        .expect("redis zrange");
+    assert_eq!(recorded.len(), 2, "history entries: {recorded:?}");
+
+    let update = text_message_update("федор, что происходит?", chat_id, 13, 3);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(openai_calls.len(), 1, "expected 1 openai call");
+    let openai_body = String::from_utf8_lossy(&openai_calls[0].body);
+    assert!(
+        openai_body.contains("borrow checker") && openai_body.contains("lifetimes"),
+        "summary prompt missing recorded history: {openai_body}"
+    );
+
+    let telegram_requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    assert!(telegram_requests
+        .iter()
+        .any(|r| r.url.path().ends_with("/SendMessage")));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn persona_context_sends_latest_exchange_to_gpt() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (_telegram, bot) = spawn_telegram().await;
+    let (openai, openai_url) = spawn_openai("Опять ты.").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_002_200_i64;
+    let key = format!("persona:fedor:chat:{chat_id}");
+    let mut cm = redis.connection_manager.clone();
+    let stored: Vec<ChatMessage> = (0..30)
+        .map(|n| ChatMessage {
+            role: ChatMessageRole::User,
+            content: format!("old-turn-{n:02}"),
+        })
+        .collect();
+    let _: () = cm.rpush(&key, &stored).await.expect("seed context");
+
+    let update = text_message_update("fedor, ну что?", chat_id, 14, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    let openai_body = String::from_utf8_lossy(&openai_calls[0].body);
+    assert!(
+        openai_body.contains("old-turn-29"),
+        "latest stored turn missing: {openai_body}"
+    );
+    assert!(
+        !openai_body.contains("old-turn-00"),
+        "oldest stored turn should not be sent: {openai_body}"
+    );
+
+    let max_messages = isize::try_from(ContextLimits::default().max_messages).unwrap();
+    let len: isize = cm.llen(&key).await.expect("llen");
+    assert_eq!(
+        len, max_messages,
+        "context should be trimmed after the reply"
+    );
+    let ttl: i64 = cm.ttl(&key).await.expect("ttl");
+    assert!(ttl > 0, "context should expire when idle, ttl={ttl}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn persona_stored_in_postgres_answers_with_its_own_settings() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (_telegram, bot) = spawn_telegram().await;
+    let (openai, openai_url) = spawn_openai("Хо-хо-хо.").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_002_300_i64;
+    sqlx::query(
+        "INSERT INTO personas (id, name, mention_regex, system_prompt, model, temperature, \
+                languages, enabled_chats) \
+                VALUES ('santa', 'Санта', '(?i)санта', 'Ты Санта.', 'gpt-4o-mini', 1.5, \
+                ARRAY['be'], ARRAY[$1::BIGINT])",
+    )
+    .bind(chat_id)
+    .execute(&pg.pool)
+    .await
+    .expect("seed persona");
+
+    let update = text_message_update("санта, привет", chat_id, 15, 1);
+    dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), update).await;
+
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(openai_calls.len(), 1, "expected 1 openai call");
+    let openai_body: serde_json::Value =
+        serde_json::from_slice(&openai_calls[0].body).expect("openai request json");
+    assert_eq!(openai_body["model"], "gpt-4o-mini");
+    assert_eq!(openai_body["temperature"], 1.5);
+    let system_prompt = openai_body["messages"][0]["content"]
+        .as_str()
+        .expect("system prompt");
+    assert!(
+        system_prompt.starts_with("Ты Санта.") && system_prompt.contains("be"),
+        "system prompt: {system_prompt}"
+    );
+
+    let mut cm = redis.connection_manager.clone();
+    let len: isize = cm
+        .llen(format!("persona:santa:chat:{chat_id}"))
+        .await
+        .expect("llen");
+    assert_eq!(len, 2, "santa context should hold the exchange");
+
+    // The persona is only enabled in its chat.
+    let update = text_message_update("санта, привет", -1_002_301, 15, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(openai_calls.len(), 1, "other chats must not reach santa");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chat_provider_routes_the_chat_to_its_backend() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (openai, openai_url) = spawn_openai("from openai").await;
+    let (anthropic, anthropic_url) = spawn_anthropic("Привет от Claude!").await;
+    let mut gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_002_400_i64;
+    gpt.settings = Arc::new(GptSettings {
+        providers: HashMap::from([(
+            "claude".to_owned(),
+            ProviderSettings {
+                kind: ProviderKind::Anthropic,
+                base_url: Arc::from(anthropic_url),
+                api_key: Some(Arc::from("anthropic-test")),
+                model: Some(Arc::from("claude-3-5-haiku-latest")),
+            },
+        )]),
+        chat_providers: HashMap::from([(chat_id, "claude".to_owned())]),
+        ..(*gpt.settings).clone()
+    });
+
+    let update = text_message_update("ferris, привет", chat_id, 16, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let anthropic_calls = anthropic
+        .received_requests()
+        .await
+        .expect("collect anthropic requests");
+    assert_eq!(anthropic_calls.len(), 1, "expected 1 anthropic call");
+    let body: serde_json::Value =
+        serde_json::from_slice(&anthropic_calls[0].body).expect("anthropic request json");
+    assert_eq!(body["model"], "claude-3-5-haiku-latest");
+    assert!(
+        body["system"].as_str().is_some_and(|s| s.contains("Rust")),
+        "ferris system prompt goes to the system field: {body}"
+    );
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert!(openai_calls.is_empty(), "the chat must not reach openai");
+
+    let telegram_requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    assert!(telegram_requests
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .any(|r| String::from_utf8_lossy(&r.body).contains("Привет от Claude!")));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn provider_failures_get_a_reply_naming_the_problem() {
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let openai = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&openai)
+        .await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai.uri(),
+    );
+
+    let update = text_message_update("fedor, привет", -1_002_500, 17, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(openai_calls.len(), 1, "credential errors are not retried");
+    let telegram_requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    assert!(telegram_requests
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .any(|r| String::from_utf8_lossy(&r.body).contains("Позовите админа")));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streamed_answer_is_shown_by_editing_a_placeholder() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai_stream(&["Привет, ", "дружище", "!"]).await;
+    let mut gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+    gpt.settings = Arc::new(GptSettings {
+        streaming: true,
+        ..(*gpt.settings).clone()
+    });
+
+    let chat_id = -1_002_600_i64;
+    let update = text_message_update("fedor, привет", chat_id, 18, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let telegram_requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = |method: &str| -> Vec<String> {
+        telegram_requests
+            .iter()
+            .filter(|r| r.url.path().ends_with(method))
+            .map(|r| String::from_utf8_lossy(&r.body).to_string())
+            .collect()
+    };
+    let sent = bodies("/SendMessage");
+    assert_eq!(sent.len(), 1, "one placeholder message: {sent:?}");
+    assert!(!sent[0].contains("дружище"), "placeholder: {}", sent[0]);
+    let edits = bodies("/EditMessageText");
+    assert!(
+        edits
+            .last()
+            .is_some_and(|edit| edit.contains("Привет, дружище!")),
+        "the last edit shows the whole answer: {edits:?}"
+    );
+
+    let mut cm = redis.connection_manager.clone();
+    let entries: Vec<String> = cm
+        .lrange(format!("persona:fedor:chat:{chat_id}"), 0, -1)
+        .await
+        .expect("redis lrange");
+    assert!(
+        entries
+            .last()
+            .is_some_and(|entry| entry.contains("Привет, дружище!")),
+        "the streamed answer is kept in the context: {entries:?}"
+    );
+
+    let usage: (i32, i32, bool) =
+        sqlx::query_as("SELECT prompt_tokens, completion_tokens, estimated FROM llm_usage")
+            .fetch_one(&pg.pool)
+            .await
+            .expect("streamed usage is booked");
+    assert_eq!(usage, (12, 3, false), "from the stream's usage chunk");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn over_quota_question_gets_a_refusal_without_calling_gpt() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (openai, openai_url) = spawn_openai("Отвечаю.").await;
+    let mut gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+    gpt.settings = Arc::new(GptSettings {
+        quota: QuotaLimits {
+            user_daily: 1,
+            ..QuotaLimits::default()
+        },
+        ..(*gpt.settings).clone()
+    });
+
+    let chat_id = -1_002_700_i64;
+    for message_id in [1, 2] {
+        let update = text_message_update("fedor, привет", chat_id, 19, message_id);
+        dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), update).await;
+    }
+
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(openai_calls.len(), 1, "the second question is over quota");
+    let telegram_requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let replies: Vec<String> = telegram_requests
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .map(|r| String::from_utf8_lossy(&r.body).to_string())
+        .collect();
+    assert_eq!(replies.len(), 2);
+    assert!(
+        replies[1].contains("Федор на сегодня с тобой наговорился"),
+        "refusal in persona: {}",
+        replies[1]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answered_question_books_its_token_usage() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (_telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("Отвечаю.").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_002_800_i64;
+    let update = text_message_update("fedor, сколько стоит ответ?", chat_id, 21, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    type UsageRow = (
+        i64,
+        Option<i64>,
+        Option<String>,
+        String,
+        String,
+        String,
+        i32,
+        i32,
+        i64,
+        bool,
+    );
+    let rows: Vec<UsageRow> = sqlx::query_as(
+        "SELECT chat_id, user_id, persona_id, handler, provider, model, \
+                    prompt_tokens, completion_tokens, cost_micros, estimated FROM llm_usage",
+    )
+    .fetch_all(&pg.pool)
+    .await
+    .expect("read usage");
+    assert_eq!(
+        rows,
+        vec![(
+            chat_id,
+            Some(21),
+            Some("fedor".to_owned()),
+            "conversation".to_owned(),
+            "openai".to_owned(),
+            "gpt-4o".to_owned(),
+            1,
+            1,
+            // One token at $2.50 and one at $10 per million, rounded.
+            13,
+            false
+        )]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn captioned_photo_is_shown_to_the_persona_and_described_in_its_context() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_file_download(&telegram, "photo-large", b"jpeg").await;
+    let canned = "На скриншоте ошибка borrow checker.";
+    let (openai, openai_url) = spawn_openai(canned).await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_002_900_i64;
+    let update = photo_message_update(Some("федор, что не так?"), chat_id, 22, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let telegram_requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let get_file: Vec<String> = telegram_requests
+        .iter()
+        .filter(|r| r.url.path().ends_with("/GetFile"))
+        .map(|r| String::from_utf8_lossy(&r.body).to_string())
+        .collect();
+    assert_eq!(get_file.len(), 1);
+    assert!(get_file[0].contains("photo-large"), "{}", get_file[0]);
+
+    // The answer and the description for the context.
+    let bodies: Vec<serde_json::Value> = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests")
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).expect("openai json"))
+        .collect();
+    assert_eq!(bodies.len(), 2);
+    let image_part = serde_json::json!({
+        "type": "image_url",
+        "image_url": {"url": "data:image/jpeg;base64,anBlZw=="}
+    });
+    for body in &bodies {
+        let question = body["messages"].as_array().unwrap().last().unwrap();
+        assert_eq!(question["content"][1], image_part, "{body}");
+    }
+    assert!(bodies.iter().any(|body| {
+        body["messages"][1]["content"][0]["text"] == "федор, что не так?"
+    }));
+
+    let mut cm = redis.connection_manager.clone();
+    let key = format!("persona:fedor:chat:{chat_id}");
+    let entries: Vec<String> = cm.lrange(&key, 0, -1).await.expect("redis lrange");
+    assert_eq!(entries.len(), 2, "{entries:?}");
+    let question: ChatMessage = serde_json::from_str(&entries[0]).expect("context json");
+    assert_eq!(
+        question.content,
+        format!("федор, что не так?\n[Картинка: {canned}]")
+    );
+
+    let handlers: Vec<(String,)> = sqlx::query_as("SELECT handler FROM llm_usage ORDER BY handler")
+        .fetch_all(&pg.pool)
+        .await
+        .expect("read usage");
+    assert_eq!(
+        handlers,
+        vec![
+            ("conversation".to_owned(),),
+            ("image_description".to_owned(),)
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn model_without_vision_is_told_about_the_picture() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_file_download(&telegram, "photo-large", b"jpeg").await;
+    let (openai, openai_url) = spawn_openai("Не вижу, но верю.").await;
+    let mut gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+    gpt.settings = Arc::new(GptSettings {
+        vision_models: Vec::new(),
+        ..(*gpt.settings).clone()
+    });
+
+    let update = photo_message_update(Some("fedor, что тут?"), -1_002_901, 23, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(openai_calls.len(), 1, "nothing to describe with");
+    let body: serde_json::Value = serde_json::from_slice(&openai_calls[0].body).unwrap();
+    let question = &body["messages"][1]["content"];
+    assert!(
+        question
+            .as_str()
+            .is_some_and(|text| text.starts_with("fedor, что тут?") && text.contains("не видишь")),
+        "{question}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn voice_note_is_transcribed_and_answered_like_text() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_file_download(&telegram, "voice-1", b"OggS").await;
+    let (transcriber, transcriber_url) = spawn_transcriber("Феррис, что такое borrow?").await;
+    let (openai, openai_url) = spawn_openai("Заимствование, краб.").await;
+    let mut gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+    gpt.settings = Arc::new(GptSettings {
+        speech: SpeechSettings {
+            base_url: Arc::from(transcriber_url),
+            ..SpeechSettings::default()
+        },
+        ..(*gpt.settings).clone()
+    });
+
+    let chat_id = -1_002_902_i64;
+    let update = voice_message_update(4, chat_id, 24, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let transcriptions = transcriber
+        .received_requests()
+        .await
+        .expect("collect transcription requests");
+    assert_eq!(transcriptions.len(), 1);
+    assert_eq!(
+        transcriptions[0].headers["authorization"].to_str().unwrap(),
+        "Bearer test-openai-token"
+    );
+
+    let send_messages: Vec<String> = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests")
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .map(|r| String::from_utf8_lossy(&r.body).to_string())
+        .collect();
+    assert_eq!(send_messages.len(), 2, "{send_messages:?}");
+    assert!(
+        send_messages[0].contains("Расшифровка: Феррис, что такое borrow?"),
+        "{}",
+        send_messages[0]
+    );
+    assert!(
+        send_messages[1].contains("Заимствование, краб."),
+        "{}",
+        send_messages[1]
+    );
+
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(openai_calls.len(), 1);
+    let body: serde_json::Value = serde_json::from_slice(&openai_calls[0].body).unwrap();
+    assert_eq!(body["messages"][1]["content"], "Феррис, что такое borrow?");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn voice_note_answered_by_a_persona_counts_once_against_the_quota() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_file_download(&telegram, "voice-1", b"OggS").await;
+    let (_transcriber, transcriber_url) = spawn_transcriber("Феррис, что такое borrow?").await;
+    let (openai, openai_url) = spawn_openai("Заимствование, краб.").await;
+    let mut gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+    gpt.settings = Arc::new(GptSettings {
+        speech: SpeechSettings {
+            base_url: Arc::from(transcriber_url),
+            ..SpeechSettings::default()
+        },
+        quota: QuotaLimits {
+            user_daily: 1,
+            ..QuotaLimits::default()
+        },
+        ..(*gpt.settings).clone()
+    });
+
+    let update = voice_message_update(4, -1_002_903, 24, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(openai_calls.len(), 1, "the transcript paid for the answer");
+    let send_messages: Vec<String> = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests")
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .map(|r| String::from_utf8_lossy(&r.body).to_string())
+        .collect();
+    assert_eq!(send_messages.len(), 2, "{send_messages:?}");
+    assert!(
+        send_messages[1].contains("Заимствование, краб."),
+        "{}",
+        send_messages[1]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn long_transcript_is_sent_whole_in_several_replies() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_file_download(&telegram, "voice-1", b"OggS").await;
+    let sentence = "Сегодня снова обсуждали времена жизни. ";
+    let transcript = format!("{}конец.", sentence.repeat(150));
+    let (_transcriber, transcriber_url) = spawn_transcriber(&transcript).await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let mut gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+    gpt.settings = Arc::new(GptSettings {
+        speech: SpeechSettings {
+            base_url: Arc::from(transcriber_url),
+            ..SpeechSettings::default()
+        },
+        ..(*gpt.settings).clone()
+    });
+
+    let update = voice_message_update(4, -1_002_904, 24, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let sent: Vec<String> = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests")
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .map(|r| {
+            let body: serde_json::Value =
+                serde_json::from_slice(&r.body).expect("sendMessage json");
+            body["text"].as_str().unwrap_or_default().to_owned()
+        })
+        .collect();
+    assert!(sent.len() > 1, "{sent:?}");
+    assert!(sent.iter().all(|text| text.encode_utf16().count() <= 4096));
+    assert!(sent[0].starts_with("Расшифровка: "), "{}", sent[0]);
+    assert!(sent.last().unwrap().ends_with("конец."), "{sent:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn markdown_answer_is_sent_as_html_and_as_is_when_telegram_rejects_it() {
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let canned = "Используй **Box<dyn Error>**:\n\n```rust\nfn main() {}\n```";
+    let (_openai, openai_url) = spawn_openai(canned).await;
+
+    let (telegram, bot) = spawn_telegram().await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url.clone(),
+    );
+    let update = text_message_update("ферис, как вернуть ошибку?", -1_002_903, 25, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let sent: Vec<serde_json::Value> = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests")
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .map(|r| serde_json::from_slice(&r.body).expect("sendMessage json"))
+        .collect();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0]["parse_mode"], "HTML");
+    assert_eq!(
+        sent[0]["text"],
+        "Используй <b>Box&lt;dyn Error&gt;</b>:\n\n<pre><code class=\"language-rust\">fn main() {}</code></pre>"
+    );
+
+    let (telegram, bot) = spawn_telegram().await;
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{TEST_BOT_TOKEN}/SendMessage")))
+        .and(body_partial_json(serde_json::json!({"parse_mode": "HTML"})))
+        .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: can't parse entities: Unsupported start tag \"b\""
+        })))
+        .with_priority(1)
+        .mount(&telegram)
+        .await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+    let update = text_message_update("ферис, как вернуть ошибку?", -1_002_904, 25, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let sent: Vec<serde_json::Value> = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests")
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .map(|r| serde_json::from_slice(&r.body).expect("sendMessage json"))
+        .collect();
+    assert_eq!(sent.len(), 2, "{sent:?}");
+    assert!(sent[1].get("parse_mode").is_none(), "{}", sent[1]);
+    assert_eq!(sent[1]["text"], canned);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn long_answer_is_sent_as_a_reply_chain_and_every_part_continues_it() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_sent_message_ids(&telegram, 200).await;
+    let paragraph = "Владение в Rust означает, что у значения один хозяин. ".repeat(30);
+    let canned = [paragraph.as_str(); 4].join("\n\n");
+    let (_openai, openai_url) = spawn_openai(&canned).await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_002_905_i64;
+    let update = text_message_update("феррис, расскажи про владение", chat_id, 26, 7);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let sent: Vec<serde_json::Value> = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests")
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .map(|r| serde_json::from_slice(&r.body).expect("sendMessage json"))
+        .collect();
+    assert!(sent.len() > 1, "{} messages", sent.len());
+    for (i, message) in sent.iter().enumerate() {
+        let text = message["text"].as_str().unwrap();
+        assert!(
+            text.encode_utf16().count() <= 4096,
+            "{} characters",
+            text.len()
+        );
+        assert!(text.ends_with("хозяин."), "split mid-paragraph: {text}");
+        let reply_to = if i == 0 { 7 } else { 199 + i };
+        assert_eq!(message["reply_parameters"]["message_id"], reply_to);
+    }
+
+    let mut cm = redis.connection_manager.clone();
+    let identifiers: HashMap<i32, String> = cm
+        .hgetall(format!("chat:{chat_id}"))
+        .await
+        .expect("redis hgetall");
+    let expected: HashMap<i32, String> = (200..200 + sent.len() as i32)
+        .map(|id| (id, "ferris".to_owned()))
+        .collect();
+    assert_eq!(identifiers, expected);
+}
@@ -3,8 +3,8 @@
 //! malformed/corrupt payload takes (must return a `RedisError`, not panic).
 
 use redis::{FromRedisValue, ToRedisArgs, Value};
-use rust_bot::chat_gpt_handler::BotProfile;
 use rust_bot::gpt_service::{ChatMessage, ChatMessageRole};
+use rust_bot::PersonaId;
 
 #[test]
 fn chat_message_round_trips_through_redis_encoding() {
@@ -28,12 +28,26 @@ fn chat_message_round_trips_through_redis_encoding() {
 }
 
 #[test]
-fn bot_profile_round_trips_through_redis_encoding() {
-    for profile in [BotProfile::Fedor, BotProfile::Felix, BotProfile::Ferris] {
-        let args = profile.to_redis_args();
-        let decoded = BotProfile::from_redis_value(&Value::Data(args[0].clone()))
-            .expect("decode BotProfile from its own encoding");
-        assert_eq!(decoded, profile);
+fn persona_id_round_trips_through_redis_encoding() {
+    for id in ["fedor", "felix", "ferris", "santa"] {
+        let persona_id = PersonaId::new(id);
+        let args = persona_id.to_redis_args();
+        let decoded = PersonaId::from_redis_value(&Value::Data(args[0].clone()))
+            .expect("decode PersonaId from its own encoding");
+        assert_eq!(decoded, persona_id);
+    }
+}
+
+#[test]
+fn legacy_bot_profile_values_decode_to_persona_ids() {
+    for (stored, id) in [
+        ("\"Fedor\"", "fedor"),
+        ("\"Felix\"", "felix"),
+        ("\"Ferris\"", "ferris"),
+    ] {
+        let decoded = PersonaId::from_redis_value(&Value::Data(stored.as_bytes().to_vec()))
+            .expect("decode a legacy BotProfile value");
+        assert_eq!(decoded, PersonaId::new(id));
     }
 }
 
@@ -47,10 +61,10 @@ fn chat_message_from_malformed_value_is_error_not_panic() {
 }
 
 #[test]
-fn bot_profile_from_unknown_variant_is_error_not_panic() {
-    let result = BotProfile::from_redis_value(&Value::Data(b"\"NotAProfile\"".to_vec()));
+fn persona_id_from_malformed_value_is_error_not_panic() {
+    let result = PersonaId::from_redis_value(&Value::Data(b"{\"Fedor\"".to_vec()));
     assert!(
         result.is_err(),
-        "an unknown BotProfile must return an error"
+        "a corrupt persona id payload must return an error"
     );
 }
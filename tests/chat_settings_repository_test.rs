@@ -0,0 +1,109 @@
+//! Direct coverage of `chat_settings_repository` against a real Postgres:
+//! defaults for unknown chats, the per-column upserts and the silent-counting
+//! seed for the chat named by the `RUST_CHAT_ID` env var.
+
+mod common;
+
+use chrono::Duration;
+use common::spawn_postgres;
+use rust_bot::chat_settings_repository::{self, ChatFeature, ChatSettings};
+use rust_bot::gayness_handler::MuteTiers;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_chat_gets_defaults() {
+    let pg = spawn_postgres().await;
+
+    let settings = chat_settings_repository::get_chat_settings(&pg.pool, -7_000)
+        .await
+        .expect("settings of unknown chat");
+    assert_eq!(settings, ChatSettings::default());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn setters_update_only_their_column() {
+    let pg = spawn_postgres().await;
+    let chat_id = -7_100_i64;
+    let mute_tiers: MuteTiers = "10:60,*:5".parse().expect("mute tiers spec");
+
+    chat_settings_repository::set_feature_enabled(&pg.pool, chat_id, ChatFeature::Gpt, false)
+        .await
+        .expect("disable gpt");
+    chat_settings_repository::set_mention_cooldown(&pg.pool, chat_id, Some(3))
+        .await
+        .expect("set cooldown");
+    chat_settings_repository::set_mute_tiers(&pg.pool, chat_id, &mute_tiers)
+        .await
+        .expect("set mute tiers");
+    chat_settings_repository::set_silent_counting(&pg.pool, chat_id, true)
+        .await
+        .expect("set silent counting");
+
+    let settings = chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("chat settings");
+    assert_eq!(
+        settings,
+        ChatSettings {
+            gpt_enabled: false,
+            mention_cooldown: Some(Duration::minutes(3)),
+            mute_tiers,
+            silent_counting: true,
+            ..ChatSettings::default()
+        }
+    );
+
+    chat_settings_repository::set_mention_cooldown(&pg.pool, chat_id, None)
+        .await
+        .expect("reset cooldown");
+    let settings = chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("chat settings");
+    assert_eq!(settings.mention_cooldown, None);
+    assert!(!settings.gpt_enabled, "other columns are left alone");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rust_chat_is_seeded_as_silent_only_once() {
+    let pg = spawn_postgres().await;
+    let chat_id = -7_050_i64;
+
+    assert!(
+        chat_settings_repository::seed_silent_chat(&pg.pool, chat_id)
+            .await
+            .expect("seed silent chat")
+    );
+    let settings = chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("seeded chat settings");
+    assert!(settings.silent_counting);
+    assert!(settings.rust_mention_enabled);
+
+    chat_settings_repository::set_silent_counting(&pg.pool, chat_id, false)
+        .await
+        .expect("admin turns silent counting off");
+    assert!(
+        !chat_settings_repository::seed_silent_chat(&pg.pool, chat_id)
+            .await
+            .expect("seed again on restart")
+    );
+    let settings = chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("chat settings");
+    assert!(!settings.silent_counting, "the admin's choice sticks");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_stored_mute_tiers_fall_back_to_defaults() {
+    let pg = spawn_postgres().await;
+    let chat_id = -7_200_i64;
+    sqlx::query("INSERT INTO chat_settings(chat_id, mute_tiers) VALUES ($1, 'garbage')")
+        .bind(chat_id)
+        .execute(&pg.pool)
+        .await
+        .expect("seed broken mute tiers");
+
+    let settings = chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("chat settings");
+    assert_eq!(settings.mute_tiers, MuteTiers::default());
+}
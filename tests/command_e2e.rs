@@ -0,0 +1,513 @@
+//! End-to-end coverage for the slash-command branch: each command is routed
+//! ahead of the regex chain and answered with a single reply.
+
+mod common;
+
+use common::*;
+use redis::AsyncCommands;
+use rust_bot::gpt_service::{ChatMessage, ChatMessageRole};
+
+fn send_message_bodies(requests: &[wiremock::Request]) -> Vec<String> {
+    requests
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .map(|r| String::from_utf8_lossy(&r.body).to_string())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn help_command_lists_commands() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let update = text_message_update("/help@test_bot", -1_008_000, 81, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert_eq!(bodies.len(), 1, "expected one reply, got {}", bodies.len());
+    for command in ["/help", "/stats", "/reset"] {
+        assert!(bodies[0].contains(command), "help body: {}", bodies[0]);
+    }
+
+    let openai_calls = openai.received_requests().await.expect("openai requests");
+    assert!(openai_calls.is_empty(), "commands must not reach GPT");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stats_command_reports_chat_mentions() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_100_i64;
+    for (user_id, counter) in [(1_i64, 3_i32), (2, 4)] {
+        sqlx::query(
+            "INSERT INTO mentions(user_id, username, chat_id, counter) VALUES ($1, 'u', $2, $3)",
+        )
+        .bind(user_id)
+        .bind(chat_id)
+        .bind(counter)
+        .execute(&pg.pool)
+        .await
+        .expect("seed mention row");
+    }
+
+    let update = text_message_update("/stats", chat_id, 82, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert_eq!(bodies.len(), 1, "expected one reply, got {}", bodies.len());
+    assert!(
+        bodies[0].contains("Упоминаний Rust: 7 от 2"),
+        "stats body: {}",
+        bodies[0]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reset_command_clears_persona_context() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_200_i64;
+    let key = format!("persona:ferris:chat:{chat_id}");
+    let mut cm = redis.connection_manager.clone();
+    let seeded = ChatMessage {
+        role: ChatMessageRole::User,
+        content: "remember me".to_string(),
+    };
+    let _: () = cm.rpush(&key, &seeded).await.expect("seed context");
+
+    let update = text_message_update("/reset", chat_id, 83, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let exists: bool = cm.exists(&key).await.expect("redis exists");
+    assert!(!exists, "persona context should be deleted");
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    assert_eq!(send_message_bodies(&requests).len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rustboard_command_ranks_chat_mentioners() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_300_i64;
+    for (user_id, username, counter) in [(1_i64, "alice", 2_i32), (2, "bob", 9)] {
+        sqlx::query(
+            "INSERT INTO mentions(user_id, username, chat_id, counter) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(user_id)
+        .bind(username)
+        .bind(chat_id)
+        .bind(counter)
+        .execute(&pg.pool)
+        .await
+        .expect("seed mention row");
+    }
+
+    let update = text_message_update("/rustboard", chat_id, 84, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert_eq!(bodies.len(), 1, "expected one reply, got {}", bodies.len());
+    let body = &bodies[0];
+    let bob = body.find("bob — 9").expect("bob ranked");
+    let alice = body.find("alice — 2").expect("alice ranked");
+    assert!(bob < alice, "bob should rank above alice: {body}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mention_timer_command_is_admin_only() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_chat_member_status(&telegram, "member").await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_400_i64;
+    let update = text_message_update("/mentiontimer topic", chat_id, 85, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let settings = rust_bot::chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("chat settings");
+    assert!(
+        !settings.mention_timer_per_topic,
+        "non-admin must not change settings"
+    );
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert!(bodies[0].contains("админы"), "reply body: {}", bodies[0]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mention_timer_command_switches_scope_for_admins() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_chat_member_status(&telegram, "administrator").await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_500_i64;
+    let update = text_message_update("/mentiontimer topic", chat_id, 86, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let settings = rust_bot::chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("chat settings");
+    assert!(settings.mention_timer_per_topic);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn toggle_command_switches_handler_for_admins() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_chat_member_status(&telegram, "creator").await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_600_i64;
+    let update = text_message_update("/toggle gayness", chat_id, 87, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let settings = rust_bot::chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("chat settings");
+    assert!(!settings.gayness_enabled);
+    assert!(settings.gpt_enabled, "other handlers stay on");
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert!(bodies[0].contains("выключен"), "reply body: {}", bodies[0]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn toggle_command_applies_to_the_next_message_at_once() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_chat_member_status(&telegram, "administrator").await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    // The first message caches the chat's settings, the toggle must drop them.
+    let chat_id = -1_008_620_i64;
+    for (message_id, text) in [
+        (1, "I am 3% gay"),
+        (2, "/toggle gayness"),
+        (3, "I am 3% gay"),
+    ] {
+        let update = text_message_update(text, chat_id, 87, message_id);
+        dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), update).await;
+    }
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert_eq!(bodies.len(), 2, "replies: {bodies:?}");
+    assert!(bodies[0].contains("mute"), "reply body: {}", bodies[0]);
+    assert!(bodies[1].contains("выключен"), "reply body: {}", bodies[1]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn settings_commands_answer_bad_arguments_with_usage() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_chat_member_status(&telegram, "administrator").await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_650_i64;
+    let commands = [
+        ("/mentiontimer forever", "/mentiontimer chat|topic"),
+        ("/toggle everything", "/toggle rust|"),
+        ("/cooldown -5", "/cooldown <минуты>|default"),
+        ("/silent maybe", "/silent on|off"),
+        ("/mutetiers 5:600", "fallback"),
+        ("/mutetiers *:150000000000", "366 дней"),
+    ];
+    for (message_id, (command, _)) in commands.iter().enumerate() {
+        let update = text_message_update(command, chat_id, 87, message_id as i32 + 1);
+        dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), update).await;
+    }
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert_eq!(bodies.len(), commands.len(), "replies: {bodies:?}");
+    for (body, (command, usage)) in bodies.iter().zip(commands) {
+        assert!(body.contains(usage), "{command}: {body}");
+    }
+    let settings = rust_bot::chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("chat settings");
+    assert!(!settings.silent_counting);
+    assert_eq!(settings.mention_cooldown, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn settings_commands_are_admin_only() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_chat_member_status(&telegram, "member").await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_700_i64;
+    let update = text_message_update("/silent on", chat_id, 88, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let settings = rust_bot::chat_settings_repository::get_chat_settings(&pg.pool, chat_id)
+        .await
+        .expect("chat settings");
+    assert!(
+        !settings.silent_counting,
+        "non-admin must not change settings"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reload_command_is_owner_only() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_800_i64;
+    let stranger = text_message_update("/reload", chat_id, 89, 1);
+    dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), stranger).await;
+    let owner = text_message_update("/reload", chat_id, TEST_OWNER_ID as i64, 2);
+    dispatch_one(bot, pg.pool.clone(), gpt, owner).await;
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert_eq!(
+        bodies.len(),
+        2,
+        "expected two replies, got {}",
+        bodies.len()
+    );
+    assert!(bodies[0].contains("владельцы"), "reply body: {}", bodies[0]);
+    assert!(
+        bodies[1].contains("перечитана"),
+        "reply body: {}",
+        bodies[1]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn admin_added_persona_answers_mentions_in_the_chat() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_chat_member_status(&telegram, "administrator").await;
+    let (openai, openai_url) = spawn_openai("Хо-хо-хо.").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_008_900_i64;
+    let add =
+        "/persona add santa\nname: Санта\nregex: (?i)санта\nprompt: Ты Санта.\nРаздаёшь подарки.";
+    let update = text_message_update(add, chat_id, 90, 1);
+    dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), update).await;
+
+    let santa = rust_bot::persona_repository::get_chat_persona(
+        &pg.pool,
+        chat_id,
+        &rust_bot::PersonaId::new("santa"),
+    )
+    .await
+    .expect("fetch persona")
+    .expect("persona stored");
+    assert_eq!(santa.name, "Санта");
+    assert_eq!(santa.system_prompt, "Ты Санта.\nРаздаёшь подарки.");
+
+    let update = text_message_update("санта, где подарки?", chat_id, 91, 2);
+    dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), update).await;
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(
+        openai_calls.len(),
+        1,
+        "mention should reach the new persona"
+    );
+    let openai_body = String::from_utf8_lossy(&openai_calls[0].body);
+    assert!(
+        openai_body.contains("Раздаёшь подарки."),
+        "openai body: {openai_body}"
+    );
+
+    // Other chats do not see it.
+    let update = text_message_update("санта, где подарки?", -1_008_901, 91, 3);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+    let openai_calls = openai
+        .received_requests()
+        .await
+        .expect("collect openai requests");
+    assert_eq!(openai_calls.len(), 1, "persona is scoped to its chat");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn persona_changes_are_admin_only() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    mock_chat_member_status(&telegram, "member").await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_009_000_i64;
+    let add = "/persona add santa\nregex: санта\nprompt: Ты Санта.";
+    let update = text_message_update(add, chat_id, 92, 1);
+    dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), update).await;
+
+    let stored = rust_bot::persona_repository::chat_persona_ids(&pg.pool, chat_id)
+        .await
+        .expect("persona ids");
+    assert!(stored.is_empty(), "non-admin must not add personas");
+
+    // Listing is open to everyone.
+    let update = text_message_update("/persona list", chat_id, 92, 2);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert!(bodies[0].contains("админы"), "reply body: {}", bodies[0]);
+    assert!(bodies[1].contains("fedor"), "reply body: {}", bodies[1]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn usage_of_all_chats_is_shown_to_owners_in_private_only() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let owner = TEST_OWNER_ID as i64;
+    let in_group = text_message_update("/usage all", -1_009_100, owner, 1);
+    dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), in_group).await;
+    let in_private = private_message_update("/usage all 2024-06", owner, 2);
+    dispatch_one(bot, pg.pool.clone(), gpt, in_private).await;
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    let bodies = send_message_bodies(&requests);
+    assert_eq!(bodies.len(), 2, "replies: {bodies:?}");
+    assert!(bodies[0].contains("в личке"), "group reply: {}", bodies[0]);
+    assert!(
+        bodies[1].contains("За 2024-06 GPT никто не звал"),
+        "private reply: {}",
+        bodies[1]
+    );
+}
@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -7,17 +8,20 @@ use redis::aio::ConnectionManager;
 use serde_json::{json, Value};
 use sqlx::PgPool;
 use teloxide::prelude::*;
-use teloxide::types::Update;
+use teloxide::types::{Me, Update};
 use testcontainers::runners::AsyncRunner;
 use testcontainers::ContainerAsync;
 use testcontainers_modules::postgres::Postgres;
 use testcontainers_modules::redis::Redis;
 use wiremock::matchers::{method, path};
-use wiremock::{Mock, MockServer, ResponseTemplate};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
 
-use rust_bot::{build_handler, GptParameters, MentionParameters};
+use rust_bot::safe_fetcher::{FetchSettings, SafeFetcher};
+use rust_bot::{build_handler, BotConfig, ConfigHandle, GptParameters, GptSettings};
 
 pub const TEST_BOT_TOKEN: &str = "test-token";
+/// Telegram user id registered as bot owner in [`dispatch_one`].
+pub const TEST_OWNER_ID: u64 = 4242;
 
 pub struct PostgresHarness {
     pub _container: ContainerAsync<Postgres>,
@@ -83,6 +87,14 @@ async fn run_migrations(pool: &PgPool) {
         include_str!("../../migration/20200924213650_mentions.sql"),
         include_str!("../../migration/202105011313650_mentions_counter.sql"),
         include_str!("../../migration/20230310100000_mensions_chat_id.sql"),
+        include_str!("../../migration/20240601120000_mention_events.sql"),
+        include_str!("../../migration/20240610120000_mentions_thread_id.sql"),
+        include_str!("../../migration/20240615120000_chat_settings.sql"),
+        include_str!("../../migration/20240620120000_chat_settings_handlers.sql"),
+        include_str!("../../migration/20240701120000_personas.sql"),
+        include_str!("../../migration/20240710120000_persona_provider.sql"),
+        include_str!("../../migration/20240720120000_llm_usage.sql"),
+        include_str!("../../migration/20240801120000_voice_enabled.sql"),
     ];
     for sql in migrations {
         for stmt in sql.split(';') {
@@ -111,12 +123,24 @@ pub async fn spawn_telegram() -> (MockServer, Bot) {
         .mount(&server)
         .await;
 
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{TEST_BOT_TOKEN}/EditMessageText")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(default_message_response()))
+        .mount(&server)
+        .await;
+
     Mock::given(method("POST"))
         .and(path(format!("/bot{TEST_BOT_TOKEN}/SendSticker")))
         .respond_with(ResponseTemplate::new(200).set_body_json(default_message_response()))
         .mount(&server)
         .await;
 
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{TEST_BOT_TOKEN}/SendChatAction")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ok": true, "result": true})))
+        .mount(&server)
+        .await;
+
     Mock::given(method("POST"))
         .and(path(format!("/bot{TEST_BOT_TOKEN}/RestrictChatMember")))
         .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ok": true, "result": true})))
@@ -152,23 +176,151 @@ pub async fn spawn_openai(canned_reply: &str) -> (MockServer, String) {
     (server, base_url)
 }
 
-pub fn gpt_parameters(redis: ConnectionManager, openai_base_url: String) -> GptParameters {
+/// OpenAI mock streaming `chunks` as server-sent events, one delta each,
+/// then the usage chunk asked for with `stream_options.include_usage`.
+pub async fn spawn_openai_stream(chunks: &[&str]) -> (MockServer, String) {
+    let server = MockServer::start().await;
+    let mut events: String = chunks
+        .iter()
+        .map(|chunk| {
+            let event = json!({
+                "id": "chatcmpl-test",
+                "object": "chat.completion.chunk",
+                "choices": [{"index": 0, "delta": {"content": chunk}, "finish_reason": null}]
+            });
+            format!("data: {event}\n\n")
+        })
+        .collect();
+    let usage = json!({
+        "id": "chatcmpl-test",
+        "object": "chat.completion.chunk",
+        "choices": [],
+        "usage": {"prompt_tokens": 12, "completion_tokens": chunks.len(), "total_tokens": 12 + chunks.len()}
+    });
+    events.push_str(&format!("data: {usage}\n\n"));
+    events.push_str("data: [DONE]\n\n");
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-type", "text/event-stream")
+                .set_body_string(events),
+        )
+        .mount(&server)
+        .await;
+    let base_url = format!("{}/v1/chat/completions", server.uri());
+    (server, base_url)
+}
+
+/// Anthropic Messages API mock answering every request with `canned_reply`.
+pub async fn spawn_anthropic(canned_reply: &str) -> (MockServer, String) {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/messages"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": "msg_test",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-5-haiku-latest",
+            "content": [{"type": "text", "text": canned_reply}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 1, "output_tokens": 1}
+        })))
+        .mount(&server)
+        .await;
+    let base_url = format!("{}/v1/messages", server.uri());
+    (server, base_url)
+}
+
+/// Ollama `/api/chat` mock answering every request with `canned_reply`.
+pub async fn spawn_ollama(canned_reply: &str) -> (MockServer, String) {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/chat"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "model": "llama3.1",
+            "created_at": "2024-07-10T12:00:00Z",
+            "message": {"role": "assistant", "content": canned_reply},
+            "done": true,
+            "prompt_eval_count": 26,
+            "eval_count": 7
+        })))
+        .mount(&server)
+        .await;
+    let base_url = format!("{}/api/chat", server.uri());
+    (server, base_url)
+}
+
+pub fn gpt_parameters(
+    pool: PgPool,
+    redis: ConnectionManager,
+    openai_base_url: String,
+) -> GptParameters {
     GptParameters {
         chat_gpt_api_token: Arc::from("test-openai-token"),
-        openai_base_url: Arc::from(openai_base_url),
         http_client: reqwest::Client::new(),
+        // Articles are served by wiremock on 127.0.0.1.
+        url_fetcher: SafeFetcher::new(FetchSettings {
+            allow_loopback: true,
+            ..FetchSettings::default()
+        })
+        .expect("url fetcher"),
         redis_connection_manager: redis,
+        db_pool: pool,
+        settings: Arc::new(GptSettings {
+            openai_base_url: Arc::from(openai_base_url),
+            // The canned mocks answer in one piece; streaming tests opt in.
+            streaming: false,
+            ..GptSettings::default()
+        }),
+        circuit_breakers: Arc::default(),
+        chat_cache: Arc::default(),
     }
 }
 
 pub fn text_message_update(text: &str, chat_id: i64, user_id: i64, message_id: i32) -> Update {
+    update_from_json(message_json(text, chat_id, user_id, message_id))
+}
+
+/// Like [`text_message_update`], but posted in forum topic `thread_id`.
+pub fn topic_message_update(
+    text: &str,
+    chat_id: i64,
+    user_id: i64,
+    message_id: i32,
+    thread_id: i32,
+) -> Update {
+    let mut value = message_json(text, chat_id, user_id, message_id);
+    value["message"]["message_thread_id"] = json!(thread_id);
+    value["message"]["is_topic_message"] = json!(true);
+    value["message"]["chat"]["is_forum"] = json!(true);
+    update_from_json(value)
+}
+
+/// Like [`text_message_update`], but sent to the bot in a private chat.
+pub fn private_message_update(text: &str, user_id: i64, message_id: i32) -> Update {
+    let mut value = message_json(text, user_id, user_id, message_id);
+    value["message"]["chat"] = json!({"id": user_id, "type": "private", "first_name": "Alice"});
+    update_from_json(value)
+}
+
+/// Like [`text_message_update`], but from a user who has no username.
+pub fn text_message_update_without_username(
+    text: &str,
+    chat_id: i64,
+    user_id: i64,
+    message_id: i32,
+) -> Update {
+    let mut value = message_json(text, chat_id, user_id, message_id);
+    if let Some(from) = value["message"]["from"].as_object_mut() {
+        from.remove("username");
+    }
+    update_from_json(value)
+}
+
+fn message_json(text: &str, chat_id: i64, user_id: i64, message_id: i32) -> Value {
     let now = chrono::Utc::now().timestamp();
-    // teloxide-core 0.10's UpdateKind::Deserialize first tries `next_key::<&str>`
-    // and falls back to `next_key::<String>` only if the &str attempt failed —
-    // but `serde_json::from_value` produces owned String keys that don't satisfy
-    // &str, and the cursor advances anyway. Round-trip through a JSON string so
-    // the borrowed-str path succeeds and Update::kind becomes Message(...).
-    let value: Value = json!({
+    json!({
         "update_id": message_id,
         "message": {
             "message_id": message_id,
@@ -187,7 +339,15 @@ pub fn text_message_update(text: &str, chat_id: i64, user_id: i64, message_id: i
             "text": text,
             "entities": []
         }
-    });
+    })
+}
+
+fn update_from_json(value: Value) -> Update {
+    // teloxide-core 0.10's UpdateKind::Deserialize first tries `next_key::<&str>`
+    // and falls back to `next_key::<String>` only if the &str attempt failed —
+    // but `serde_json::from_value` produces owned String keys that don't satisfy
+    // &str, and the cursor advances anyway. Round-trip through a JSON string so
+    // the borrowed-str path succeeds and Update::kind becomes Message(...).
     let serialized = serde_json::to_string(&value).expect("serialize update json");
     serde_json::from_str(&serialized).expect("build Update")
 }
@@ -236,14 +396,121 @@ pub fn reply_message_update(
     serde_json::from_str(&serialized).expect("build reply Update")
 }
 
+/// A photo posted with `caption`, in a small and a large size; the large one
+/// is `photo-large`.
+pub fn photo_message_update(
+    caption: Option<&str>,
+    chat_id: i64,
+    user_id: i64,
+    message_id: i32,
+) -> Update {
+    let mut value = message_json("", chat_id, user_id, message_id);
+    let message = value["message"].as_object_mut().expect("message object");
+    message.remove("text");
+    message.remove("entities");
+    message.insert(
+        "photo".to_owned(),
+        json!([
+            {"file_id": "photo-small", "file_unique_id": "small", "file_size": 1_000, "width": 90, "height": 51},
+            {"file_id": "photo-large", "file_unique_id": "large", "file_size": 120_000, "width": 1280, "height": 720}
+        ]),
+    );
+    if let Some(caption) = caption {
+        message.insert("caption".to_owned(), json!(caption));
+    }
+    update_from_json(value)
+}
+
+/// A voice note of `duration` seconds, stored as the Telegram file `voice-1`.
+pub fn voice_message_update(duration: u32, chat_id: i64, user_id: i64, message_id: i32) -> Update {
+    let mut value = message_json("", chat_id, user_id, message_id);
+    let message = value["message"].as_object_mut().expect("message object");
+    message.remove("text");
+    message.remove("entities");
+    message.insert(
+        "voice".to_owned(),
+        json!({
+            "file_id": "voice-1",
+            "file_unique_id": "voice-1",
+            "file_size": 4_000,
+            "duration": duration,
+            "mime_type": "audio/ogg"
+        }),
+    );
+    update_from_json(value)
+}
+
+/// A speech-to-text endpoint that transcribes everything as `transcript`.
+pub async fn spawn_transcriber(transcript: &str) -> (MockServer, String) {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/audio/transcriptions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "text": transcript })))
+        .mount(&server)
+        .await;
+    let base_url = format!("{}/v1/audio/transcriptions", server.uri());
+    (server, base_url)
+}
+
+/// Serve `content` as the Telegram file `file_id`: `getFile` and the download
+/// it points at.
+pub async fn mock_file_download(server: &MockServer, file_id: &str, content: &[u8]) {
+    let file_path = format!("photos/{file_id}.jpg");
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{TEST_BOT_TOKEN}/GetFile")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ok": true,
+            "result": {
+                "file_id": file_id,
+                "file_unique_id": file_id,
+                "file_size": content.len(),
+                "file_path": file_path
+            }
+        })))
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/file/bot{TEST_BOT_TOKEN}/{file_path}")))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+        .mount(server)
+        .await;
+}
+
+/// Answers `sendMessage` with a message in the requested chat and a new id
+/// each time, counting up from `first_id`.
+pub async fn mock_sent_message_ids(server: &MockServer, first_id: i32) {
+    struct SentMessages(AtomicI32);
+
+    impl Respond for SentMessages {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: Value = serde_json::from_slice(&request.body).expect("sendMessage json");
+            let mut response = default_message_response();
+            response["result"]["message_id"] = json!(self.0.fetch_add(1, Ordering::SeqCst));
+            response["result"]["chat"]["id"] = body["chat_id"].clone();
+            ResponseTemplate::new(200).set_body_json(response)
+        }
+    }
+
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{TEST_BOT_TOKEN}/SendMessage")))
+        .respond_with(SentMessages(AtomicI32::new(first_id)))
+        .with_priority(1)
+        .mount(server)
+        .await;
+}
+
 /// Build deps, dispatch a single update through the real handler tree, and
 /// fail fast if anything stalls.
 pub async fn dispatch_one(bot: Bot, pool: PgPool, gpt_parameters: GptParameters, update: Update) {
     use std::ops::ControlFlow;
 
     let handler = build_handler();
-    let mention_parameters = MentionParameters::default();
-    let deps = dptree::deps![update, bot, mention_parameters, pool, gpt_parameters];
+    let config = ConfigHandle::new(BotConfig {
+        gpt_settings: Arc::clone(&gpt_parameters.settings),
+        owner_ids: vec![TEST_OWNER_ID],
+        ..BotConfig::default()
+    });
+    let deps = dptree::deps![update, bot, bot_me(), pool, gpt_parameters, config];
     let outcome = tokio::time::timeout(Duration::from_secs(15), handler.dispatch(deps))
         .await
         .expect("dispatcher did not complete within 15s");
@@ -253,6 +520,49 @@ pub async fn dispatch_one(bot: Bot, pool: PgPool, gpt_parameters: GptParameters,
     );
 }
 
+/// Answer `getChatMember` on the Telegram mock with the given member
+/// `status` ("administrator", "member", ...), for admin-only commands.
+pub async fn mock_chat_member_status(server: &MockServer, status: &str) {
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{TEST_BOT_TOKEN}/GetChatMember")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ok": true,
+            "result": {
+                "status": status,
+                "user": {"id": 42, "is_bot": false, "first_name": "Alice", "username": "alice"},
+                "can_be_edited": false,
+                "is_anonymous": false,
+                "can_manage_chat": true,
+                "can_delete_messages": true,
+                "can_manage_video_chats": true,
+                "can_restrict_members": true,
+                "can_promote_members": false,
+                "can_change_info": true,
+                "can_invite_users": true,
+                "can_post_stories": false,
+                "can_edit_stories": false,
+                "can_delete_stories": false
+            }
+        })))
+        .mount(server)
+        .await;
+}
+
+/// The bot's own identity, which the dispatcher normally fetches via `getMe`
+/// and command parsing needs to strip `/cmd@bot_username` suffixes.
+pub fn bot_me() -> Me {
+    serde_json::from_value(json!({
+        "id": 1,
+        "is_bot": true,
+        "first_name": "TestBot",
+        "username": "test_bot",
+        "can_join_groups": true,
+        "can_read_all_group_messages": true,
+        "supports_inline_queries": false
+    }))
+    .expect("build Me")
+}
+
 fn default_message_response() -> Value {
     json!({
         "ok": true,
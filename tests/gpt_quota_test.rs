@@ -0,0 +1,159 @@
+//! Coverage of the GPT quotas in `gpt_quota` against a real Redis
+//! (testcontainers): token buckets per user and per chat, daily quotas,
+//! refusals that cost nothing, and the counters behind `/quota`.
+
+mod common;
+
+use common::spawn_redis;
+use rust_bot::gpt_quota::{self, QuotaLimits, QuotaRefusal};
+
+const NO_LIMITS: QuotaLimits = QuotaLimits {
+    user_burst: 0,
+    user_per_minute: 0,
+    chat_burst: 0,
+    chat_per_minute: 0,
+    user_daily: 0,
+    chat_daily: 0,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn user_bucket_allows_a_burst_then_asks_to_wait() {
+    let redis = spawn_redis().await;
+    let mut cm = redis.connection_manager.clone();
+    let limits = QuotaLimits {
+        user_burst: 2,
+        user_per_minute: 1,
+        ..NO_LIMITS
+    };
+
+    for _ in 0..2 {
+        let allowed = gpt_quota::try_acquire(&mut cm, &limits, -1, Some(7))
+            .await
+            .expect("acquire");
+        assert_eq!(allowed, Ok(()));
+    }
+    let refused = gpt_quota::try_acquire(&mut cm, &limits, -1, Some(7))
+        .await
+        .expect("acquire");
+    let Err(QuotaRefusal::UserRate { retry_after }) = refused else {
+        panic!("expected a rate refusal, got {refused:?}");
+    };
+    assert!(
+        retry_after.as_secs() > 50 && retry_after.as_secs() <= 60,
+        "one token a minute: {retry_after:?}"
+    );
+
+    let other_user = gpt_quota::try_acquire(&mut cm, &limits, -1, Some(8))
+        .await
+        .expect("acquire");
+    assert_eq!(other_user, Ok(()), "buckets are per user");
+    let other_chat = gpt_quota::try_acquire(&mut cm, &limits, -2, Some(7))
+        .await
+        .expect("acquire");
+    assert_eq!(other_chat, Ok(()), "and per chat");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chat_bucket_is_shared_by_its_users() {
+    let redis = spawn_redis().await;
+    let mut cm = redis.connection_manager.clone();
+    let limits = QuotaLimits {
+        chat_burst: 2,
+        chat_per_minute: 1,
+        ..NO_LIMITS
+    };
+
+    for user_id in [1, 2] {
+        let allowed = gpt_quota::try_acquire(&mut cm, &limits, -10, Some(user_id))
+            .await
+            .expect("acquire");
+        assert_eq!(allowed, Ok(()));
+    }
+    let refused = gpt_quota::try_acquire(&mut cm, &limits, -10, None)
+        .await
+        .expect("acquire");
+    assert!(
+        matches!(refused, Err(QuotaRefusal::ChatRate { .. })),
+        "got {refused:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn daily_quotas_are_counted_and_refusals_cost_nothing() {
+    let redis = spawn_redis().await;
+    let mut cm = redis.connection_manager.clone();
+    let limits = QuotaLimits {
+        user_daily: 2,
+        chat_daily: 3,
+        ..NO_LIMITS
+    };
+
+    for _ in 0..2 {
+        let allowed = gpt_quota::try_acquire(&mut cm, &limits, -20, Some(1))
+            .await
+            .expect("acquire");
+        assert_eq!(allowed, Ok(()));
+    }
+    for _ in 0..3 {
+        let refused = gpt_quota::try_acquire(&mut cm, &limits, -20, Some(1))
+            .await
+            .expect("acquire");
+        assert_eq!(refused, Err(QuotaRefusal::UserDaily));
+    }
+    let usage = gpt_quota::usage(&mut cm, -20, Some(1))
+        .await
+        .expect("usage");
+    assert_eq!(usage.chat_today, 2, "refused calls are not counted");
+    assert_eq!(usage.user_today, Some(2));
+
+    let allowed = gpt_quota::try_acquire(&mut cm, &limits, -20, Some(2))
+        .await
+        .expect("acquire");
+    assert_eq!(allowed, Ok(()));
+    let refused = gpt_quota::try_acquire(&mut cm, &limits, -20, Some(3))
+        .await
+        .expect("acquire");
+    assert_eq!(refused, Err(QuotaRefusal::ChatDaily));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reset_clears_a_user_or_the_chat() {
+    let redis = spawn_redis().await;
+    let mut cm = redis.connection_manager.clone();
+    let limits = QuotaLimits {
+        user_burst: 1,
+        user_per_minute: 1,
+        user_daily: 1,
+        ..NO_LIMITS
+    };
+
+    for user_id in [1, 2] {
+        gpt_quota::try_acquire(&mut cm, &limits, -30, Some(user_id))
+            .await
+            .expect("acquire")
+            .expect("first call is allowed");
+    }
+    gpt_quota::reset(&mut cm, -30, Some(1))
+        .await
+        .expect("reset user");
+    let usage = gpt_quota::usage(&mut cm, -30, Some(1))
+        .await
+        .expect("usage");
+    assert_eq!(usage.user_today, Some(0));
+    assert_eq!(usage.chat_today, 2, "the chat counter stays");
+    let allowed = gpt_quota::try_acquire(&mut cm, &limits, -30, Some(1))
+        .await
+        .expect("acquire");
+    assert_eq!(allowed, Ok(()), "bucket and day are fresh again");
+    let refused = gpt_quota::try_acquire(&mut cm, &limits, -30, Some(2))
+        .await
+        .expect("acquire");
+    assert!(refused.is_err(), "other users are untouched");
+
+    gpt_quota::reset(&mut cm, -30, None)
+        .await
+        .expect("reset chat");
+    let usage = gpt_quota::usage(&mut cm, -30, None).await.expect("usage");
+    assert_eq!(usage.chat_today, 0);
+    assert_eq!(usage.user_today, None);
+}
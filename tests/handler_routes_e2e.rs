@@ -1,12 +1,16 @@
 //! End-to-end coverage for the handler routes the existing e2e suite did not
-//! reach: the blazing-fast mention, the gayness mention (restrict + reply), and
-//! the reply-to-a-bot-message path through `handle_reply`.
+//! reach: the blazing-fast mention, the gayness mention (restrict + reply), the
+//! reply-to-a-bot-message path through `handle_reply`, photos nobody asked a
+//! persona about, and voice notes that are not transcribed.
 
 mod common;
 
+use std::sync::Arc;
+
 use common::*;
-use rust_bot::chat_gpt_handler::BotProfile;
 use rust_bot::chat_repository;
+use rust_bot::speech::SpeechSettings;
+use rust_bot::{GptSettings, PersonaId};
 
 fn send_message_bodies(requests: &[wiremock::Request]) -> Vec<String> {
     requests
@@ -22,7 +26,11 @@ async fn blazing_fast_mention_replies() {
     let redis = spawn_redis().await;
     let (telegram, bot) = spawn_telegram().await;
     let (_openai, openai_url) = spawn_openai("unused").await;
-    let gpt = gpt_parameters(redis.connection_manager.clone(), openai_url);
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
 
     // "blazing fast" matches BLAZING_FAST_REGEX and no earlier route.
     let update = text_message_update("blazing fast is a myth", -1_005_000, 55, 1);
@@ -47,7 +55,11 @@ async fn gayness_mention_restricts_and_replies() {
     let redis = spawn_redis().await;
     let (telegram, bot) = spawn_telegram().await;
     let (_openai, openai_url) = spawn_openai("unused").await;
-    let gpt = gpt_parameters(redis.connection_manager.clone(), openai_url);
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
 
     // "I am 3% gay" matches GAYNESS_REGEX (the " 3% g" substring).
     let update = text_message_update("I am 3% gay", -1_006_000, 66, 1);
@@ -73,6 +85,42 @@ async fn gayness_mention_restricts_and_replies() {
     );
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn disabled_gayness_handler_is_skipped() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_006_100_i64;
+    rust_bot::chat_settings_repository::set_feature_enabled(
+        &pg.pool,
+        chat_id,
+        rust_bot::chat_settings_repository::ChatFeature::Gayness,
+        false,
+    )
+    .await
+    .expect("disable gayness handler");
+
+    let update = text_message_update("I am 3% gay", chat_id, 66, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    assert!(
+        requests.is_empty(),
+        "disabled handler must not touch Telegram, got {} requests",
+        requests.len()
+    );
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn reply_to_bot_message_routes_to_gpt() {
     let pg = spawn_postgres().await;
@@ -80,18 +128,27 @@ async fn reply_to_bot_message_routes_to_gpt() {
     let (telegram, bot) = spawn_telegram().await;
     let canned = "И тебе не хворать.";
     let (openai, openai_url) = spawn_openai(canned).await;
-    let gpt = gpt_parameters(redis.connection_manager.clone(), openai_url);
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
 
     let chat_id = -1_007_000_i64;
     let bot_msg_id = 500_i32;
 
-    // Seed: the replied-to message was sent under the Fedor profile. Use the
+    // Seed: the replied-to message was sent as the fedor persona. Use the
     // same key format the handler builds (`chat:{chat_id:#?}`).
     let mut cm = redis.connection_manager.clone();
     let chat_key = format!("chat:{chat_id:#?}");
-    chat_repository::push_bot_msg_identifier(&mut cm, &chat_key, bot_msg_id, BotProfile::Fedor)
-        .await
-        .expect("seed bot msg profile");
+    chat_repository::push_bot_msg_identifier(
+        &mut cm,
+        &chat_key,
+        bot_msg_id,
+        &PersonaId::new("fedor"),
+    )
+    .await
+    .expect("seed bot msg persona");
 
     // Neutral text (no keyword) replying to that bot message -> handle_reply.
     let update = reply_message_update("спасибо", chat_id, 77, 1, bot_msg_id);
@@ -115,3 +172,83 @@ async fn reply_to_bot_message_routes_to_gpt() {
         bodies[0]
     );
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn photo_without_a_persona_mention_is_ignored() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (openai, openai_url) = spawn_openai("unused").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let update = photo_message_update(Some("котик"), -1_005_100, 56, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let openai_calls = openai.received_requests().await.expect("openai requests");
+    assert!(openai_calls.is_empty());
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    assert!(
+        !requests.iter().any(|r| r.url.path().ends_with("/GetFile")),
+        "nothing is downloaded for a photo nobody asked about"
+    );
+    assert!(send_message_bodies(&requests).is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn voice_notes_are_skipped_when_switched_off_or_too_long() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (transcriber, transcriber_url) = spawn_transcriber("unused").await;
+    let (_openai, openai_url) = spawn_openai("unused").await;
+    let mut gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+    gpt.settings = Arc::new(GptSettings {
+        speech: SpeechSettings {
+            base_url: Arc::from(transcriber_url),
+            ..SpeechSettings::default()
+        },
+        ..(*gpt.settings).clone()
+    });
+
+    let muted_chat = -1_005_200_i64;
+    rust_bot::chat_settings_repository::set_feature_enabled(
+        &pg.pool,
+        muted_chat,
+        rust_bot::chat_settings_repository::ChatFeature::Voice,
+        false,
+    )
+    .await
+    .expect("disable voice handler");
+    let update = voice_message_update(4, muted_chat, 57, 1);
+    dispatch_one(bot.clone(), pg.pool.clone(), gpt.clone(), update).await;
+
+    let too_long = gpt.settings.speech.max_duration.as_secs() as u32 + 1;
+    let update = voice_message_update(too_long, -1_005_201, 57, 2);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let transcriptions = transcriber
+        .received_requests()
+        .await
+        .expect("collect transcription requests");
+    assert!(transcriptions.is_empty());
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    assert!(
+        requests.is_empty(),
+        "skipped voice notes must not touch Telegram, got {} requests",
+        requests.len()
+    );
+}
@@ -0,0 +1,608 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::*;
+use rust_bot::gpt_service::{ChatMessage, ChatMessageRole};
+use rust_bot::llm_provider::{
+    Completion, CompletionRequest, ProviderKind, ProviderSettings, StreamChunk, TokenUsage,
+};
+use rust_bot::llm_retry::{complete_with_retry, BreakerPolicy, CircuitBreakers, RetryPolicy};
+use rust_bot::vision::Image;
+use rust_bot::LlmError;
+use serde_json::{json, Value};
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn settings(kind: ProviderKind, base_url: String, api_key: Option<&str>) -> ProviderSettings {
+    ProviderSettings {
+        kind,
+        base_url: Arc::from(base_url),
+        api_key: api_key.map(Arc::from),
+        model: None,
+    }
+}
+
+fn conversation() -> Vec<ChatMessage> {
+    vec![
+        ChatMessage {
+            role: ChatMessageRole::System,
+            content: "Ты Феррис.".to_owned(),
+        },
+        ChatMessage {
+            role: ChatMessageRole::User,
+            content: "привет".to_owned(),
+        },
+    ]
+}
+
+fn request(messages: &[ChatMessage]) -> CompletionRequest<'_> {
+    CompletionRequest {
+        messages,
+        model: "test-model",
+        max_tokens: 100,
+        temperature: Some(0.5),
+        image: None,
+    }
+}
+
+async fn complete(settings: &ProviderSettings) -> Result<Completion, LlmError> {
+    let messages = conversation();
+    settings
+        .build(reqwest::Client::new(), Duration::from_secs(5))
+        .complete(request(&messages))
+        .await
+}
+
+async fn only_request(server: &MockServer) -> wiremock::Request {
+    let mut requests = server.received_requests().await.expect("collect requests");
+    assert_eq!(requests.len(), 1, "expected exactly one request");
+    requests.remove(0)
+}
+
+#[tokio::test]
+async fn openai_provider_sends_chat_completions_request() {
+    let (server, base_url) = spawn_openai("Привет!").await;
+    let reply = complete(&settings(ProviderKind::OpenAi, base_url, Some("sk-test")))
+        .await
+        .expect("completion");
+    assert_eq!(reply.message.content, "Привет!");
+    assert!(matches!(reply.message.role, ChatMessageRole::Assistant));
+    assert_eq!(
+        reply.usage,
+        Some(TokenUsage {
+            prompt_tokens: 1,
+            completion_tokens: 1
+        })
+    );
+
+    let request = only_request(&server).await;
+    assert_eq!(
+        request
+            .headers
+            .get("authorization")
+            .map(|v| v.to_str().unwrap()),
+        Some("Bearer sk-test")
+    );
+    let body: Value = serde_json::from_slice(&request.body).expect("request json");
+    assert_eq!(body["model"], "test-model");
+    assert_eq!(body["max_tokens"], 100);
+    assert_eq!(body["temperature"], 0.5);
+    assert_eq!(body["messages"][0]["role"], "system");
+    assert_eq!(body["messages"][1]["content"], "привет");
+}
+
+#[tokio::test]
+async fn openai_provider_without_key_sends_no_authorization() {
+    let (server, base_url) = spawn_openai("Привет!").await;
+    complete(&settings(ProviderKind::OpenAi, base_url, None))
+        .await
+        .expect("completion");
+    let request = only_request(&server).await;
+    assert!(request.headers.get("authorization").is_none());
+}
+
+#[tokio::test]
+async fn anthropic_provider_moves_system_prompt_out_of_messages() {
+    let (server, base_url) = spawn_anthropic("Привет от Claude!").await;
+    let reply = complete(&settings(
+        ProviderKind::Anthropic,
+        base_url,
+        Some("anthropic-test"),
+    ))
+    .await
+    .expect("completion");
+    assert_eq!(reply.message.content, "Привет от Claude!");
+    assert!(matches!(reply.message.role, ChatMessageRole::Assistant));
+    assert_eq!(
+        reply.usage,
+        Some(TokenUsage {
+            prompt_tokens: 1,
+            completion_tokens: 1
+        })
+    );
+
+    let request = only_request(&server).await;
+    let header = |name: &str| {
+        request
+            .headers
+            .get(name)
+            .map(|v| v.to_str().unwrap().to_owned())
+    };
+    assert_eq!(header("x-api-key").as_deref(), Some("anthropic-test"));
+    assert_eq!(header("anthropic-version").as_deref(), Some("2023-06-01"));
+    assert!(header("authorization").is_none());
+    let body: Value = serde_json::from_slice(&request.body).expect("request json");
+    assert_eq!(body["model"], "test-model");
+    assert_eq!(body["max_tokens"], 100);
+    assert_eq!(body["system"], "Ты Феррис.");
+    let messages = body["messages"].as_array().expect("messages");
+    assert_eq!(messages.len(), 1, "system prompt must not be a message");
+    assert_eq!(messages[0]["role"], "user");
+}
+
+#[tokio::test]
+async fn anthropic_provider_rejects_reply_without_text() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "content": [{"type": "tool_use", "id": "t", "name": "x", "input": {}}]
+        })))
+        .mount(&server)
+        .await;
+    let result = complete(&settings(ProviderKind::Anthropic, server.uri(), None)).await;
+    assert!(result.is_err(), "a reply without text is an error");
+}
+
+#[tokio::test]
+async fn ollama_provider_requests_a_single_non_streamed_message() {
+    let (server, base_url) = spawn_ollama("Привет от ламы!").await;
+    let reply = complete(&settings(ProviderKind::Ollama, base_url, None))
+        .await
+        .expect("completion");
+    assert_eq!(reply.message.content, "Привет от ламы!");
+    assert_eq!(
+        reply.usage,
+        Some(TokenUsage {
+            prompt_tokens: 26,
+            completion_tokens: 7
+        })
+    );
+
+    let request = only_request(&server).await;
+    let body: Value = serde_json::from_slice(&request.body).expect("request json");
+    assert_eq!(body["model"], "test-model");
+    assert_eq!(body["stream"], false);
+    assert_eq!(body["options"]["num_predict"], 100);
+    assert_eq!(body["options"]["temperature"], 0.5);
+    assert_eq!(body["messages"][0]["role"], "system");
+}
+
+/// Ask a follow-up question with a picture.
+async fn complete_with_image(kind: ProviderKind, base_url: String) {
+    let messages = [
+        conversation(),
+        vec![
+            ChatMessage {
+                role: ChatMessageRole::Assistant,
+                content: "Привет!".to_owned(),
+            },
+            ChatMessage {
+                role: ChatMessageRole::User,
+                content: "что не так?".to_owned(),
+            },
+        ],
+    ]
+    .concat();
+    let image = Image {
+        media_type: "image/png".to_owned(),
+        data: b"png".to_vec(),
+    };
+    settings(kind, base_url, None)
+        .build(reqwest::Client::new(), Duration::from_secs(5))
+        .complete(CompletionRequest {
+            image: Some(&image),
+            ..request(&messages)
+        })
+        .await
+        .expect("completion");
+}
+
+#[tokio::test]
+async fn image_goes_with_the_last_user_message() {
+    let (server, base_url) = spawn_openai("Ошибка в строке 3.").await;
+    complete_with_image(ProviderKind::OpenAi, base_url).await;
+    let body: Value = serde_json::from_slice(&only_request(&server).await.body).unwrap();
+    assert_eq!(
+        body["messages"][1]["content"], "привет",
+        "earlier turns stay text"
+    );
+    assert_eq!(
+        body["messages"][3]["content"],
+        json!([
+            {"type": "text", "text": "что не так?"},
+            {"type": "image_url", "image_url": {"url": "data:image/png;base64,cG5n"}},
+        ])
+    );
+
+    let (server, base_url) = spawn_anthropic("Ошибка в строке 3.").await;
+    complete_with_image(ProviderKind::Anthropic, base_url).await;
+    let body: Value = serde_json::from_slice(&only_request(&server).await.body).unwrap();
+    assert_eq!(body["messages"][0]["content"], "привет");
+    assert_eq!(
+        body["messages"][2]["content"],
+        json!([
+            {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "cG5n"}},
+            {"type": "text", "text": "что не так?"},
+        ])
+    );
+
+    let (server, base_url) = spawn_ollama("Ошибка в строке 3.").await;
+    complete_with_image(ProviderKind::Ollama, base_url).await;
+    let body: Value = serde_json::from_slice(&only_request(&server).await.body).unwrap();
+    assert!(body["messages"][1].get("images").is_none());
+    assert_eq!(body["messages"][3]["content"], "что не так?");
+    assert_eq!(body["messages"][3]["images"], json!(["cG5n"]));
+}
+
+#[tokio::test]
+async fn provider_http_errors_are_reported() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    for kind in [
+        ProviderKind::OpenAi,
+        ProviderKind::Anthropic,
+        ProviderKind::Ollama,
+    ] {
+        let result = complete(&settings(kind, server.uri(), None)).await;
+        assert!(
+            matches!(result, Err(LlmError::Overloaded(500))),
+            "{kind:?} must surface a 500: {result:?}"
+        );
+    }
+}
+
+async fn failing_with(status: u16, retry_after: Option<&str>) -> MockServer {
+    let server = MockServer::start().await;
+    let mut response = ResponseTemplate::new(status).set_body_string("nope");
+    if let Some(retry_after) = retry_after {
+        response = response.insert_header("retry-after", retry_after);
+    }
+    Mock::given(method("POST"))
+        .respond_with(response)
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn error_for(status: u16) -> LlmError {
+    let server = failing_with(status, Some("3")).await;
+    complete(&settings(ProviderKind::OpenAi, server.uri(), None))
+        .await
+        .expect_err("failure status")
+}
+
+#[tokio::test]
+async fn statuses_are_classified() {
+    assert!(matches!(error_for(401).await, LlmError::Auth(401)));
+    assert!(matches!(error_for(403).await, LlmError::Auth(403)));
+    assert!(matches!(
+        error_for(400).await,
+        LlmError::BadRequest { status: 400, message } if message == "nope"
+    ));
+    assert!(matches!(error_for(529).await, LlmError::Overloaded(529)));
+    assert!(matches!(
+        error_for(429).await,
+        LlmError::RateLimited { retry_after: Some(delay) } if delay == Duration::from_secs(3)
+    ));
+}
+
+#[tokio::test]
+async fn slow_provider_is_a_timeout() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .mount(&server)
+        .await;
+    let messages = conversation();
+    let err = settings(ProviderKind::OpenAi, server.uri(), None)
+        .build(reqwest::Client::new(), Duration::from_millis(200))
+        .complete(request(&messages))
+        .await
+        .expect_err("timeout");
+    assert!(matches!(err, LlmError::Timeout), "got {err:?}");
+}
+
+const FAST_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    base_delay: Duration::from_millis(10),
+    max_delay: Duration::from_secs(2),
+};
+
+async fn complete_retrying(
+    server: &MockServer,
+    breakers: &CircuitBreakers,
+    breaker: &BreakerPolicy,
+) -> Result<Completion, LlmError> {
+    let messages = conversation();
+    let provider = settings(ProviderKind::OpenAi, server.uri(), None)
+        .build(reqwest::Client::new(), Duration::from_secs(5));
+    complete_with_retry(
+        provider.as_ref(),
+        "openai",
+        request(&messages),
+        &FAST_RETRY,
+        breakers,
+        breaker,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn rate_limit_is_retried_after_the_requested_delay() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "1"))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{"message": {"role": "assistant", "content": "Наконец-то!"}}]
+        })))
+        .mount(&server)
+        .await;
+
+    let started = std::time::Instant::now();
+    let reply = complete_retrying(
+        &server,
+        &CircuitBreakers::default(),
+        &BreakerPolicy::default(),
+    )
+    .await
+    .expect("second attempt succeeds");
+    assert_eq!(reply.message.content, "Наконец-то!");
+    assert_eq!(reply.usage, None, "the usage block is optional");
+    assert!(
+        started.elapsed() >= Duration::from_secs(1),
+        "Retry-After must be honoured"
+    );
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn retry_after_beyond_the_limit_gives_up() {
+    let server = failing_with(429, Some("120")).await;
+    let err = complete_retrying(
+        &server,
+        &CircuitBreakers::default(),
+        &BreakerPolicy::default(),
+    )
+    .await
+    .expect_err("gives up");
+    assert!(matches!(err, LlmError::RateLimited { .. }));
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn server_errors_are_retried_and_client_errors_are_not() {
+    let overloaded = failing_with(503, None).await;
+    let err = complete_retrying(
+        &overloaded,
+        &CircuitBreakers::default(),
+        &BreakerPolicy::default(),
+    )
+    .await
+    .expect_err("keeps failing");
+    assert!(matches!(err, LlmError::Overloaded(503)));
+    assert_eq!(
+        overloaded.received_requests().await.unwrap().len(),
+        3,
+        "every attempt is used"
+    );
+
+    let unauthorized = failing_with(401, None).await;
+    let err = complete_retrying(
+        &unauthorized,
+        &CircuitBreakers::default(),
+        &BreakerPolicy::default(),
+    )
+    .await
+    .expect_err("rejected");
+    assert!(matches!(err, LlmError::Auth(401)));
+    assert_eq!(unauthorized.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn open_circuit_stops_calling_the_provider() {
+    let server = failing_with(500, None).await;
+    let breakers = CircuitBreakers::default();
+    let breaker = BreakerPolicy {
+        failure_threshold: 3,
+        cooldown: Duration::from_secs(60),
+    };
+    let err = complete_retrying(&server, &breakers, &breaker)
+        .await
+        .expect_err("keeps failing");
+    assert!(matches!(err, LlmError::Overloaded(500)));
+
+    let err = complete_retrying(&server, &breakers, &breaker)
+        .await
+        .expect_err("circuit open");
+    assert!(matches!(err, LlmError::CircuitOpen(_)), "got {err:?}");
+    assert_eq!(
+        server.received_requests().await.unwrap().len(),
+        3,
+        "no request while the circuit is open"
+    );
+}
+
+async fn stream_chunks(settings: &ProviderSettings) -> Result<Option<Vec<StreamChunk>>, LlmError> {
+    use futures::StreamExt;
+
+    let messages = conversation();
+    let provider = settings.build(reqwest::Client::new(), Duration::from_secs(5));
+    let Some(stream) = provider.stream(request(&messages)).await? else {
+        return Ok(None);
+    };
+    stream
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+async fn stream_text(settings: &ProviderSettings) -> Result<Option<Vec<String>>, LlmError> {
+    let chunks = stream_chunks(settings).await?;
+    Ok(chunks.map(|chunks| {
+        chunks
+            .into_iter()
+            .filter_map(|chunk| match chunk {
+                StreamChunk::Text(text) => Some(text),
+                StreamChunk::Usage(_) => None,
+            })
+            .collect()
+    }))
+}
+
+/// Usage chunks of a stream added up, as the usage booking does.
+fn stream_usage(chunks: &[StreamChunk]) -> Option<TokenUsage> {
+    chunks
+        .iter()
+        .filter_map(|chunk| match chunk {
+            StreamChunk::Usage(usage) => Some(*usage),
+            StreamChunk::Text(_) => None,
+        })
+        .reduce(|total, usage| total + usage)
+}
+
+async fn serving_events(events: &str) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-type", "text/event-stream")
+                .set_body_string(events),
+        )
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn openai_provider_streams_deltas() {
+    let (server, base_url) = spawn_openai_stream(&["При", "вет", "!"]).await;
+    let deltas = stream_text(&settings(ProviderKind::OpenAi, base_url, Some("sk-test")))
+        .await
+        .expect("stream")
+        .expect("openai streams");
+    assert_eq!(deltas, vec!["При", "вет", "!"]);
+
+    let request = only_request(&server).await;
+    let body: Value = serde_json::from_slice(&request.body).expect("request json");
+    assert_eq!(body["stream"], true);
+    assert_eq!(body["stream_options"]["include_usage"], true);
+}
+
+#[tokio::test]
+async fn openai_stream_ends_with_its_usage() {
+    let (_server, base_url) = spawn_openai_stream(&["При", "вет"]).await;
+    let chunks = stream_chunks(&settings(ProviderKind::OpenAi, base_url, None))
+        .await
+        .expect("stream")
+        .expect("openai streams");
+    assert_eq!(
+        chunks.last(),
+        Some(&StreamChunk::Usage(TokenUsage {
+            prompt_tokens: 12,
+            completion_tokens: 2
+        }))
+    );
+}
+
+#[tokio::test]
+async fn anthropic_provider_streams_text_deltas() {
+    let events = concat!(
+        "event: message_start\n",
+        "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg\",\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+        "event: ping\n",
+        "data: {\"type\":\"ping\"}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"При\"}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"вет\"}}\n\n",
+        "event: message_delta\n",
+        "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":15}}\n\n",
+        "event: message_stop\n",
+        "data: {\"type\":\"message_stop\"}\n\n",
+    );
+    let server = serving_events(events).await;
+    let chunks = stream_chunks(&settings(ProviderKind::Anthropic, server.uri(), None))
+        .await
+        .expect("stream")
+        .expect("anthropic streams");
+    let deltas: Vec<&StreamChunk> = chunks
+        .iter()
+        .filter(|chunk| matches!(chunk, StreamChunk::Text(_)))
+        .collect();
+    assert_eq!(
+        deltas,
+        vec![
+            &StreamChunk::Text("При".to_owned()),
+            &StreamChunk::Text("вет".to_owned())
+        ]
+    );
+    assert_eq!(
+        stream_usage(&chunks),
+        Some(TokenUsage {
+            prompt_tokens: 25,
+            completion_tokens: 15
+        }),
+        "input tokens from message_start, output tokens from message_delta"
+    );
+
+    let request = only_request(&server).await;
+    let body: Value = serde_json::from_slice(&request.body).expect("request json");
+    assert_eq!(body["stream"], true);
+    assert_eq!(body["system"], "Ты Феррис.");
+}
+
+#[tokio::test]
+async fn anthropic_stream_error_event_is_classified() {
+    let events = concat!(
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"При\"}}\n\n",
+        "event: error\n",
+        "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+    );
+    let server = serving_events(events).await;
+    let err = stream_text(&settings(ProviderKind::Anthropic, server.uri(), None))
+        .await
+        .expect_err("error event");
+    assert!(matches!(err, LlmError::Overloaded(529)), "got {err:?}");
+}
+
+#[tokio::test]
+async fn ollama_provider_does_not_stream() {
+    let (server, base_url) = spawn_ollama("Привет от ламы!").await;
+    let stream = stream_text(&settings(ProviderKind::Ollama, base_url, None))
+        .await
+        .expect("no error");
+    assert!(stream.is_none());
+    assert!(server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn stream_that_fails_to_start_reports_the_status() {
+    let server = failing_with(429, Some("2")).await;
+    let err = stream_text(&settings(ProviderKind::OpenAi, server.uri(), None))
+        .await
+        .expect_err("rate limited");
+    assert!(matches!(err, LlmError::RateLimited { .. }));
+}
@@ -4,8 +4,9 @@
 
 mod common;
 
+use chrono::{Duration, NaiveDate};
 use common::spawn_postgres;
-use rust_bot::mention_repository;
+use rust_bot::mention_repository::{self, MentionEvent, MentionPeriod};
 use sqlx::types::chrono::NaiveDateTime;
 
 #[tokio::test(flavor = "multi_thread")]
@@ -15,7 +16,7 @@ async fn insert_upserts_and_lead_time_reflects_state() {
     let user_id = 7_i64;
 
     // No rows for this chat yet -> the query returns the MAX sentinel.
-    let empty = mention_repository::lead_earliest_mention_time(&pg.pool, chat_id)
+    let empty = mention_repository::lead_earliest_mention_time(&pg.pool, chat_id, None)
         .await
         .expect("lead time on empty table");
     assert_eq!(
@@ -25,7 +26,7 @@ async fn insert_upserts_and_lead_time_reflects_state() {
     );
 
     // First insert creates exactly one row with counter = 1 (column default).
-    mention_repository::insert_mention(&pg.pool, user_id, "alice", chat_id)
+    mention_repository::insert_mention(&pg.pool, user_id, "alice", chat_id, None)
         .await
         .expect("first insert");
 
@@ -34,7 +35,7 @@ async fn insert_upserts_and_lead_time_reflects_state() {
     assert_eq!(counter, 1, "counter starts at 1");
 
     // lead time now returns a real timestamp, not the sentinel.
-    let after_insert = mention_repository::lead_earliest_mention_time(&pg.pool, chat_id)
+    let after_insert = mention_repository::lead_earliest_mention_time(&pg.pool, chat_id, None)
         .await
         .expect("lead time after insert");
     assert_ne!(
@@ -44,7 +45,7 @@ async fn insert_upserts_and_lead_time_reflects_state() {
     );
 
     // Re-inserting the same (user, chat) upserts: still one row, counter bumped.
-    mention_repository::insert_mention(&pg.pool, user_id, "alice", chat_id)
+    mention_repository::insert_mention(&pg.pool, user_id, "alice", chat_id, None)
         .await
         .expect("second insert (conflict)");
 
@@ -53,6 +54,179 @@ async fn insert_upserts_and_lead_time_reflects_state() {
     assert_eq!(counter2, 2, "counter should increment on conflict");
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn top_mentioners_orders_by_counter_within_chat() {
+    let pg = spawn_postgres().await;
+    let chat_id = -4343_i64;
+
+    for _ in 0..3 {
+        mention_repository::insert_mention(&pg.pool, 1, "alice", chat_id, None)
+            .await
+            .expect("insert alice");
+    }
+    mention_repository::insert_mention(&pg.pool, 2, "bob", chat_id, None)
+        .await
+        .expect("insert bob");
+    for _ in 0..2 {
+        mention_repository::insert_mention(&pg.pool, 3, "carol", chat_id, None)
+            .await
+            .expect("insert carol");
+    }
+    // Another chat must not leak into the board.
+    for _ in 0..5 {
+        mention_repository::insert_mention(&pg.pool, 4, "mallory", -1, None)
+            .await
+            .expect("insert other chat");
+    }
+
+    let board = mention_repository::top_mentioners(&pg.pool, chat_id, None, 2)
+        .await
+        .expect("top mentioners");
+    let ranked: Vec<(&str, i32)> = board
+        .iter()
+        .map(|rank| (rank.username.as_str(), rank.counter))
+        .collect();
+    assert_eq!(ranked, vec![("alice", 3), ("carol", 2)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mention_events_aggregate_per_period_user_and_gap() {
+    let pg = spawn_postgres().await;
+    let chat_id = -4444_i64;
+    let monday = NaiveDate::from_ymd_opt(2024, 6, 3)
+        .unwrap()
+        .and_hms_opt(10, 0, 0)
+        .unwrap();
+    // (user, offset from Monday 10:00): two on Monday, one on Tuesday, then a
+    // six-day silence until next Monday.
+    let events = [
+        (1_i64, "alice", Duration::zero()),
+        (2, "bob", Duration::hours(2)),
+        (1, "alice", Duration::days(1)),
+        (1, "alice", Duration::days(7)),
+    ];
+    for (message_id, (user_id, username, offset)) in events.iter().enumerate() {
+        mention_repository::insert_mention_event(
+            &pg.pool,
+            &MentionEvent {
+                chat_id,
+                thread_id: None,
+                user_id: *user_id,
+                username: Some(username),
+                message_id: message_id as i64,
+                created_at: monday + *offset,
+            },
+        )
+        .await
+        .expect("insert mention event");
+    }
+    // A mention in another chat is ignored by every aggregation.
+    mention_repository::insert_mention_event(
+        &pg.pool,
+        &MentionEvent {
+            chat_id: -1,
+            thread_id: Some(5),
+            user_id: 3,
+            username: Some("mallory"),
+            message_id: 99,
+            created_at: monday + Duration::days(3),
+        },
+    )
+    .await
+    .expect("insert other chat event");
+
+    let per_day = mention_repository::mentions_per_period(
+        &pg.pool,
+        chat_id,
+        MentionPeriod::Day,
+        NaiveDateTime::MIN,
+    )
+    .await
+    .expect("per day");
+    let per_day: Vec<i64> = per_day.iter().map(|(_, count)| *count).collect();
+    assert_eq!(per_day, vec![2, 1, 1]);
+
+    let per_week = mention_repository::mentions_per_period(
+        &pg.pool,
+        chat_id,
+        MentionPeriod::Week,
+        NaiveDateTime::MIN,
+    )
+    .await
+    .expect("per week");
+    assert_eq!(
+        per_week,
+        vec![
+            (monday.date().and_hms_opt(0, 0, 0).unwrap(), 3),
+            (
+                (monday + Duration::days(7))
+                    .date()
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap(),
+                1
+            )
+        ]
+    );
+
+    let per_user = mention_repository::mentions_per_user(&pg.pool, chat_id, monday)
+        .await
+        .expect("per user");
+    let per_user: Vec<(&str, i64)> = per_user
+        .iter()
+        .map(|user| (user.username.as_str(), user.mentions))
+        .collect();
+    assert_eq!(per_user, vec![("alice", 3), ("bob", 1)]);
+
+    let gap = mention_repository::longest_mention_gap(&pg.pool, chat_id)
+        .await
+        .expect("longest gap")
+        .expect("a gap exists");
+    assert_eq!(gap.started_at, monday + Duration::days(1));
+    assert_eq!(gap.duration(), Duration::days(6));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn topic_mentions_keep_chat_and_thread_apart() {
+    let pg = spawn_postgres().await;
+    let chat_id = -4545_i64;
+
+    mention_repository::insert_mention(&pg.pool, 1, "alice", chat_id, Some(10))
+        .await
+        .expect("insert topic 10");
+    mention_repository::insert_mention(&pg.pool, 1, "alice", chat_id, Some(20))
+        .await
+        .expect("insert topic 20");
+    sqlx::query("UPDATE mentions SET updated_at = NOW() - INTERVAL '1 day' WHERE thread_id = 10")
+        .execute(&pg.pool)
+        .await
+        .expect("age topic 10");
+
+    // Both topics are stored under the real chat id, as separate rows.
+    let (rows,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM mentions WHERE chat_id = $1")
+        .bind(chat_id)
+        .fetch_one(&pg.pool)
+        .await
+        .expect("count rows");
+    assert_eq!(rows, 2);
+
+    let chat_wide = mention_repository::lead_earliest_mention_time(&pg.pool, chat_id, None)
+        .await
+        .expect("chat-wide lead time");
+    let topic_10 = mention_repository::lead_earliest_mention_time(&pg.pool, chat_id, Some(10))
+        .await
+        .expect("topic lead time");
+    assert!(
+        topic_10 < chat_wide,
+        "topic timer should only see its own mentions"
+    );
+
+    let board = mention_repository::top_mentioners(&pg.pool, chat_id, None, 10)
+        .await
+        .expect("chat board");
+    assert_eq!(board.len(), 1, "chat board aggregates topics per user");
+    assert_eq!(board[0].counter, 2);
+}
+
 async fn row_stats(pool: &sqlx::PgPool, user_id: i64, chat_id: i64) -> (i64, i32) {
     let count: i64 =
         sqlx::query_scalar("SELECT COUNT(*) FROM mentions WHERE user_id = $1 AND chat_id = $2")
@@ -0,0 +1,91 @@
+//! Coverage of `persona_repository` against a real Postgres (testcontainers):
+//! chat personas are created, replaced and deleted per chat, and reads return
+//! the shared personas before the chat's own.
+
+mod common;
+
+use common::spawn_postgres;
+use regex::Regex;
+use rust_bot::persona_repository::{self, GLOBAL_PERSONAS_CHAT_ID};
+use rust_bot::{Persona, PersonaId};
+
+fn persona(id: &str, prompt: &str) -> Persona {
+    Persona::new(id, id, Regex::new(id).unwrap(), prompt)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chat_personas_are_upserted_and_deleted_per_chat() {
+    let pg = spawn_postgres().await;
+    let santa_id = PersonaId::new("santa");
+
+    let mut santa = persona("santa", "Ты Санта.");
+    santa.model = Some("gpt-4o-mini".into());
+    santa.temperature = Some(0.5);
+    santa.languages = vec!["ru".to_owned()];
+    persona_repository::upsert_chat_persona(&pg.pool, -1, &santa)
+        .await
+        .expect("insert persona");
+
+    santa.system_prompt = "Ты добрый Санта.".to_owned();
+    persona_repository::upsert_chat_persona(&pg.pool, -1, &santa)
+        .await
+        .expect("replace persona");
+
+    let stored = persona_repository::get_chat_persona(&pg.pool, -1, &santa_id)
+        .await
+        .expect("fetch persona")
+        .expect("persona exists");
+    assert_eq!(stored.system_prompt, "Ты добрый Санта.");
+    assert_eq!(stored.model.as_deref(), Some("gpt-4o-mini"));
+    assert_eq!(stored.temperature, Some(0.5));
+    assert_eq!(stored.languages, vec!["ru"]);
+
+    assert!(
+        persona_repository::get_chat_persona(&pg.pool, -2, &santa_id)
+            .await
+            .expect("fetch persona")
+            .is_none(),
+        "personas are scoped to their chat"
+    );
+
+    assert!(
+        persona_repository::delete_chat_persona(&pg.pool, -1, &santa_id)
+            .await
+            .expect("delete persona")
+    );
+    assert!(
+        !persona_repository::delete_chat_persona(&pg.pool, -1, &santa_id)
+            .await
+            .expect("delete persona again")
+    );
+    assert!(persona_repository::chat_persona_ids(&pg.pool, -1)
+        .await
+        .expect("persona ids")
+        .is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shared_personas_come_before_the_chats_own() {
+    let pg = spawn_postgres().await;
+    persona_repository::upsert_chat_persona(&pg.pool, -1, &persona("fedor", "chat"))
+        .await
+        .expect("insert chat persona");
+    persona_repository::upsert_chat_persona(
+        &pg.pool,
+        GLOBAL_PERSONAS_CHAT_ID,
+        &persona("fedor", "shared"),
+    )
+    .await
+    .expect("insert shared persona");
+
+    let personas = persona_repository::get_personas(&pg.pool, -1)
+        .await
+        .expect("fetch personas");
+    let prompts: Vec<&str> = personas.iter().map(|p| p.system_prompt.as_str()).collect();
+    assert_eq!(prompts, vec!["shared", "chat"]);
+
+    let elsewhere = persona_repository::get_personas(&pg.pool, -2)
+        .await
+        .expect("fetch personas");
+    assert_eq!(elsewhere.len(), 1);
+}
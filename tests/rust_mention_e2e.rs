@@ -8,7 +8,11 @@ async fn rust_mention_triggers_reply_and_inserts_mention() {
     let redis = spawn_redis().await;
     let (telegram, bot) = spawn_telegram().await;
     let (_openai, openai_url) = spawn_openai("not used").await;
-    let gpt = gpt_parameters(redis.connection_manager.clone(), openai_url);
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
 
     let chat_id = -1001000_i64;
     let user_id = 42_i64;
@@ -63,4 +67,141 @@ async fn rust_mention_triggers_reply_and_inserts_mention() {
         .await
         .expect("count alice mentions");
     assert_eq!(alice_count, 1, "alice should have one mention row");
+
+    let (event_chat_id, event_message_id): (i64, i64) =
+        sqlx::query_as("SELECT chat_id, message_id FROM mention_events WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pg.pool)
+            .await
+            .expect("alice mention event");
+    assert_eq!(event_chat_id, chat_id);
+    assert_eq!(event_message_id, 1);
+}
+
+async fn seed_topic_mention(pool: &sqlx::PgPool, chat_id: i64, thread_id: i64, age: &str) {
+    sqlx::query(&format!(
+        "INSERT INTO mentions(user_id, username, chat_id, thread_id, updated_at) \
+         VALUES ($1, 'seed', $2, $3, NOW() - INTERVAL '{age}')"
+    ))
+    .bind(thread_id + 1000)
+    .bind(chat_id)
+    .bind(thread_id)
+    .execute(pool)
+    .await
+    .expect("seed topic mention row");
+}
+
+async fn rust_mention_replies(per_topic: bool) -> usize {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("not used").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_001_100_i64;
+    // Topic 7 was quiet for an hour, but topic 8 mentioned Rust a minute ago.
+    seed_topic_mention(&pg.pool, chat_id, 7, "1 hour").await;
+    seed_topic_mention(&pg.pool, chat_id, 8, "1 minute").await;
+    rust_bot::chat_settings_repository::set_mention_timer_per_topic(&pg.pool, chat_id, per_topic)
+        .await
+        .expect("set timer scope");
+
+    let update = topic_message_update("Rust again", chat_id, 43, 1, 7);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let (thread_rows,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM mentions WHERE user_id = 43 AND chat_id = $1 AND thread_id = 7",
+    )
+    .bind(chat_id)
+    .fetch_one(&pg.pool)
+    .await
+    .expect("count topic mention");
+    assert_eq!(
+        thread_rows, 1,
+        "mention should be stored under chat and topic"
+    );
+
+    telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests")
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .count()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chat_wide_timer_sees_mentions_from_other_topics() {
+    assert_eq!(rust_mention_replies(false).await, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn per_topic_timer_only_sees_its_own_topic() {
+    assert_eq!(rust_mention_replies(true).await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn silent_chat_counts_mentions_without_replying() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("not used").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_001_200_i64;
+    // Without the flag a mention an hour after the last one would be announced.
+    seed_topic_mention(&pg.pool, chat_id, 0, "1 hour").await;
+    rust_bot::chat_settings_repository::set_silent_counting(&pg.pool, chat_id, true)
+        .await
+        .expect("enable silent counting");
+
+    let update = text_message_update("Rust is great", chat_id, 44, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let requests = telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests");
+    assert!(requests.is_empty(), "silent chat must not be announced");
+
+    let (rows,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM mentions WHERE user_id = 44 AND chat_id = $1")
+            .bind(chat_id)
+            .fetch_one(&pg.pool)
+            .await
+            .expect("count mention");
+    assert_eq!(rows, 1, "silent chat still counts the mention");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mention_by_user_without_username_is_still_logged() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let (_telegram, bot) = spawn_telegram().await;
+    let (_openai, openai_url) = spawn_openai("not used").await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
+
+    let chat_id = -1_001_300_i64;
+    let update = text_message_update_without_username("Rust is great", chat_id, 45, 3);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+
+    let (event_chat_id, username): (i64, Option<String>) =
+        sqlx::query_as("SELECT chat_id, username FROM mention_events WHERE user_id = 45")
+            .fetch_one(&pg.pool)
+            .await
+            .expect("mention event of a user without username");
+    assert_eq!(event_chat_id, chat_id);
+    assert_eq!(username, None);
 }
@@ -0,0 +1,231 @@
+//! The URL fetcher against a local wiremock server: what it follows and
+//! decodes, and the addresses, redirects, sizes and content types it refuses.
+
+use rust_bot::safe_fetcher::{FetchSettings, SafeFetcher};
+use rust_bot::FetchError;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn fetcher(settings: FetchSettings) -> SafeFetcher {
+    SafeFetcher::new(settings).expect("fetcher")
+}
+
+/// Wiremock listens on 127.0.0.1, which only a test fetcher may reach.
+fn local_fetcher_settings() -> FetchSettings {
+    FetchSettings {
+        allow_loopback: true,
+        ..FetchSettings::default()
+    }
+}
+
+fn local_fetcher() -> SafeFetcher {
+    fetcher(local_fetcher_settings())
+}
+
+async fn serve(server: &MockServer, route: &str, response: ResponseTemplate) {
+    Mock::given(method("GET"))
+        .and(path(route))
+        .respond_with(response)
+        .mount(server)
+        .await;
+}
+
+fn redirect_to(location: &str) -> ResponseTemplate {
+    ResponseTemplate::new(302).insert_header("location", location)
+}
+
+#[tokio::test]
+async fn page_is_fetched_through_redirects_and_decoded() {
+    let server = MockServer::start().await;
+    serve(&server, "/s/abc", redirect_to("/post/42")).await;
+    let (html, _, _) = encoding_rs::WINDOWS_1251.encode("<p>Привет, Феррис!</p>");
+    serve(
+        &server,
+        "/post/42",
+        ResponseTemplate::new(200)
+            .set_body_raw(html.into_owned(), "text/html; charset=windows-1251"),
+    )
+    .await;
+
+    let page = local_fetcher()
+        .fetch(&format!("{}/s/abc", server.uri()))
+        .await
+        .expect("page");
+    assert_eq!(page.url.path(), "/post/42");
+    assert_eq!(page.content_type, "text/html");
+    assert_eq!(page.text, "<p>Привет, Феррис!</p>");
+
+    let requests = server.received_requests().await.expect("requests");
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].headers["accept"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+}
+
+#[tokio::test]
+async fn private_addresses_are_refused() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/",
+        ResponseTemplate::new(200).set_body_string("secret"),
+    )
+    .await;
+    let port = server.address().port();
+    let fetcher = fetcher(FetchSettings::default());
+    for url in [
+        server.uri(),
+        format!("http://localhost:{port}/"),
+        "http://169.254.169.254/latest/meta-data/".to_owned(),
+        "http://[::1]:1/".to_owned(),
+        "http://10.1.2.3/".to_owned(),
+        "http://[fdaa:0:1::2]/".to_owned(),
+        "http://2130706433/".to_owned(),
+    ] {
+        let error = fetcher.fetch(&url).await.expect_err(&url);
+        assert!(
+            matches!(error, FetchError::PrivateAddress(_)),
+            "{url}: {error:?}"
+        );
+    }
+    let requests = server.received_requests().await.expect("requests");
+    assert!(requests.is_empty(), "the local server was reached");
+}
+
+#[tokio::test]
+async fn redirects_to_private_addresses_are_refused() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/metadata",
+        redirect_to("http://169.254.169.254/latest/meta-data/"),
+    )
+    .await;
+    serve(
+        &server,
+        "/internal",
+        redirect_to("http://10.0.0.1:8080/admin"),
+    )
+    .await;
+    serve(&server, "/file", redirect_to("file:///etc/passwd")).await;
+    let fetcher = local_fetcher();
+
+    for route in ["/metadata", "/internal"] {
+        let error = fetcher
+            .fetch(&format!("{}{route}", server.uri()))
+            .await
+            .expect_err(route);
+        assert!(
+            matches!(error, FetchError::PrivateAddress(_)),
+            "{route}: {error:?}"
+        );
+    }
+    let error = fetcher
+        .fetch(&format!("{}/file", server.uri()))
+        .await
+        .expect_err("file redirect");
+    assert!(matches!(error, FetchError::InvalidUrl(_)), "{error:?}");
+}
+
+#[tokio::test]
+async fn redirect_chains_are_cut_short() {
+    let server = MockServer::start().await;
+    serve(&server, "/loop", redirect_to("/loop")).await;
+    let fetcher = fetcher(FetchSettings {
+        max_redirects: 3,
+        ..local_fetcher_settings()
+    });
+
+    let error = fetcher
+        .fetch(&format!("{}/loop", server.uri()))
+        .await
+        .expect_err("redirect loop");
+    assert!(
+        matches!(error, FetchError::TooManyRedirects(3)),
+        "{error:?}"
+    );
+    let requests = server.received_requests().await.expect("requests");
+    assert_eq!(requests.len(), 4);
+}
+
+#[tokio::test]
+async fn large_pages_are_refused() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/big",
+        ResponseTemplate::new(200).set_body_raw("x".repeat(2048), "text/html"),
+    )
+    .await;
+    let fetcher = fetcher(FetchSettings {
+        max_bytes: 1024,
+        ..local_fetcher_settings()
+    });
+
+    let error = fetcher
+        .fetch(&format!("{}/big", server.uri()))
+        .await
+        .expect_err("big page");
+    assert!(matches!(error, FetchError::TooLarge(1024)), "{error:?}");
+}
+
+#[tokio::test]
+async fn only_html_and_text_are_read() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/paper.pdf",
+        ResponseTemplate::new(200).set_body_raw(b"%PDF-1.7".to_vec(), "application/pdf"),
+    )
+    .await;
+    serve(
+        &server,
+        "/notes.txt",
+        ResponseTemplate::new(200).set_body_string("просто текст"),
+    )
+    .await;
+    let fetcher = local_fetcher();
+
+    let error = fetcher
+        .fetch(&format!("{}/paper.pdf", server.uri()))
+        .await
+        .expect_err("pdf");
+    assert!(
+        matches!(&error, FetchError::UnsupportedContentType(content_type) if content_type == "application/pdf"),
+        "{error:?}"
+    );
+    let page = fetcher
+        .fetch(&format!("{}/notes.txt", server.uri()))
+        .await
+        .expect("text page");
+    assert_eq!(page.text, "просто текст");
+}
+
+#[tokio::test]
+async fn error_pages_and_other_schemes_are_refused() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/gone",
+        ResponseTemplate::new(404).set_body_raw("<p>not found</p>", "text/html"),
+    )
+    .await;
+    let fetcher = local_fetcher();
+
+    let error = fetcher
+        .fetch(&format!("{}/gone", server.uri()))
+        .await
+        .expect_err("404");
+    assert!(
+        matches!(&error, FetchError::Http(err) if err.status().map(|s| s.as_u16()) == Some(404)),
+        "{error:?}"
+    );
+    for url in ["file:///etc/passwd", "ftp://example.com/file", "not a url"] {
+        let error = fetcher.fetch(url).await.expect_err(url);
+        assert!(
+            matches!(error, FetchError::InvalidUrl(_)),
+            "{url}: {error:?}"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Speech-to-text backends against wiremock: the multipart form each kind
+//! sends, the key it authenticates with, and how failures are classified.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use rust_bot::speech::{Recording, SpeechKind, SpeechSettings};
+use rust_bot::LlmError;
+use serde_json::json;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+async fn spawn_transcriber(response: ResponseTemplate) -> (MockServer, String) {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(response)
+        .mount(&server)
+        .await;
+    let base_url = format!("{}/v1/audio/transcriptions", server.uri());
+    (server, base_url)
+}
+
+fn speech_settings(kind: SpeechKind, base_url: String) -> SpeechSettings {
+    SpeechSettings {
+        kind,
+        base_url: Arc::from(base_url),
+        ..SpeechSettings::default()
+    }
+}
+
+fn recording() -> Recording {
+    Recording {
+        file_name: "voice.ogg".to_owned(),
+        media_type: "audio/ogg".to_owned(),
+        data: b"OggS-voice".to_vec(),
+    }
+}
+
+async fn transcribe(settings: &SpeechSettings) -> Result<String, LlmError> {
+    settings
+        .build(
+            reqwest::Client::new(),
+            &Arc::from("fallback-token"),
+            Duration::from_secs(5),
+        )
+        .transcribe(recording())
+        .await
+}
+
+async fn only_request(server: &MockServer) -> wiremock::Request {
+    let mut requests = server.received_requests().await.expect("collect requests");
+    assert_eq!(requests.len(), 1, "expected exactly one request");
+    requests.remove(0)
+}
+
+#[tokio::test]
+async fn openai_transcription_sends_the_recording_and_model() {
+    let (server, base_url) = spawn_transcriber(
+        ResponseTemplate::new(200).set_body_json(json!({"text": " Феррис, привет! "})),
+    )
+    .await;
+    let settings = SpeechSettings {
+        language: Some(Arc::from("ru")),
+        ..speech_settings(SpeechKind::OpenAi, base_url)
+    };
+    let transcript = transcribe(&settings).await.expect("transcript");
+    assert_eq!(transcript, "Феррис, привет!");
+
+    let request = only_request(&server).await;
+    assert_eq!(request.url.path(), "/v1/audio/transcriptions");
+    assert_eq!(
+        request.headers["authorization"].to_str().unwrap(),
+        "Bearer fallback-token"
+    );
+    let content_type = request.headers["content-type"].to_str().unwrap();
+    assert!(
+        content_type.starts_with("multipart/form-data"),
+        "{content_type}"
+    );
+    let body = String::from_utf8_lossy(&request.body);
+    assert!(
+        body.contains(r#"name="file"; filename="voice.ogg""#),
+        "{body}"
+    );
+    assert!(body.contains("Content-Type: audio/ogg"), "{body}");
+    assert!(body.contains("OggS-voice"), "{body}");
+    assert!(body.contains("name=\"model\"\r\n\r\nwhisper-1"), "{body}");
+    assert!(body.contains("name=\"language\"\r\n\r\nru"), "{body}");
+    assert!(!body.contains("temperature"), "{body}");
+}
+
+#[tokio::test]
+async fn whisper_server_is_asked_without_a_key_or_model() {
+    let (server, base_url) =
+        spawn_transcriber(ResponseTemplate::new(200).set_body_json(json!({"text": "привет"})))
+            .await;
+    let transcript = transcribe(&speech_settings(SpeechKind::Whisper, base_url))
+        .await
+        .expect("transcript");
+    assert_eq!(transcript, "привет");
+
+    let request = only_request(&server).await;
+    assert!(!request.headers.contains_key("authorization"));
+    let body = String::from_utf8_lossy(&request.body);
+    assert!(body.contains("name=\"temperature\"\r\n\r\n0"), "{body}");
+    assert!(
+        body.contains("name=\"response_format\"\r\n\r\njson"),
+        "{body}"
+    );
+    assert!(!body.contains("name=\"model\""), "{body}");
+    assert!(!body.contains("name=\"language\""), "{body}");
+}
+
+#[tokio::test]
+async fn configured_key_replaces_the_chat_gpt_token() {
+    let (server, base_url) =
+        spawn_transcriber(ResponseTemplate::new(200).set_body_json(json!({"text": "ok"}))).await;
+    let settings = SpeechSettings {
+        api_key: Some(Arc::from("speech-key")),
+        ..speech_settings(SpeechKind::Whisper, base_url)
+    };
+    transcribe(&settings).await.expect("transcript");
+
+    let request = only_request(&server).await;
+    assert_eq!(
+        request.headers["authorization"].to_str().unwrap(),
+        "Bearer speech-key"
+    );
+}
+
+#[tokio::test]
+async fn transcription_failures_are_classified() {
+    let cases = [
+        (401, "auth"),
+        (429, "rate limit"),
+        (503, "overload"),
+        (400, "bad request"),
+    ];
+    for (status, case) in cases {
+        let (_server, base_url) =
+            spawn_transcriber(ResponseTemplate::new(status).set_body_string("unsupported file"))
+                .await;
+        let error = transcribe(&speech_settings(SpeechKind::OpenAi, base_url))
+            .await
+            .expect_err(case);
+        let expected = match status {
+            401 => matches!(error, LlmError::Auth(401)),
+            429 => matches!(error, LlmError::RateLimited { .. }),
+            503 => matches!(error, LlmError::Overloaded(503)),
+            _ => matches!(
+                &error,
+                LlmError::BadRequest { status: 400, message } if message == "unsupported file"
+            ),
+        };
+        assert!(expected, "{case}: {error:?}");
+    }
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use serde_json::Value;
+use teloxide::types::{ChatId, MessageId, ThreadId};
+use wiremock::MockServer;
+
+use rust_bot::typing_indicator::TypingIndicator;
+
+async fn chat_actions(telegram: &MockServer) -> Vec<Value> {
+    telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests")
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendChatAction"))
+        .map(|r| serde_json::from_slice(&r.body).expect("chat action json"))
+        .collect()
+}
+
+#[tokio::test]
+async fn typing_is_repeated_until_the_guard_is_dropped() {
+    let (telegram, bot) = spawn_telegram().await;
+    let typing = TypingIndicator::with_interval(
+        &bot,
+        ChatId(-1_007_000),
+        Some(ThreadId(MessageId(7))),
+        Duration::from_millis(50),
+    );
+    tokio::time::sleep(Duration::from_millis(180)).await;
+    drop(typing);
+
+    // Let a request that was already in flight land.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let actions = chat_actions(&telegram).await;
+    assert!(actions.len() >= 3, "typing refreshed: {actions:?}");
+    for action in &actions {
+        assert_eq!(action["action"], "typing");
+        assert_eq!(action["chat_id"], -1_007_000);
+        assert_eq!(action["message_thread_id"], 7);
+    }
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(
+        chat_actions(&telegram).await.len(),
+        actions.len(),
+        "no typing after the guard is gone"
+    );
+}
+
+#[tokio::test]
+async fn typing_outside_a_topic_has_no_thread() {
+    let (telegram, bot) = spawn_telegram().await;
+    let typing = TypingIndicator::start(&bot, ChatId(-1_007_001), None);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(typing);
+
+    let actions = chat_actions(&telegram).await;
+    assert_eq!(actions.len(), 1, "the first action is sent right away");
+    assert!(actions[0].get("message_thread_id").is_none());
+}
@@ -11,11 +11,14 @@ async fn url_summary_fetches_article_summarizes_and_replies() {
     let (telegram, bot) = spawn_telegram().await;
     let canned_summary = "Краткое содержание статьи.";
     let (openai, openai_url) = spawn_openai(canned_summary).await;
-    let gpt = gpt_parameters(redis.connection_manager.clone(), openai_url);
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
 
     let article = MockServer::start().await;
-    let long_text = "lorem ipsum dolor sit amet ".repeat(80);
-    let html = format!("<html><body><p>{long_text}</p></body></html>");
+    let html = include_str!("fixtures/articles/blog_post.html");
     Mock::given(method("GET"))
         .and(path("/post/42"))
         .respond_with(ResponseTemplate::new(200).set_body_string(html))
@@ -41,6 +44,14 @@ async fn url_summary_fetches_article_summarizes_and_replies() {
         .await
         .expect("collect openai requests");
     assert_eq!(openai_calls.len(), 1, "expected 1 openai call");
+    let prompt = String::from_utf8_lossy(&openai_calls[0].body);
+    assert!(
+        prompt.contains("Rust 1.85 ships async closures"),
+        "{prompt}"
+    );
+    for chrome in ["We use cookies", "rustacean_42", "All rights reserved"] {
+        assert!(!prompt.contains(chrome), "{chrome:?} summarized: {prompt}");
+    }
 
     let telegram_requests = telegram
         .received_requests()
@@ -60,6 +71,17 @@ async fn url_summary_fetches_article_summarizes_and_replies() {
     let body = &send_message_bodies[0];
     assert!(body.contains("TLDR"), "sendMessage body: {body}");
     assert!(body.contains(canned_summary), "sendMessage body: {body}");
+    assert!(
+        body.contains("<b>Async closures are stable</b>")
+            && body.contains("Ferris Writes · Ferris Crab · 2025-02-20"),
+        "the TLDR is headed with the article's title and byline: {body}"
+    );
+    assert!(
+        telegram_requests
+            .iter()
+            .any(|r| r.url.path().ends_with("/SendChatAction")),
+        "the chat sees the bot typing while the article is summarized"
+    );
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -68,9 +90,13 @@ async fn url_summary_unreachable_source_does_not_reply() {
     let redis = spawn_redis().await;
     let (telegram, bot) = spawn_telegram().await;
     let (_openai, openai_url) = spawn_openai("unused").await;
-    let gpt = gpt_parameters(redis.connection_manager.clone(), openai_url);
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url,
+    );
 
-    // Port 1 refuses connections immediately, so `get_content_call` errors,
+    // Port 1 refuses connections immediately, so the page fetch errors,
     // `handle_url_summary` returns `Err`, and the dispatcher logs + swallows it.
     // The dispatcher still routes (dispatch_one asserts no panic) and no reply
     // goes out.
@@ -90,3 +116,86 @@ async fn url_summary_unreachable_source_does_not_reply() {
         "no reply should be sent when the article source is unreachable"
     );
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn url_summaries_are_cached_by_canonical_url() {
+    let pg = spawn_postgres().await;
+    let redis = spawn_redis().await;
+    let canned_summary = "Краткое содержание статьи.";
+    let (openai, openai_url) = spawn_openai(canned_summary).await;
+
+    let article = MockServer::start().await;
+    let long_text = "lorem ipsum dolor sit amet ".repeat(80);
+    Mock::given(method("GET"))
+        .and(path("/post/42"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(format!("<p>{long_text}</p>")))
+        .mount(&article)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/s/abc"))
+        .respond_with(
+            ResponseTemplate::new(301)
+                .insert_header("location", format!("{}/post/42", article.uri())),
+        )
+        .mount(&article)
+        .await;
+
+    // A short link is fetched, followed to the article and summarized.
+    let chat_id = -1_001_234_567_890_i64;
+    let (telegram, bot) = spawn_telegram().await;
+    let gpt = gpt_parameters(
+        pg.pool.clone(),
+        redis.connection_manager.clone(),
+        openai_url.clone(),
+    );
+    let update = text_message_update(&format!("{}/s/abc", article.uri()), chat_id, 22, 1);
+    dispatch_one(bot, pg.pool.clone(), gpt.clone(), update).await;
+    assert_eq!(send_message_texts(&telegram).await.len(), 1);
+
+    // The article itself, with tracking, in the same chat points at the TLDR.
+    let (telegram, bot) = spawn_telegram().await;
+    let text = format!("{}/post/42/?utm_source=tg#comments", article.uri());
+    let update = text_message_update(&text, chat_id, 23, 2);
+    dispatch_one(bot, pg.pool.clone(), gpt.clone(), update).await;
+    let replies = send_message_texts(&telegram).await;
+    assert_eq!(replies.len(), 1);
+    assert!(
+        replies[0].contains("уже пересказывали")
+            && replies[0].contains("https://t.me/c/1234567890/100"),
+        "{}",
+        replies[0]
+    );
+
+    // Another chat gets the cached summary.
+    let (telegram, bot) = spawn_telegram().await;
+    let update = text_message_update(&text, -1_001_234_567_891, 24, 3);
+    dispatch_one(bot, pg.pool.clone(), gpt, update).await;
+    let replies = send_message_texts(&telegram).await;
+    assert_eq!(replies.len(), 1);
+    assert!(replies[0].contains(canned_summary), "{}", replies[0]);
+
+    let article_paths: Vec<String> = article
+        .received_requests()
+        .await
+        .expect("collect article requests")
+        .iter()
+        .map(|r| r.url.path().to_owned())
+        .collect();
+    assert_eq!(article_paths, vec!["/s/abc", "/post/42"]);
+    let openai_calls = openai.received_requests().await.expect("openai requests");
+    assert_eq!(openai_calls.len(), 1, "the page is summarized once");
+}
+
+async fn send_message_texts(telegram: &MockServer) -> Vec<String> {
+    telegram
+        .received_requests()
+        .await
+        .expect("collect telegram requests")
+        .iter()
+        .filter(|r| r.url.path().ends_with("/SendMessage"))
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).expect("json body");
+            body["text"].as_str().unwrap_or_default().to_owned()
+        })
+        .collect()
+}
@@ -0,0 +1,72 @@
+//! Coverage of `usage_repository` against a real Postgres (testcontainers):
+//! booked LLM calls roll up per chat, handler, persona and model within a
+//! calendar month.
+
+mod common;
+
+use chrono::NaiveDate;
+use common::spawn_postgres;
+use rust_bot::usage_repository::{self, UsageEntry};
+
+fn entry<'a>(chat_id: i64, handler: &'a str, day: &str, cost_micros: i64) -> UsageEntry<'a> {
+    UsageEntry {
+        chat_id,
+        user_id: Some(7),
+        persona_id: (handler == "conversation").then_some("fedor"),
+        handler,
+        provider: "openai",
+        model: "gpt-4o",
+        prompt_tokens: 100,
+        completion_tokens: 20,
+        cost_micros,
+        estimated: false,
+        created_at: NaiveDate::parse_from_str(day, "%Y-%m-%d")
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap(),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn usage_rolls_up_per_month_chat_and_handler() {
+    let pg = spawn_postgres().await;
+    for usage in [
+        entry(-1, "conversation", "2024-06-01", 500),
+        entry(-1, "conversation", "2024-06-30", 700),
+        entry(-1, "url_summary", "2024-06-15", 100),
+        entry(-2, "conversation", "2024-06-10", 2_000),
+        entry(-1, "conversation", "2024-05-31", 9_000),
+        entry(-1, "conversation", "2024-07-01", 9_000),
+    ] {
+        usage_repository::insert_usage(&pg.pool, &usage)
+            .await
+            .expect("insert usage");
+    }
+    let june = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+
+    let chat = usage_repository::monthly_rollup(&pg.pool, june, Some(-1))
+        .await
+        .expect("chat rollup");
+    assert_eq!(chat.len(), 2, "conversation and url_summary: {chat:?}");
+    assert_eq!(chat[0].handler, "conversation");
+    assert_eq!(chat[0].persona_id.as_deref(), Some("fedor"));
+    assert_eq!(chat[0].calls, 2, "other months are left out");
+    assert_eq!(chat[0].prompt_tokens, 200);
+    assert_eq!(chat[0].completion_tokens, 40);
+    assert_eq!(chat[0].cost_micros, 1_200);
+    assert_eq!(chat[1].handler, "url_summary");
+    assert_eq!(chat[1].persona_id, None);
+
+    let all = usage_repository::monthly_rollup(&pg.pool, june, None)
+        .await
+        .expect("rollup of every chat");
+    assert_eq!(all.len(), 3);
+    assert_eq!(all[0].chat_id, -2, "costliest first");
+    assert_eq!(all[0].cost_micros, 2_000);
+
+    let empty = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+    assert!(usage_repository::monthly_rollup(&pg.pool, empty, None)
+        .await
+        .expect("empty rollup")
+        .is_empty());
+}